@@ -0,0 +1,1120 @@
+// Notes:
+// I think we can explore two ways for achieving good plotting performance:
+// - either caching lookup positions and having heuristics for speeding up successive
+//   requests to close x values; need to reason about interpolation in this case
+// - provide the x range to be plotted and return a copy of the slice of samples;
+//   might need to implement undersampling for the case of very large time slices,
+//   which might be cacheable
+// - the second way is the one in use: on top of the samples we keep a pyramid of min/max
+//   envelopes, each level summarizing blocks of PYRAMID_FACTOR blocks of the level below, so that
+//   a large time slice can be reduced to a couple of points per pixel column without losing
+//   spikes and glitches, and without visiting every sample
+//
+// Samples are stored in a ring of fixed-size chunks: truncating drops whole chunks from the
+// front instead of shifting the buffer, and each chunk encodes its timestamps as compactly as
+// they allow (implicitly for a regular rate, as the f32 jitter around it otherwise).
+//
+// When memory is not enough, the oldest chunks can be spilled to disk (see the spill module)
+// while their envelopes in the pyramid stay in memory, so that zooming out does not need to read
+// them back.
+//
+// TODOs:
+
+mod shared;
+mod spill;
+
+use std::{collections::VecDeque, ops::Range, path::Path, sync::Arc};
+
+use egui_plot::{PlotPoint, PlotPoints};
+
+use shared::SharedDeque;
+use spill::{Column, Segment, SegmentWriter};
+
+/// Number of blocks (or samples, for the first level) summarized by each block of a pyramid level.
+const PYRAMID_FACTOR: usize = 8;
+
+/// Number of samples in each chunk of the buffer.
+const CHUNK_SIZE: usize = 4096;
+
+/// Number of chunks spilled to disk at once, into a single segment file.
+const SEGMENT_CHUNKS: usize = 64;
+
+/// How far a timestamp can be from the one that was pushed when it is encoded compactly. Samplers
+/// timestamp samples with a microsecond resolution, so this never merges distinct timestamps.
+const TIME_TOLERANCE: f64 = 0.25e-6;
+
+/// The minimum and maximum sample of a block, with their timestamps so that they can be plotted in
+/// the order they occurred.
+#[derive(Clone, Copy, Debug)]
+struct Envelope {
+    min: PlotPoint,
+    max: PlotPoint,
+}
+
+impl Envelope {
+    fn new(point: PlotPoint) -> Envelope {
+        Envelope {
+            min: point,
+            max: point,
+        }
+    }
+
+    fn of(mut points: impl Iterator<Item = PlotPoint>) -> Envelope {
+        let first = points.next().unwrap();
+
+        points.fold(Envelope::new(first), |mut envelope, point| {
+            envelope.add(point);
+            envelope
+        })
+    }
+
+    fn add(&mut self, point: PlotPoint) {
+        if point.y < self.min.y {
+            self.min = point;
+        }
+        if point.y > self.max.y {
+            self.max = point;
+        }
+    }
+
+    fn merge(&mut self, other: &Envelope) {
+        self.add(other.min);
+        self.add(other.max);
+    }
+}
+
+/// A level of the min/max pyramid. Blocks are indexed by the absolute index of the samples they
+/// cover (counting the ones dropped by truncation), so that truncating the buffer only needs to
+/// drop the blocks at the front; the first block may still summarize samples that have already
+/// been dropped, hence it must never be used as a whole.
+#[derive(Clone)]
+struct PyramidLevel {
+    block_size: usize,
+    first_block: usize,
+    blocks: SharedDeque<Envelope>,
+}
+
+impl PyramidLevel {
+    fn block(&self, j: usize) -> Option<&Envelope> {
+        j.checked_sub(self.first_block)
+            .and_then(|j| self.blocks.get(j))
+    }
+
+    fn update(&mut self, j: usize, envelope: &Envelope) {
+        if self.blocks.is_empty() {
+            self.first_block = j;
+        }
+
+        if j < self.first_block + self.blocks.len() {
+            self.blocks.back_mut().unwrap().merge(envelope);
+        } else {
+            self.blocks.push_back(*envelope);
+        }
+    }
+
+    /// Drops the blocks that only cover samples before the absolute index `origin`.
+    fn truncate(&mut self, origin: usize) {
+        let first_block = origin / self.block_size;
+
+        if first_block > self.first_block {
+            let count = (first_block - self.first_block).min(self.blocks.len());
+            self.blocks.drop_front(count);
+            self.first_block = first_block;
+        }
+    }
+}
+
+/// Timestamps of the samples of a chunk: the ones of a regular rate starting from the first
+/// sample, plus the jitter of each sample if they do not match exactly. The step is not known
+/// until the second sample.
+#[derive(Clone)]
+enum Timestamps {
+    Regular { step: f64 },
+    Jittered { step: f64, jitter: Column<f32> },
+    Explicit(Column<f64>),
+}
+
+#[derive(Clone)]
+struct Chunk {
+    t0: f64,
+    timestamps: Timestamps,
+    values: Column<f64>,
+}
+
+impl Chunk {
+    fn new(t: f64, value: f64) -> Chunk {
+        let mut values = Vec::with_capacity(CHUNK_SIZE);
+        values.push(value);
+
+        Chunk {
+            t0: t,
+            timestamps: Timestamps::Regular { step: f64::NAN },
+            values: Column::Memory(values),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.values.as_slice().len()
+    }
+
+    fn is_full(&self) -> bool {
+        self.len() == CHUNK_SIZE
+    }
+
+    fn regular_time(&self, i: usize, step: f64) -> f64 {
+        if i == 0 {
+            self.t0
+        } else {
+            self.t0 + i as f64 * step
+        }
+    }
+
+    fn time(&self, i: usize) -> f64 {
+        match &self.timestamps {
+            Timestamps::Regular { step } => self.regular_time(i, *step),
+            Timestamps::Jittered { step, jitter } => {
+                self.regular_time(i, *step) + jitter.as_slice()[i] as f64
+            }
+            Timestamps::Explicit(times) => times.as_slice()[i],
+        }
+    }
+
+    fn get(&self, i: usize) -> PlotPoint {
+        PlotPoint::new(self.time(i), self.values.as_slice()[i])
+    }
+
+    fn push(&mut self, t: f64, value: f64) {
+        debug_assert!(!self.is_full());
+
+        let i = self.len();
+        let timestamps =
+            std::mem::replace(&mut self.timestamps, Timestamps::Regular { step: f64::NAN });
+
+        self.timestamps = match timestamps {
+            Timestamps::Regular { .. } if i == 1 => Timestamps::Regular { step: t - self.t0 },
+            // the step is the difference of the first two timestamps, whose rounding error adds up
+            // along the chunk and is not worth storing any jitter for
+            Timestamps::Regular { step }
+                if (self.regular_time(i, step) - t).abs()
+                    <= (i + 1) as f64 * t.abs() * f64::EPSILON =>
+            {
+                Timestamps::Regular { step }
+            }
+            Timestamps::Regular { step } => {
+                let mut jitter = Vec::with_capacity(CHUNK_SIZE);
+                jitter.resize(i, 0.0);
+                self.add_jittered(step, Column::Memory(jitter), t)
+            }
+            Timestamps::Jittered { step, jitter } => self.add_jittered(step, jitter, t),
+            Timestamps::Explicit(mut times) => {
+                times.push(t);
+                Timestamps::Explicit(times)
+            }
+        };
+
+        self.values.push(value);
+    }
+
+    /// Adds the timestamp `t` of the next sample to the ones of a jittered chunk, falling back to
+    /// explicit timestamps if its jitter is too large to be stored accurately enough.
+    fn add_jittered(&self, step: f64, mut jitter: Column<f32>, t: f64) -> Timestamps {
+        let i = jitter.as_slice().len();
+        let regular_t = self.regular_time(i, step);
+        let delta = (t - regular_t) as f32;
+
+        if (regular_t + delta as f64 - t).abs() <= TIME_TOLERANCE {
+            jitter.push(delta);
+            Timestamps::Jittered { step, jitter }
+        } else {
+            let mut times = Vec::with_capacity(CHUNK_SIZE);
+            times.extend(
+                jitter
+                    .as_slice()
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &delta)| self.regular_time(i, step) + delta as f64),
+            );
+            times.push(t);
+            Timestamps::Explicit(Column::Memory(times))
+        }
+    }
+
+    fn is_spilled(&self) -> bool {
+        self.values.is_spilled()
+    }
+
+    /// Writes the values and timestamps to a segment, returning their offsets.
+    fn write(&self, writer: &mut SegmentWriter) -> std::io::Result<(usize, Option<usize>)> {
+        let values_offset = writer.write(&self.values)?;
+
+        let timestamps_offset = match &self.timestamps {
+            Timestamps::Regular { .. } => None,
+            Timestamps::Jittered { jitter, .. } => Some(writer.write(jitter)?),
+            Timestamps::Explicit(times) => Some(writer.write(times)?),
+        };
+
+        Ok((values_offset, timestamps_offset))
+    }
+
+    /// Moves the values and timestamps to the segment they were written to by `write`.
+    fn spill(
+        &mut self,
+        segment: &Arc<Segment>,
+        (values_offset, timestamps_offset): (usize, Option<usize>),
+    ) {
+        self.values.spill(segment, values_offset);
+
+        match (&mut self.timestamps, timestamps_offset) {
+            (Timestamps::Regular { .. }, None) => {}
+            (Timestamps::Jittered { jitter, .. }, Some(offset)) => jitter.spill(segment, offset),
+            (Timestamps::Explicit(times), Some(offset)) => times.spill(segment, offset),
+            _ => unreachable!("timestamps changed after being written"),
+        }
+    }
+
+    fn memory_footprint(&self) -> (usize, usize) {
+        let (times_used, times_capacity) = match &self.timestamps {
+            Timestamps::Regular { .. } => (0, 0),
+            Timestamps::Jittered { jitter, .. } => jitter.memory_footprint(),
+            Timestamps::Explicit(times) => times.memory_footprint(),
+        };
+
+        let (values_used, values_capacity) = self.values.memory_footprint();
+
+        (values_used + times_used, values_capacity + times_capacity)
+    }
+
+    fn disk_footprint(&self) -> usize {
+        let times = match &self.timestamps {
+            Timestamps::Regular { .. } => 0,
+            Timestamps::Jittered { jitter, .. } => jitter.disk_footprint(),
+            Timestamps::Explicit(times) => times.disk_footprint(),
+        };
+
+        self.values.disk_footprint() + times
+    }
+}
+
+/// Cloning a buffer is cheap, as the clone shares the samples (and their summaries) until either
+/// of them gets changed, which only copies the last chunk: clones can be used as snapshots, for
+/// instance by other threads.
+#[derive(Clone)]
+pub struct SampleBuffer {
+    chunks: VecDeque<Arc<Chunk>>,
+    /// Number of chunks dropped from the front so far.
+    dropped_chunks: usize,
+    /// Index of the first sample in the first chunk; the ones before it have been truncated.
+    start: usize,
+    /// Number of chunks at the front that have been spilled to disk.
+    spilled_chunks: usize,
+    disk_size: usize,
+    pyramid: Vec<PyramidLevel>,
+}
+
+impl SampleBuffer {
+    pub fn new() -> SampleBuffer {
+        SampleBuffer {
+            chunks: VecDeque::new(),
+            dropped_chunks: 0,
+            start: 0,
+            spilled_chunks: 0,
+            disk_size: 0,
+            pyramid: Vec::new(),
+        }
+    }
+
+    /// Absolute index of the first sample, i.e. how many samples have been truncated so far.
+    fn origin(&self) -> usize {
+        self.dropped_chunks * CHUNK_SIZE + self.start
+    }
+
+    pub fn len(&self) -> usize {
+        match self.chunks.back() {
+            Some(last) => (self.chunks.len() - 1) * CHUNK_SIZE + last.len() - self.start,
+            None => 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the `i`-th sample; panics if `i` is out of bounds.
+    pub fn get(&self, i: usize) -> PlotPoint {
+        let i = self.start + i;
+        self.chunks[i / CHUNK_SIZE].get(i % CHUNK_SIZE)
+    }
+
+    fn time(&self, i: usize) -> f64 {
+        let i = self.start + i;
+        self.chunks[i / CHUNK_SIZE].time(i % CHUNK_SIZE)
+    }
+
+    pub fn push(&mut self, t: f64, value: f64) {
+        let index = self.origin() + self.len();
+
+        match self.chunks.back_mut() {
+            Some(chunk) if !chunk.is_full() => Arc::make_mut(chunk).push(t, value),
+            _ => {
+                self.chunks.push_back(Arc::new(Chunk::new(t, value)));
+
+                // the previous chunk might have been truncated entirely while it was the last one
+                if self.start >= CHUNK_SIZE {
+                    self.drop_front(0);
+                }
+            }
+        }
+
+        let envelope = Envelope::new(PlotPoint::new(t, value));
+        for level in &mut self.pyramid {
+            level.update(index / level.block_size, &envelope);
+        }
+
+        self.grow_pyramid();
+    }
+
+    /// Adds a level on top of the pyramid whenever the current top one has more than
+    /// PYRAMID_FACTOR blocks, so that the top level always stays small.
+    fn grow_pyramid(&mut self) {
+        let (block_size, first, envelopes) = match self.pyramid.last() {
+            None if self.len() > PYRAMID_FACTOR => (
+                1,
+                self.origin(),
+                self.samples().map(Envelope::new).collect::<Vec<_>>(),
+            ),
+            Some(level) if level.blocks.len() > PYRAMID_FACTOR => {
+                let blocks = (0..level.blocks.len()).filter_map(|j| level.blocks.get(j));
+                (
+                    level.block_size,
+                    level.first_block,
+                    blocks.copied().collect(),
+                )
+            }
+            _ => return,
+        };
+
+        let mut level = PyramidLevel {
+            block_size: block_size * PYRAMID_FACTOR,
+            first_block: 0,
+            blocks: SharedDeque::new(),
+        };
+
+        for (j, envelope) in envelopes.iter().enumerate() {
+            level.update((first + j) / PYRAMID_FACTOR, envelope);
+        }
+
+        self.pyramid.push(level);
+    }
+
+    /// All the samples, oldest first.
+    pub fn samples(&self) -> impl Iterator<Item = PlotPoint> + '_ {
+        self.samples_between(0, self.len())
+    }
+
+    fn samples_between(&self, from_i: usize, to_i: usize) -> impl Iterator<Item = PlotPoint> + '_ {
+        (from_i..to_i).map(|i| self.get(i))
+    }
+
+    /// The samples with a timestamp between `from_t` and `to_t`, both included.
+    pub fn range(&self, from_t: f64, to_t: f64) -> impl Iterator<Item = PlotPoint> + '_ {
+        let indices = self.index_range(from_t, to_t);
+
+        self.samples_between(indices.start, indices.end)
+    }
+
+    /// Indices of the samples with a timestamp between `from_t` and `to_t`, both included.
+    pub fn index_range(&self, from_t: f64, to_t: f64) -> Range<usize> {
+        let from_i = self.partition_point(|t| t < from_t);
+        let to_i = self.partition_point(|t| t <= to_t);
+
+        from_i..to_i.max(from_i)
+    }
+
+    /// Index of the first sample whose timestamp does not satisfy `pred`, which must be true for
+    /// all the samples before it and false for all the ones after.
+    fn partition_point(&self, pred: impl Fn(f64) -> bool) -> usize {
+        let (mut a, mut b) = (0, self.len());
+
+        while a < b {
+            let i = (a + b) / 2;
+
+            if pred(self.time(i)) {
+                a = i + 1;
+            } else {
+                b = i;
+            }
+        }
+
+        a
+    }
+
+    /// Index of the last sample at or before `t`, if any.
+    fn index_at_or_before(&self, t: f64) -> Option<usize> {
+        self.partition_point(|x| x <= t).checked_sub(1)
+    }
+
+    /// Returns the samples between `from_t` and `to_t`, scaled by `scale`. If there are more than
+    /// `max_points` of them, the range is split in at most `max_points / 2` blocks and each of
+    /// them is reduced to its minimum and maximum sample, which is what a plot would show anyway
+    /// if the blocks were about a pixel wide.
+    pub fn plot_points(&self, from_t: f64, to_t: f64, scale: f64, max_points: usize) -> PlotPoints {
+        if self.is_empty() {
+            return PlotPoints::Owned(Vec::new());
+        }
+
+        let from_i = self.index_at_or_before(from_t);
+        let to_i = self.index_at_or_before(to_t);
+
+        let len = self.len();
+
+        let (start, end) = match (from_i, to_i) {
+            (Some(a), Some(b)) if b >= len => (a, len),
+            (Some(a), Some(b)) if b < len => (a, b),
+            (None, Some(b)) if b < len => (0, b),
+            _ => (0, len),
+        };
+
+        if start < end && from_i.is_some() && to_i.is_some() {
+            let last_t = self.time(end - 1);
+            debug_assert!(last_t <= to_t, "last_t = {}, to_t = {}", last_t, to_t);
+        }
+
+        let points = if end - start > max_points {
+            self.decimate(start, end, max_points / 2)
+        } else {
+            self.samples_between(start, end).collect()
+        };
+
+        PlotPoints::Owned(
+            points
+                .iter()
+                .map(|p| PlotPoint::new(p.x, p.y * scale))
+                .collect(),
+        )
+    }
+
+    /// Reduces the samples in `from_i..to_i` to the envelopes of at most about `max_blocks`
+    /// blocks, taken from the lowest pyramid level coarse enough, followed by the last sample.
+    /// Blocks that are only partially within the range are computed from the samples, which costs
+    /// at most two blocks worth of samples.
+    fn decimate(&self, from_i: usize, to_i: usize, max_blocks: usize) -> Vec<PlotPoint> {
+        let max_blocks = max_blocks.max(1);
+        let origin = self.origin();
+        let (from_a, to_a) = (origin + from_i, origin + to_i);
+
+        let level = self
+            .pyramid
+            .iter()
+            .find(|level| (to_a - 1) / level.block_size - from_a / level.block_size < max_blocks)
+            .or(self.pyramid.last());
+
+        let Some(level) = level else {
+            return self.samples_between(from_i, to_i).collect();
+        };
+
+        let size = level.block_size;
+        let mut points = Vec::with_capacity(2 * ((to_a - from_a) / size + 2) + 1);
+
+        for j in (from_a / size)..=((to_a - 1) / size) {
+            let (a, b) = (j * size, (j + 1) * size);
+
+            let envelope = match level.block(j) {
+                Some(envelope) if a >= from_a && b <= to_a && a >= origin => *envelope,
+                _ => {
+                    Envelope::of(self.samples_between(a.max(from_a) - origin, b.min(to_a) - origin))
+                }
+            };
+
+            let (first, second) = if envelope.min.x <= envelope.max.x {
+                (envelope.min, envelope.max)
+            } else {
+                (envelope.max, envelope.min)
+            };
+
+            points.push(first);
+            if second.x != first.x {
+                points.push(second);
+            }
+        }
+
+        // the line must still end where the samples do, even though the last block might reach its
+        // extremes much earlier
+        let last = self.get(to_i - 1);
+        if points.last().is_some_and(|p| p.x != last.x) {
+            points.push(last);
+        }
+
+        points
+    }
+
+    /// Value at time `t`, interpolated between the samples around it, or the one of the closest
+    /// sample if `t` is out of the time bounds; NaN if there are no samples at all.
+    pub fn value_at(&self, t: f64) -> f64 {
+        let len = self.len();
+
+        match self.index_at_or_before(t) {
+            None if len > 0 => self.get(0).y,
+            Some(i) if i + 1 < len => {
+                let a = self.get(i);
+                let b = self.get(i + 1);
+
+                debug_assert!(a.x <= t && t <= b.x);
+
+                let alpha = (t - a.x) / (b.x - a.x);
+
+                a.y * (1.0 - alpha) + b.y * alpha
+            }
+            Some(i) => self.get(i).y,
+            None => f64::NAN,
+        }
+    }
+
+    /// The sample closest in time to `t`, if any.
+    pub fn nearest_sample(&self, t: f64) -> Option<PlotPoint> {
+        let after = self.partition_point(|x| x <= t);
+
+        [after.checked_sub(1), Some(after)]
+            .into_iter()
+            .flatten()
+            .filter(|&i| i < self.len())
+            .map(|i| self.get(i))
+            .min_by(|a, b| (a.x - t).abs().total_cmp(&(b.x - t).abs()))
+    }
+
+    pub fn memory_footprint(&self) -> (usize, usize) {
+        let envelope_size = std::mem::size_of::<Envelope>();
+
+        let (mut used, mut capacity) = self.chunks.iter().fold((0, 0), |(u, c), chunk| {
+            let (cu, cc) = chunk.memory_footprint();
+            (u + cu, c + cc)
+        });
+
+        for level in &self.pyramid {
+            used += level.blocks.len() * envelope_size;
+            capacity += level.blocks.capacity() * envelope_size;
+        }
+
+        (used, capacity)
+    }
+
+    pub fn time_bounds(&self) -> Option<(f64, f64)> {
+        if !self.is_empty() {
+            Some((self.time(0), self.time(self.len() - 1)))
+        } else {
+            None
+        }
+    }
+
+    pub fn truncate(&mut self, keep_seconds: f64) {
+        if self.is_empty() {
+            return;
+        }
+
+        let last_timestamp = self.time(self.len() - 1);
+        let truncate_timestamp = last_timestamp - keep_seconds;
+        let trigger_timestamp = last_timestamp - keep_seconds;
+
+        if self.time(0) < trigger_timestamp {
+            let a = self.index_at_or_before(truncate_timestamp).unwrap();
+            log::trace!("truncating buffer at {} / {}", a, self.len());
+            self.drop_front(a + 1);
+        }
+    }
+
+    /// Drops the oldest chunk of samples to free space, and returns how much memory and disk space
+    /// was released; nothing is dropped if only the chunk currently being filled is left.
+    pub fn drop_oldest_chunk(&mut self) -> (usize, usize) {
+        match self.chunks.front() {
+            Some(first) if self.chunks.len() > 1 => {
+                let (_, memory) = first.memory_footprint();
+                let disk = first.disk_footprint();
+                self.drop_front(first.len() - self.start);
+                (memory, disk)
+            }
+            _ => (0, 0),
+        }
+    }
+
+    /// Start time of the oldest chunks in memory, if there are enough of them to be spilled to disk.
+    pub fn spillable_since(&self) -> Option<f64> {
+        // the last chunk is still being filled
+        let resident = self.chunks.len().saturating_sub(self.spilled_chunks + 1);
+
+        if resident >= SEGMENT_CHUNKS {
+            Some(self.chunks[self.spilled_chunks].t0)
+        } else {
+            None
+        }
+    }
+
+    /// Moves the oldest chunks in memory to a new segment file in `directory`, and returns how
+    /// much memory was released. The samples stay accessible exactly as before.
+    pub fn spill(&mut self, directory: &Path) -> std::io::Result<usize> {
+        if self.spillable_since().is_none() {
+            return Ok(0);
+        }
+
+        let chunks = self.spilled_chunks..(self.spilled_chunks + SEGMENT_CHUNKS);
+
+        let mut writer = SegmentWriter::create(directory)?;
+        let offsets = self
+            .chunks
+            .range(chunks.clone())
+            .map(|chunk| chunk.write(&mut writer))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        let segment = writer.finish()?;
+
+        let mut released = 0;
+        for (chunk, offsets) in self.chunks.range_mut(chunks).zip(offsets) {
+            released += chunk.memory_footprint().1;
+            Arc::make_mut(chunk).spill(&segment, offsets);
+            self.disk_size += chunk.disk_footprint();
+        }
+
+        self.spilled_chunks += SEGMENT_CHUNKS;
+
+        Ok(released)
+    }
+
+    /// Size of the samples spilled to disk.
+    pub fn disk_footprint(&self) -> usize {
+        self.disk_size
+    }
+
+    /// Drops the first `count` samples. Full chunks are released as soon as none of their samples
+    /// are left, while the last one is kept (possibly empty) so that new samples keep their place.
+    fn drop_front(&mut self, count: usize) {
+        debug_assert!(count <= self.len());
+
+        self.start += count;
+
+        while self.chunks.len() > 1 && self.start >= CHUNK_SIZE {
+            let chunk = self.chunks.pop_front().unwrap();
+            if chunk.is_spilled() {
+                self.spilled_chunks -= 1;
+                self.disk_size -= chunk.disk_footprint();
+            }
+
+            self.dropped_chunks += 1;
+            self.start -= CHUNK_SIZE;
+        }
+
+        let origin = self.origin();
+        for level in &mut self.pyramid {
+            level.truncate(origin);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_index_before_at() {
+        let mut buffer = SampleBuffer::new();
+
+        for i in 0..10 {
+            buffer.push(i as f64, i as f64);
+        }
+
+        assert_eq!(buffer.index_at_or_before(-1.0), None);
+        assert_eq!(buffer.index_at_or_before(0.5), Some(0));
+        assert_eq!(buffer.index_at_or_before(5.0), Some(5));
+        assert_eq!(buffer.index_at_or_before(10.0), Some(9));
+        assert_eq!(buffer.index_at_or_before(-f64::INFINITY), None);
+        assert_eq!(buffer.index_at_or_before(f64::INFINITY), Some(9));
+
+        assert_eq!(buffer.nearest_sample(-1.0).map(|p| p.x), Some(0.0));
+        assert_eq!(buffer.nearest_sample(4.4).map(|p| p.x), Some(4.0));
+        assert_eq!(buffer.nearest_sample(4.6).map(|p| p.x), Some(5.0));
+        assert_eq!(buffer.nearest_sample(20.0).map(|p| p.x), Some(9.0));
+        assert_eq!(SampleBuffer::new().nearest_sample(0.0), None);
+    }
+
+    #[test]
+    fn test_samplebuffer_push() {
+        let mut buffer = SampleBuffer::new();
+
+        for i in 0..10 {
+            buffer.push(i as f64, i as f64 + 1.0);
+        }
+
+        assert_eq!(buffer.len(), 10);
+
+        for (i, sample) in (0..10).zip(buffer.samples()) {
+            assert_eq!(sample.x, i as f64);
+            assert_eq!(sample.y, i as f64 + 1.0);
+        }
+    }
+
+    #[test]
+    fn test_samplebuffer_plot_points_range() {
+        let mut buffer = SampleBuffer::new();
+
+        for i in 0..100 {
+            buffer.push(i as f64, i as f64 + 1.0);
+        }
+
+        assert_eq!(buffer.len(), 100);
+
+        assert!(buffer
+            .plot_points(3.0, 50.0, 1.0, usize::MAX)
+            .points()
+            .iter()
+            .all(|p| p.x >= 3.0 && p.x <= 50.0));
+
+        assert!(buffer
+            .plot_points(-f64::INFINITY, f64::INFINITY, 1.0, usize::MAX)
+            .points()
+            .iter()
+            .all(|p| p.x >= 0.0 && p.x <= 99.0));
+    }
+
+    #[test]
+    fn test_samplebuffer_plot_points_scale() {
+        let mut buffer = SampleBuffer::new();
+
+        for i in 0..10 {
+            buffer.push(i as f64, i as f64 + 1.0);
+        }
+
+        assert_eq!(buffer.len(), 10);
+
+        let scaled = buffer.plot_points(-f64::INFINITY, f64::INFINITY, 1e3, usize::MAX);
+
+        for (bp, sp) in buffer.samples().zip(scaled.points().iter()) {
+            assert_eq!(sp.y, bp.y * 1e3);
+        }
+    }
+
+    #[test]
+    fn test_samplebuffer_time_bounds() {
+        let mut buffer = SampleBuffer::new();
+
+        assert_eq!(buffer.time_bounds(), None);
+
+        buffer.push(0.0, 1.0);
+
+        assert_eq!(buffer.time_bounds(), Some((0.0, 0.0)));
+
+        for i in 1..100 {
+            buffer.push(i as f64, i as f64 + 1.0);
+        }
+
+        assert_eq!(buffer.len(), 100);
+        assert_eq!(buffer.time_bounds(), Some((0.0, 99.0)));
+    }
+
+    #[test]
+    fn test_samplebuffer_truncate() {
+        let mut buffer = SampleBuffer::new();
+
+        for i in 0..100 {
+            buffer.push(i as f64, i as f64 + 1.0);
+        }
+
+        assert_eq!(buffer.len(), 100);
+
+        buffer.truncate(10.0);
+
+        assert_eq!(buffer.len(), 10);
+        assert_eq!(buffer.time_bounds(), Some((90.0, 99.0)));
+    }
+
+    #[test]
+    fn test_samplebuffer_plot_points_decimation() {
+        let mut buffer = SampleBuffer::new();
+
+        for i in 0..100_000 {
+            // a single glitch in an otherwise flat signal
+            let value = if i == 54_321 { 100.0 } else { (i % 2) as f64 };
+            buffer.push(i as f64, value);
+        }
+
+        let all = buffer.plot_points(-f64::INFINITY, f64::INFINITY, 2.0, usize::MAX);
+        let all = all.points();
+        let points = buffer.plot_points(-f64::INFINITY, f64::INFINITY, 2.0, 1000);
+        let points = points.points();
+
+        assert!(points.len() <= 1000 + 4, "{} points", points.len());
+        assert!(points.windows(2).all(|w| w[0].x < w[1].x));
+        assert_eq!(points.first().unwrap().x, all.first().unwrap().x);
+        assert_eq!(points.last().unwrap().x, all.last().unwrap().x);
+        assert!(points.iter().any(|p| p.x == 54_321.0 && p.y == 200.0));
+        assert!(points
+            .iter()
+            .all(|p| p.y == 0.0 || p.y == 2.0 || p.y == 200.0));
+
+        // a subrange, not aligned to the blocks of any level
+        let points = buffer.plot_points(12_345.5, 67_890.5, 1.0, 100);
+        let points = points.points();
+
+        assert!(points.len() <= 100 + 4, "{} points", points.len());
+        assert!(points.iter().all(|p| p.x >= 12_345.0 && p.x <= 67_890.0));
+        assert!(points.iter().any(|p| p.x == 54_321.0 && p.y == 100.0));
+    }
+
+    #[test]
+    fn test_samplebuffer_plot_points_decimation_truncated() {
+        let mut buffer = SampleBuffer::new();
+
+        // the maximum of each truncation round is dropped with it, and must not show up afterwards
+        for round in 0..10 {
+            for i in 0..10_000 {
+                let t = (round * 10_000 + i) as f64;
+                let value = if i == 0 {
+                    1000.0 + round as f64
+                } else {
+                    round as f64
+                };
+                buffer.push(t, value);
+            }
+
+            buffer.truncate(3_333.0);
+
+            let bounds = buffer.time_bounds().unwrap();
+            let points = buffer.plot_points(-f64::INFINITY, f64::INFINITY, 1.0, 50);
+            let points = points.points();
+
+            assert!(points.len() <= 50 + 4, "{} points", points.len());
+            assert!(points.iter().all(|p| p.x >= bounds.0 && p.x <= bounds.1));
+            assert!(points.iter().all(|p| p.y == round as f64));
+        }
+
+        let (used, _) = buffer.memory_footprint();
+        let samples = buffer.len() * std::mem::size_of::<PlotPoint>();
+        assert!(used < samples * 2);
+    }
+
+    #[test]
+    fn test_samplebuffer_timestamps() {
+        let mut regular = SampleBuffer::new();
+        let mut jittered = SampleBuffer::new();
+        let mut irregular = SampleBuffer::new();
+
+        let n = 3 * CHUNK_SIZE + 10;
+        let jitter = |i: usize| ((i * 7919) % 13) as f64 * 1e-6;
+        // gaps too long for their exact length to be stored as jitter
+        let gap = |i: usize| (i / 100) as f64 * 1234.5678;
+
+        for i in 0..n {
+            regular.push(i as f64 * 1e-3, i as f64);
+            jittered.push(10.0 + i as f64 * 1e-3 + jitter(i), i as f64);
+            irregular.push(i as f64 * 1e-3 + gap(i), i as f64);
+        }
+
+        for i in 0..n {
+            assert!((regular.get(i).x - i as f64 * 1e-3).abs() < 1e-9);
+            assert!(
+                (jittered.get(i).x - (10.0 + i as f64 * 1e-3 + jitter(i))).abs() <= TIME_TOLERANCE
+            );
+            assert!((irregular.get(i).x - (i as f64 * 1e-3 + gap(i))).abs() <= TIME_TOLERANCE);
+            assert_eq!(irregular.get(i).y, i as f64);
+        }
+
+        // a regular rate takes only the values (and the pyramid), the jitter adds a f32 per sample
+        let (regular_size, _) = regular.memory_footprint();
+        let (jittered_size, _) = jittered.memory_footprint();
+        let (irregular_size, _) = irregular.memory_footprint();
+        assert!(
+            regular_size < n * 14,
+            "{} bytes per sample",
+            regular_size as f64 / n as f64
+        );
+        assert_eq!(jittered_size - regular_size, n * 4);
+        assert!(irregular_size > jittered_size);
+    }
+
+    #[test]
+    fn test_samplebuffer_truncate_chunks() {
+        let mut buffer = SampleBuffer::new();
+
+        for i in 0..(3 * CHUNK_SIZE) {
+            buffer.push(i as f64, i as f64);
+        }
+
+        assert_eq!(buffer.chunks.len(), 3);
+
+        // within the first chunk, which is kept
+        buffer.truncate((3 * CHUNK_SIZE - 100) as f64);
+        assert_eq!(buffer.chunks.len(), 3);
+        assert_eq!(buffer.len(), 3 * CHUNK_SIZE - 100);
+        assert_eq!(buffer.get(0).x, 100.0);
+
+        // the first chunk is released, along with a part of the second one
+        buffer.truncate((2 * CHUNK_SIZE - 100) as f64);
+        assert_eq!(buffer.chunks.len(), 2);
+        assert_eq!(
+            buffer.time_bounds(),
+            Some(((CHUNK_SIZE + 100) as f64, (3 * CHUNK_SIZE - 1) as f64))
+        );
+
+        // the chunk being filled is never dropped
+        assert!(buffer.drop_oldest_chunk().0 > 0);
+        assert_eq!(buffer.len(), CHUNK_SIZE);
+        assert_eq!(buffer.get(0).x, (2 * CHUNK_SIZE) as f64);
+        assert_eq!(buffer.drop_oldest_chunk(), (0, 0));
+
+        // truncating everything leaves an empty buffer that keeps on working
+        buffer.truncate(0.0);
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.time_bounds(), None);
+
+        for i in 0..10 {
+            buffer.push((3 * CHUNK_SIZE + i) as f64, 0.0);
+        }
+
+        assert_eq!(buffer.len(), 10);
+        assert_eq!(buffer.chunks.len(), 1);
+        assert_eq!(buffer.get(0).x, (3 * CHUNK_SIZE) as f64);
+        assert_eq!(buffer.range(3.0 * CHUNK_SIZE as f64 + 2.5, 1e9).count(), 7);
+    }
+
+    #[test]
+    fn test_samplebuffer_spill() {
+        let directory =
+            std::env::temp_dir().join(format!("ocdscope-test-spill-{}", std::process::id()));
+        let segments = || std::fs::read_dir(&directory).unwrap().count();
+
+        let mut memory = SampleBuffer::new();
+        let mut spilled = SampleBuffer::new();
+
+        // a regular chunk, a jittered one and an explicit one, repeated
+        let n = 3 * SEGMENT_CHUNKS * CHUNK_SIZE - 10;
+        let t = |i: usize| {
+            let (chunk, j) = (i / CHUNK_SIZE, i % CHUNK_SIZE);
+            let local = match chunk % 3 {
+                0 => j as f64 * 1e-3,
+                1 => j as f64 * 1e-3 + (j % 7) as f64 * 1e-6,
+                _ => j as f64 * 1e-3 + (j / 5) as f64 * 1.2345,
+            };
+            chunk as f64 * 1e4 + local
+        };
+
+        for i in 0..n {
+            let value = ((i * 31) % 101) as f64;
+            memory.push(t(i), value);
+            spilled.push(t(i), value);
+        }
+
+        let (_, before) = spilled.memory_footprint();
+        assert_eq!(spilled.spillable_since(), Some(0.0));
+        assert!(spilled.spill(&directory).unwrap() > 0);
+        assert!(spilled.spill(&directory).unwrap() > 0);
+        assert_eq!(segments(), 2);
+
+        // only the last, incomplete segment worth of chunks is left in memory
+        assert_eq!(spilled.spillable_since(), None);
+        assert_eq!(spilled.spill(&directory).unwrap(), 0);
+
+        let (_, after) = spilled.memory_footprint();
+        assert!(after < before * 2 / 3);
+        assert!(spilled.disk_footprint() >= 2 * SEGMENT_CHUNKS * CHUNK_SIZE * 8);
+
+        assert_eq!(spilled.len(), memory.len());
+        assert!(memory
+            .samples()
+            .zip(spilled.samples())
+            .all(|(a, b)| a.x == b.x && a.y == b.y));
+
+        for (from_t, to_t, max_points) in [
+            (f64::NEG_INFINITY, f64::INFINITY, 1000),
+            (123.4, 567.8, 100),
+            (10.0, 10.5, 1000),
+        ] {
+            let a = memory.plot_points(from_t, to_t, 1.0, max_points);
+            let b = spilled.plot_points(from_t, to_t, 1.0, max_points);
+            assert_eq!(a.points(), b.points());
+        }
+
+        // dropping all the chunks of a segment removes its file
+        for _ in 0..SEGMENT_CHUNKS {
+            let (memory, disk) = spilled.drop_oldest_chunk();
+            assert_eq!(memory, 0);
+            assert!(disk > 0);
+        }
+        assert_eq!(segments(), 1);
+
+        drop(spilled);
+        assert_eq!(segments(), 0);
+
+        std::fs::remove_dir(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_samplebuffer_snapshot() {
+        let mut buffer = SampleBuffer::new();
+
+        for i in 0..(2 * CHUNK_SIZE + 100) {
+            buffer.push(i as f64, 2.0 * i as f64);
+        }
+
+        let snapshot = buffer.clone();
+
+        // the snapshot can be read by another thread while the buffer keeps changing
+        let reader = std::thread::spawn(move || {
+            let values: Vec<f64> = [-1.0, 0.5, 100.25, 1e9]
+                .map(|t| snapshot.value_at(t))
+                .into();
+            (snapshot.len(), values)
+        });
+
+        buffer.truncate(10.0);
+        for i in 0..100 {
+            buffer.push((2 * CHUNK_SIZE + 100 + i) as f64, 0.0);
+        }
+
+        let (len, values) = reader.join().unwrap();
+        assert_eq!(len, 2 * CHUNK_SIZE + 100);
+        assert_eq!(
+            values,
+            [0.0, 1.0, 200.5, 2.0 * (2 * CHUNK_SIZE + 99) as f64]
+        );
+
+        // the sealed chunks are shared, the one being filled is copied once written
+        let snapshot = buffer.clone();
+        assert!(Arc::ptr_eq(&buffer.chunks[0], &snapshot.chunks[0]));
+        buffer.push(1e9, 0.0);
+        assert!(!Arc::ptr_eq(
+            buffer.chunks.back().unwrap(),
+            snapshot.chunks.back().unwrap()
+        ));
+        assert_eq!(snapshot.len() + 1, buffer.len());
+
+        assert!(SampleBuffer::new().value_at(0.0).is_nan());
+    }
+
+    /// Run with `cargo test --release -- --ignored --nocapture bench_plot_points`: the time per
+    /// frame should stay about the same as the buffer grows.
+    #[test]
+    #[ignore]
+    fn bench_plot_points() {
+        const FRAMES: u32 = 100;
+
+        for size in [10_000, 100_000, 1_000_000, 10_000_000] {
+            let mut buffer = SampleBuffer::new();
+
+            for i in 0..size {
+                buffer.push(i as f64 * 1e-6, (i as f64 * 1e-3).sin());
+            }
+
+            let start = std::time::Instant::now();
+            let mut count = 0;
+
+            for _ in 0..FRAMES {
+                count += buffer
+                    .plot_points(-f64::INFINITY, f64::INFINITY, 1.0, 4000)
+                    .points()
+                    .len();
+            }
+
+            println!(
+                "{:>10} samples: {:>10.3?} per frame, {} points",
+                size,
+                start.elapsed() / FRAMES,
+                count / FRAMES as usize
+            );
+        }
+    }
+}
@@ -0,0 +1,120 @@
+// A double-ended queue stored in fixed-size blocks shared between clones: cloning only copies the
+// pointers to the blocks, and a block is copied only when it gets written while shared, which can
+// only happen to the last one since elements are only appended or changed at the back.
+
+use std::{collections::VecDeque, sync::Arc};
+
+/// Number of elements in each block.
+const BLOCK_SIZE: usize = 1024;
+
+#[derive(Clone)]
+pub struct SharedDeque<T> {
+    blocks: VecDeque<Arc<Vec<T>>>,
+    /// Index of the first element in the first block; the ones before it have been dropped.
+    start: usize,
+}
+
+impl<T: Clone> SharedDeque<T> {
+    pub fn new() -> SharedDeque<T> {
+        SharedDeque {
+            blocks: VecDeque::new(),
+            start: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self.blocks.back() {
+            Some(last) => (self.blocks.len() - 1) * BLOCK_SIZE + last.len() - self.start,
+            None => 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, i: usize) -> Option<&T> {
+        if i < self.len() {
+            let i = self.start + i;
+            self.blocks[i / BLOCK_SIZE].get(i % BLOCK_SIZE)
+        } else {
+            None
+        }
+    }
+
+    pub fn back_mut(&mut self) -> Option<&mut T> {
+        if self.is_empty() {
+            return None;
+        }
+
+        self.blocks
+            .back_mut()
+            .and_then(|last| Arc::make_mut(last).last_mut())
+    }
+
+    pub fn push_back(&mut self, value: T) {
+        match self.blocks.back_mut() {
+            Some(last) if last.len() < BLOCK_SIZE => Arc::make_mut(last).push(value),
+            _ => {
+                let mut block = Vec::with_capacity(BLOCK_SIZE);
+                block.push(value);
+                self.blocks.push_back(Arc::new(block));
+
+                // the previous block might have been dropped entirely while it was the last one
+                self.drop_front(0);
+            }
+        }
+    }
+
+    /// Drops the first `count` elements, releasing the blocks left empty but the last one.
+    pub fn drop_front(&mut self, count: usize) {
+        debug_assert!(count <= self.len());
+
+        self.start += count;
+
+        while self.blocks.len() > 1 && self.start >= BLOCK_SIZE {
+            self.blocks.pop_front();
+            self.start -= BLOCK_SIZE;
+        }
+    }
+
+    /// Number of elements allocated.
+    pub fn capacity(&self) -> usize {
+        self.blocks.iter().map(|block| block.capacity()).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shared_deque() {
+        let mut deque = SharedDeque::new();
+
+        for i in 0..(3 * BLOCK_SIZE) {
+            deque.push_back(i);
+        }
+
+        let snapshot = deque.clone();
+
+        *deque.back_mut().unwrap() = 0;
+        deque.drop_front(BLOCK_SIZE + 10);
+        deque.push_back(3 * BLOCK_SIZE);
+
+        assert_eq!(deque.len(), 2 * BLOCK_SIZE - 9);
+        assert_eq!(deque.get(0), Some(&(BLOCK_SIZE + 10)));
+        assert_eq!(deque.get(deque.len() - 2), Some(&0));
+        assert_eq!(deque.get(deque.len()), None);
+
+        // the snapshot does not see any of the changes, and only the last block was copied
+        assert_eq!(snapshot.len(), 3 * BLOCK_SIZE);
+        assert!((0..snapshot.len()).all(|i| snapshot.get(i) == Some(&i)));
+        assert!(Arc::ptr_eq(&deque.blocks[0], &snapshot.blocks[1]));
+        assert!(!Arc::ptr_eq(&deque.blocks[1], &snapshot.blocks[2]));
+
+        deque.drop_front(deque.len());
+        assert!(deque.is_empty());
+        assert_eq!(deque.back_mut(), None);
+    }
+}
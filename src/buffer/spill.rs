@@ -0,0 +1,180 @@
+// Columns of samples that are either in memory or spilled to a memory-mapped file in a cache
+// directory, where the OS pages them in and out as they are accessed.
+//
+// Chunks are spilled in batches, each written to its own segment file which is then mapped read
+// only: a segment is never written again once mapped, and its file is removed as soon as the last
+// chunk referencing it is dropped.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use memmap2::Mmap;
+
+/// Types that can be stored as raw bytes in a segment, and read back in place.
+pub trait Plain: Copy {}
+
+impl Plain for f32 {}
+impl Plain for f64 {}
+
+/// Alignment of the columns within a segment, enough for any Plain type.
+const ALIGNMENT: usize = 8;
+
+/// Used to name the segment files uniquely within the process.
+static SEGMENT_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// A file in the cache directory, removed when dropped.
+struct CacheFile(PathBuf);
+
+impl Drop for CacheFile {
+    fn drop(&mut self) {
+        if let Err(err) = std::fs::remove_file(&self.0) {
+            log::warn!("cannot remove {}: {}", self.0.display(), err);
+        }
+    }
+}
+
+pub struct Segment {
+    // declared before the file, so that it gets unmapped before the file is removed
+    map: Mmap,
+    _file: CacheFile,
+}
+
+#[derive(Clone)]
+pub enum Column<T> {
+    Memory(Vec<T>),
+    Spilled {
+        segment: Arc<Segment>,
+        offset: usize,
+        len: usize,
+    },
+}
+
+impl<T: Plain> Column<T> {
+    pub fn as_slice(&self) -> &[T] {
+        match self {
+            Column::Memory(values) => values,
+            Column::Spilled {
+                segment,
+                offset,
+                len,
+            } => {
+                let bytes = &segment.map[*offset..(*offset + len * std::mem::size_of::<T>())];
+                debug_assert!((bytes.as_ptr() as usize).is_multiple_of(std::mem::align_of::<T>()));
+
+                // the bytes were written from a slice of the same type by SegmentWriter::write, at
+                // an aligned offset of a page-aligned map that is never written again
+                unsafe { std::slice::from_raw_parts(bytes.as_ptr() as *const T, *len) }
+            }
+        }
+    }
+
+    /// Appends a value; only columns still in memory can grow.
+    pub fn push(&mut self, value: T) {
+        match self {
+            Column::Memory(values) => values.push(value),
+            Column::Spilled { .. } => panic!("cannot push to a spilled column"),
+        }
+    }
+
+    pub fn is_spilled(&self) -> bool {
+        matches!(self, Column::Spilled { .. })
+    }
+
+    pub fn memory_footprint(&self) -> (usize, usize) {
+        match self {
+            Column::Memory(values) => (
+                std::mem::size_of_val(values.as_slice()),
+                values.capacity() * std::mem::size_of::<T>(),
+            ),
+            Column::Spilled { .. } => (0, 0),
+        }
+    }
+
+    pub fn disk_footprint(&self) -> usize {
+        match self {
+            Column::Memory(_) => 0,
+            Column::Spilled { len, .. } => len * std::mem::size_of::<T>(),
+        }
+    }
+
+    /// Replaces the values in memory with the ones written to `segment` at `offset`.
+    pub fn spill(&mut self, segment: &Arc<Segment>, offset: usize) {
+        debug_assert!(!self.is_spilled());
+
+        *self = Column::Spilled {
+            segment: segment.clone(),
+            offset,
+            len: self.as_slice().len(),
+        };
+    }
+}
+
+pub struct SegmentWriter {
+    writer: BufWriter<File>,
+    file: CacheFile,
+    size: usize,
+}
+
+impl SegmentWriter {
+    pub fn create(directory: &Path) -> std::io::Result<SegmentWriter> {
+        std::fs::create_dir_all(directory)?;
+
+        let filename = directory.join(format!(
+            "{}-{}.segment",
+            std::process::id(),
+            SEGMENT_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        // readable too, for it to be mapped once written
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&filename)?;
+        let writer = BufWriter::new(file);
+
+        Ok(SegmentWriter {
+            writer,
+            file: CacheFile(filename),
+            size: 0,
+        })
+    }
+
+    /// Writes the values of a column, and returns their offset within the segment.
+    pub fn write<T: Plain>(&mut self, column: &Column<T>) -> std::io::Result<usize> {
+        let values = column.as_slice();
+
+        // the raw bytes of the values, which only need to be read back by this same process
+        let bytes = unsafe {
+            std::slice::from_raw_parts(values.as_ptr() as *const u8, std::mem::size_of_val(values))
+        };
+
+        let offset = self.size;
+        let padding = bytes.len().next_multiple_of(ALIGNMENT) - bytes.len();
+
+        self.writer.write_all(bytes)?;
+        self.writer.write_all(&[0; ALIGNMENT][..padding])?;
+        self.size += bytes.len() + padding;
+
+        Ok(offset)
+    }
+
+    pub fn finish(self) -> std::io::Result<Arc<Segment>> {
+        let file = self.writer.into_inner().map_err(|err| err.into_error())?;
+
+        // the file is private to this process, which never writes it again
+        let map = unsafe { Mmap::map(&file)? };
+
+        Ok(Arc::new(Segment {
+            map,
+            _file: self.file,
+        }))
+    }
+}
@@ -0,0 +1,87 @@
+// Measurement cursors: two vertical ones, at times shared by all the plot panes, and two horizontal
+// ones in each pane, at values of its left axis. They are moved dragging them on the plot, and can
+// snap to the samples.
+
+use eframe::egui::Pos2;
+
+/// Distance, in points, from a cursor within which a drag grabs it.
+pub const GRAB_DISTANCE: f32 = 6.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CursorId {
+    Time(usize),
+    Value(usize),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Cursors {
+    pub enabled: bool,
+    /// Whether the cursors snap to the samples closest to the pointer.
+    pub snap: bool,
+    /// Times of the vertical cursors, in the time base of the plot.
+    pub times: [f64; 2],
+    /// The cursor being dragged, and the pane it's dragged in.
+    pub grabbed: Option<(usize, CursorId)>,
+}
+
+impl Cursors {
+    /// Time between the cursors, in seconds.
+    pub fn delta_time(&self) -> f64 {
+        self.times[1] - self.times[0]
+    }
+}
+
+/// The cursor within `GRAB_DISTANCE` of `pointer`, the closest one if more are, given the screen
+/// positions of the vertical ones, along X, and of the horizontal ones, along Y.
+pub fn grabbable(pointer: Pos2, times_x: [f32; 2], values_y: [f32; 2]) -> Option<CursorId> {
+    let times = times_x
+        .iter()
+        .enumerate()
+        .map(|(i, x)| (CursorId::Time(i), (x - pointer.x).abs()));
+    let values = values_y
+        .iter()
+        .enumerate()
+        .map(|(i, y)| (CursorId::Value(i), (y - pointer.y).abs()));
+
+    times
+        .chain(values)
+        .filter(|(_, distance)| *distance <= GRAB_DISTANCE)
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(id, _)| id)
+}
+
+/// The candidate closest to `target`, if any.
+pub fn closest(target: f64, candidates: impl Iterator<Item = f64>) -> Option<f64> {
+    candidates
+        .filter(|candidate| candidate.is_finite())
+        .min_by(|a, b| (a - target).abs().total_cmp(&(b - target).abs()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_grabbable() {
+        let times_x = [100.0, 200.0];
+        let values_y = [50.0, 150.0];
+
+        let grab = |x, y| grabbable(Pos2::new(x, y), times_x, values_y);
+
+        assert_eq!(grab(103.0, 0.0), Some(CursorId::Time(0)));
+        assert_eq!(grab(198.0, 300.0), Some(CursorId::Time(1)));
+        assert_eq!(grab(0.0, 152.0), Some(CursorId::Value(1)));
+        assert_eq!(grab(0.0, 0.0), None);
+
+        // the closest one wins where two cross
+        assert_eq!(grab(101.0, 48.0), Some(CursorId::Time(0)));
+        assert_eq!(grab(104.0, 49.0), Some(CursorId::Value(0)));
+    }
+
+    #[test]
+    fn test_closest() {
+        assert_eq!(closest(1.2, [0.0, 1.0, 2.0].into_iter()), Some(1.0));
+        assert_eq!(closest(1.2, [f64::NAN, 2.0].into_iter()), Some(2.0));
+        assert_eq!(closest(1.2, std::iter::empty()), None);
+    }
+}
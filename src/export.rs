@@ -1,23 +1,186 @@
 use std::collections::HashMap;
 use std::fmt::Display;
-use std::io::Write;
+use std::io::{BufWriter, Write};
 use std::path::Path;
 
-use anyhow::Context;
+use egui_plot::PlotPoint;
 
 use crate::{buffer::SampleBuffer, SignalConfig};
 
+/// How the samples of signals with different timestamps are laid out in the exported table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportLayout {
+    /// One row per sample, with the time, the signal and the value; in NumPy files the signal is
+    /// its position among the exported ones.
+    Tidy,
+    /// One row per time at which any signal was sampled, with the time and a column per signal,
+    /// whose values at the times it wasn't sampled are computed as given.
+    Aligned(Alignment),
+}
+
+/// How to compute the value of a signal between its samples; where it can't, the value is NaN.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Alignment {
+    /// Interpolate linearly between the samples before and after.
+    Linear,
+    /// Hold the value of the sample before.
+    Hold,
+    /// Take the value of the nearest sample.
+    Nearest,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Csv,
+    Npy,
+    Npz,
+    ArrowIpc,
+    Mat,
+    Wav,
+}
+
+impl ExportFormat {
+    pub const ALL: [ExportFormat; 6] = [
+        ExportFormat::Csv,
+        ExportFormat::Npy,
+        ExportFormat::Npz,
+        ExportFormat::ArrowIpc,
+        ExportFormat::Mat,
+        ExportFormat::Wav,
+    ];
+
+    pub fn description(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "CSV",
+            ExportFormat::Npy => "NumPy array (.npy)",
+            ExportFormat::Npz => "NumPy archive (.npz)",
+            ExportFormat::ArrowIpc => "Arrow IPC (.arrow)",
+            ExportFormat::Mat => "MATLAB (.mat)",
+            ExportFormat::Wav => "WAV audio",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Npy => "npy",
+            ExportFormat::Npz => "npz",
+            ExportFormat::ArrowIpc => "arrow",
+            ExportFormat::Mat => "mat",
+            ExportFormat::Wav => "wav",
+        }
+    }
+}
+
+/// What to export, besides the layout.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ExportOptions {
+    pub layout: ExportLayout,
+    /// Only export the samples between these times, if given.
+    pub range: Option<(f64, f64)>,
+    /// Export one sample every `decimation` of each signal.
+    pub decimation: usize,
+}
+
+/// Time of all the samples of the given signals, sorted and without duplicates.
+fn common_timeline(signal_samples: &[impl AsRef<[PlotPoint]>]) -> Vec<f64> {
+    let mut timeline: Vec<f64> = signal_samples
+        .iter()
+        .flat_map(|samples| samples.as_ref().iter().map(|p| p.x))
+        .collect();
+
+    timeline.sort_by(f64::total_cmp);
+    timeline.dedup();
+
+    timeline
+}
+
+/// Values of a signal at each time of `timeline`; both the samples and the timeline must be
+/// sorted by time.
+fn align(samples: &[PlotPoint], timeline: &[f64], alignment: Alignment) -> Vec<f64> {
+    // number of samples at or before the current time
+    let mut i = 0;
+
+    timeline
+        .iter()
+        .map(|&t| {
+            while i < samples.len() && samples[i].x <= t {
+                i += 1;
+            }
+
+            let before = i.checked_sub(1).map(|i| samples[i]);
+            let after = samples.get(i).copied();
+
+            match (alignment, before, after) {
+                (_, Some(before), _) if before.x == t => before.y,
+                (Alignment::Hold, Some(before), _) => before.y,
+                (Alignment::Linear, Some(before), Some(after)) => {
+                    let alpha = (t - before.x) / (after.x - before.x);
+                    before.y + alpha * (after.y - before.y)
+                }
+                (Alignment::Nearest, Some(before), Some(after)) => {
+                    match t - before.x <= after.x - t {
+                        true => before.y,
+                        false => after.y,
+                    }
+                }
+                (Alignment::Nearest, Some(p), None) | (Alignment::Nearest, None, Some(p)) => p.y,
+                _ => f64::NAN,
+            }
+        })
+        .collect()
+}
+
+/// Samples of each of the given signals to export, empty for the ones without a buffer.
+fn signal_samples(
+    signals: &[SignalConfig],
+    samples: &HashMap<u32, SampleBuffer>,
+    options: &ExportOptions,
+) -> Vec<Vec<PlotPoint>> {
+    signals
+        .iter()
+        .map(|signal| {
+            let Some(buffer) = samples.get(&signal.id) else {
+                return Vec::new();
+            };
+
+            let (from_t, to_t) = options.range.unwrap_or((-f64::INFINITY, f64::INFINITY));
+
+            buffer
+                .range(from_t, to_t)
+                .step_by(options.decimation.max(1))
+                .collect()
+        })
+        .collect()
+}
+
+/// Samples of all the signals as (time, position of the signal, value), sorted by time.
+fn tidy_rows(signal_samples: &[impl AsRef<[PlotPoint]>]) -> Vec<(f64, usize, f64)> {
+    let mut rows: Vec<_> = signal_samples
+        .iter()
+        .enumerate()
+        .flat_map(|(i, samples)| samples.as_ref().iter().map(move |p| (p.x, i, p.y)))
+        .collect();
+
+    // the sort is stable, the signals at the same time stay in order
+    rows.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    rows
+}
+
 pub fn write_csv(
     filename: &Path,
     signals: &[SignalConfig],
     samples: &HashMap<u32, SampleBuffer>,
+    options: &ExportOptions,
 ) -> anyhow::Result<()> {
     if signals.len() == 0 {
         // nothing to do
         return Ok(());
     }
 
-    let mut file = std::fs::File::create(filename)?;
+    let file = std::fs::File::create(filename)?;
+    let mut writer = BufWriter::new(&file);
 
     fn write_csv_row<I, T>(writer: &mut impl Write, items: I) -> std::io::Result<()>
     where
@@ -38,42 +201,39 @@ pub fn write_csv(
         Ok(())
     }
 
-    write_csv_row(&mut file, signals.iter().map(|signal| &signal.name))?;
+    let signal_samples = signal_samples(signals, samples, options);
 
-    // FIXME: currently we only support exporting a set of signals which share the same
-    //        time vector, but we may want to handle the case of different sampling times
-    //        (that arises, for instance, when sampling memory, or when the acquisition of
-    //        some signals is paused)
+    match options.layout {
+        ExportLayout::Tidy => {
+            write_csv_row(&mut writer, ["time", "signal", "value"].iter())?;
 
-    let signal_buffers: Vec<&SampleBuffer> = signals
-        .iter()
-        .filter_map(|signal| samples.get(&signal.id))
-        .collect();
-
-    if signal_buffers.len() != signals.len() {
-        anyhow::bail!("some of the signals requested for export have no buffer");
-    }
-
-    let n_samples = signal_buffers
-        .iter()
-        .map(|buffer| buffer.samples().len())
-        .min()
-        .context("internal error, should have had at least one signal at this point")?;
+            for (t, i, y) in tidy_rows(&signal_samples) {
+                writeln!(writer, "{},{},{}", t, signals[i].name, y)?;
+            }
+        }
+        ExportLayout::Aligned(alignment) => {
+            write_csv_row(
+                &mut writer,
+                std::iter::once("time").chain(signals.iter().map(|signal| signal.name.as_str())),
+            )?;
 
-    for i in 0..n_samples {
-        let t = signal_buffers[0].samples()[i].x;
+            let timeline = common_timeline(&signal_samples);
+            let columns: Vec<Vec<f64>> = signal_samples
+                .iter()
+                .map(|samples| align(samples, &timeline, alignment))
+                .collect();
 
-        // we assert here, in debug mode, that all the time values are equal
-        for buffer in &signal_buffers {
-            debug_assert_eq!(t, buffer.samples()[i].x);
+            for (i, t) in timeline.iter().enumerate() {
+                write_csv_row(
+                    &mut writer,
+                    std::iter::once(*t).chain(columns.iter().map(|column| column[i])),
+                )?;
+            }
         }
-
-        write_csv_row(
-            &mut file,
-            signal_buffers.iter().map(|buffer| buffer.samples()[i].y),
-        )?;
     }
 
+    writer.flush()?;
+    drop(writer);
     file.sync_all()?;
 
     Ok(())
@@ -83,6 +243,7 @@ pub fn write_npy(
     filename: &Path,
     signals: &[SignalConfig],
     samples: &HashMap<u32, SampleBuffer>,
+    options: &ExportOptions,
 ) -> anyhow::Result<()> {
     use npyz::WriterBuilder;
 
@@ -93,47 +254,669 @@ pub fn write_npy(
 
     let mut file = std::fs::File::create(filename)?;
 
-    // FIXME: currently we only support exporting a set of signals which share the same
-    //        time vector, but we may want to handle the case of different sampling times
-    //        (that arises, for instance, when sampling memory, or when the acquisition of
-    //        some signals is paused)
+    let signal_samples = signal_samples(signals, samples, options);
 
-    let signal_buffers: Vec<&SampleBuffer> = signals
-        .iter()
-        .filter_map(|signal| samples.get(&signal.id))
-        .collect();
+    match options.layout {
+        ExportLayout::Tidy => {
+            let rows = tidy_rows(&signal_samples);
+
+            let mut writer = npyz::WriteOptions::new()
+                .default_dtype()
+                .shape(&[rows.len() as u64, 3])
+                .writer(&mut file)
+                .begin_nd()?;
+
+            for (t, i, y) in rows {
+                writer.extend([t, i as f64, y])?;
+            }
+
+            writer.finish()?;
+        }
+        ExportLayout::Aligned(alignment) => {
+            let timeline = common_timeline(&signal_samples);
+            let columns: Vec<Vec<f64>> = signal_samples
+                .iter()
+                .map(|samples| align(samples, &timeline, alignment))
+                .collect();
+
+            let mut writer = npyz::WriteOptions::new()
+                .default_dtype()
+                .shape(&[timeline.len() as u64, 1 + columns.len() as u64])
+                .writer(&mut file)
+                .begin_nd()?;
+
+            for (i, t) in timeline.iter().enumerate() {
+                writer.push(t)?;
+                writer.extend(columns.iter().map(|column| column[i]))?;
+            }
 
-    if signal_buffers.len() != signals.len() {
-        anyhow::bail!("some of the signals requested for export have no buffer");
+            writer.finish()?;
+        }
     }
 
-    let n_samples = signal_buffers
-        .iter()
-        .map(|buffer| buffer.samples().len())
-        .min()
-        .context("internal error, should have had at least one signal at this point")?;
+    file.sync_all()?;
+
+    Ok(())
+}
+
+/// Columns of the exported table, with their names: in the tidy layout the time, the position
+/// of the signal and the value, otherwise the time and a column per signal.
+fn table_columns(
+    signals: &[SignalConfig],
+    samples: &HashMap<u32, SampleBuffer>,
+    options: &ExportOptions,
+) -> Vec<(String, Vec<f64>)> {
+    let signal_samples = signal_samples(signals, samples, options);
+
+    match options.layout {
+        ExportLayout::Tidy => {
+            let rows = tidy_rows(&signal_samples);
+
+            vec![
+                ("time".into(), rows.iter().map(|row| row.0).collect()),
+                (
+                    "signal".into(),
+                    rows.iter().map(|row| row.1 as f64).collect(),
+                ),
+                ("value".into(), rows.iter().map(|row| row.2).collect()),
+            ]
+        }
+        ExportLayout::Aligned(alignment) => {
+            let timeline = common_timeline(&signal_samples);
+
+            let columns = signals
+                .iter()
+                .zip(&signal_samples)
+                .map(|(signal, samples)| {
+                    (signal.name.clone(), align(samples, &timeline, alignment))
+                });
+
+            std::iter::once(("time".into(), timeline.clone()))
+                .chain(columns)
+                .collect()
+        }
+    }
+}
+
+/// Make the names unique, appending a suffix to the repeated ones, after making them valid
+/// with `sanitize`.
+fn unique_names<'a>(
+    names: impl Iterator<Item = &'a str>,
+    sanitize: impl Fn(&str) -> String,
+) -> Vec<String> {
+    let mut unique: Vec<String> = Vec::new();
+
+    for name in names {
+        let name = sanitize(name);
+
+        let mut candidate = name.clone();
+        let mut suffix = 1;
+        while unique.contains(&candidate) {
+            candidate = format!("{}_{}", name, suffix);
+            suffix += 1;
+        }
+
+        unique.push(candidate);
+    }
+
+    unique
+}
+
+/// NumPy archive, with an array for each column named after it.
+pub fn write_npz(
+    filename: &Path,
+    signals: &[SignalConfig],
+    samples: &HashMap<u32, SampleBuffer>,
+    options: &ExportOptions,
+) -> anyhow::Result<()> {
+    use npyz::WriterBuilder;
+
+    let columns = table_columns(signals, samples, options);
+    let names = unique_names(columns.iter().map(|(name, _)| name.as_str()), |name| {
+        name.replace('/', "_")
+    });
+
+    let mut npz = npyz::npz::NpzWriter::create(filename)?;
 
-    let mut writer = {
-        npyz::WriteOptions::new()
+    for (name, (_, values)) in names.iter().zip(&columns) {
+        let mut writer = npz
+            .array::<f64>(name, Default::default())?
             .default_dtype()
-            .shape(&[n_samples as u64, signal_buffers.len() as u64])
-            .writer(&mut file)
-            .begin_nd()?
-    };
+            .shape(&[values.len() as u64])
+            .begin_nd()?;
+
+        writer.extend(values.iter().copied())?;
+        writer.finish()?;
+    }
+
+    // the archive is completed when the writer is dropped
+    drop(npz);
 
-    for i in 0..n_samples {
-        let t = signal_buffers[0].samples()[i].x;
+    Ok(())
+}
+
+/// Arrow IPC file, with a column for each column of the table; the fields of the signals have
+/// their id and scale as metadata, the schema the exported range.
+pub fn write_arrow(
+    filename: &Path,
+    signals: &[SignalConfig],
+    samples: &HashMap<u32, SampleBuffer>,
+    options: &ExportOptions,
+) -> anyhow::Result<()> {
+    use arrow_array::{ArrayRef, Float64Array, RecordBatch};
+    use arrow_schema::{DataType, Field, Schema};
 
-        // we assert here, in debug mode, that all the time values are equal
-        for buffer in &signal_buffers {
-            debug_assert_eq!(t, buffer.samples()[i].x);
+    let columns = table_columns(signals, samples, options);
+    let names = unique_names(columns.iter().map(|(name, _)| name.as_str()), str::to_owned);
+
+    let fields = names.iter().enumerate().map(|(i, name)| {
+        let field = Field::new(name, DataType::Float64, true);
+
+        // in the aligned layout, the first column is the time, then a column per signal
+        match (
+            options.layout,
+            i.checked_sub(1).and_then(|i| signals.get(i)),
+        ) {
+            (ExportLayout::Aligned(_), Some(signal)) => field.with_metadata(HashMap::from([
+                ("signal_id".to_owned(), signal.id.to_string()),
+                ("scale".to_owned(), signal.scale.to_string()),
+            ])),
+            _ => field,
         }
+    });
 
-        writer.extend(signal_buffers.iter().map(|buffer| buffer.samples()[i].y))?;
+    let mut metadata = HashMap::from([
+        ("layout".to_owned(), format!("{:?}", options.layout)),
+        ("time_unit".to_owned(), "s".to_owned()),
+    ]);
+    if let Some((from_t, to_t)) = options.range {
+        metadata.insert("range".to_owned(), format!("{},{}", from_t, to_t));
     }
+    if options.layout == ExportLayout::Tidy {
+        let signal_names = signals.iter().map(|signal| signal.name.as_str());
+        metadata.insert(
+            "signals".to_owned(),
+            signal_names.collect::<Vec<_>>().join(","),
+        );
+    }
+
+    let schema = std::sync::Arc::new(Schema::new_with_metadata(
+        fields.collect::<Vec<_>>(),
+        metadata,
+    ));
 
+    let arrays = columns
+        .into_iter()
+        .map(|(_, values)| std::sync::Arc::new(Float64Array::from(values)) as ArrayRef)
+        .collect();
+    let batch = RecordBatch::try_new(schema.clone(), arrays)?;
+
+    let file = std::fs::File::create(filename)?;
+    let mut writer = arrow_ipc::writer::FileWriter::try_new(BufWriter::new(file), &schema)?;
+    writer.write(&batch)?;
     writer.finish()?;
+
+    Ok(())
+}
+
+const MAT_INT8: u32 = 1;
+const MAT_INT32: u32 = 5;
+const MAT_UINT32: u32 = 6;
+const MAT_DOUBLE: u32 = 9;
+const MAT_MATRIX: u32 = 14;
+const MAT_DOUBLE_CLASS: u32 = 6;
+const MAT_MAX_NAME_LENGTH: usize = 63;
+
+/// Append a MAT-file data element, padded to 8 bytes; fails if the contents are larger than the
+/// 4 GiB its size can tell.
+fn put_mat_element(data: &mut Vec<u8>, data_type: u32, contents: &[u8]) -> anyhow::Result<()> {
+    let Ok(size) = u32::try_from(contents.len()) else {
+        anyhow::bail!(
+            "{} bytes are too many for a MAT-file variable, export a shorter range",
+            contents.len()
+        );
+    };
+
+    data.extend(data_type.to_le_bytes());
+    data.extend(size.to_le_bytes());
+    data.extend(contents);
+    data.resize(data.len().next_multiple_of(8), 0);
+
+    Ok(())
+}
+
+/// Valid MATLAB variable name: starting with a letter, with only letters, digits and
+/// underscores.
+fn mat_variable_name(name: &str) -> String {
+    let mut variable: String = name
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() {
+            true => c,
+            false => '_',
+        })
+        .collect();
+
+    if !variable.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        variable.insert_str(0, "s_");
+    }
+    variable.truncate(MAT_MAX_NAME_LENGTH - 4);
+
+    variable
+}
+
+/// MATLAB level 5 MAT-file, with a double column vector for each column of the table.
+pub fn write_mat(
+    filename: &Path,
+    signals: &[SignalConfig],
+    samples: &HashMap<u32, SampleBuffer>,
+    options: &ExportOptions,
+) -> anyhow::Result<()> {
+    let columns = table_columns(signals, samples, options);
+    let names = unique_names(
+        columns.iter().map(|(name, _)| name.as_str()),
+        mat_variable_name,
+    );
+
+    let file = std::fs::File::create(filename)?;
+    let mut writer = BufWriter::new(&file);
+
+    let mut header = b"MATLAB 5.0 MAT-file, created by OCDScope".to_vec();
+    header.resize(116, b' ');
+    header.extend([0; 8]); // no subsystem data
+    header.extend(0x0100u16.to_le_bytes());
+    header.extend(b"IM");
+    writer.write_all(&header)?;
+
+    for (name, (_, values)) in names.iter().zip(&columns) {
+        let mut flags = Vec::new();
+        flags.extend(MAT_DOUBLE_CLASS.to_le_bytes());
+        flags.extend(0u32.to_le_bytes());
+
+        let mut dimensions = Vec::new();
+        let Ok(rows) = i32::try_from(values.len()) else {
+            anyhow::bail!("{} rows are too many for a MAT-file variable", values.len());
+        };
+        dimensions.extend(rows.to_le_bytes());
+        dimensions.extend(1i32.to_le_bytes());
+
+        let real: Vec<u8> = values.iter().flat_map(|y| y.to_le_bytes()).collect();
+
+        let mut matrix = Vec::new();
+        put_mat_element(&mut matrix, MAT_UINT32, &flags)?;
+        put_mat_element(&mut matrix, MAT_INT32, &dimensions)?;
+        put_mat_element(&mut matrix, MAT_INT8, name.as_bytes())?;
+        put_mat_element(&mut matrix, MAT_DOUBLE, &real)?;
+
+        let mut element = Vec::with_capacity(matrix.len() + 8);
+        put_mat_element(&mut element, MAT_MATRIX, &matrix)?;
+        writer.write_all(&element)?;
+    }
+
+    writer.flush()?;
+    drop(writer);
+    file.sync_all()?;
+
+    Ok(())
+}
+
+/// WAV file, with a 32 bit float channel per signal. The signals are resampled at a constant
+/// rate, the one of the median interval between the samples, computing their values as the
+/// alignment says (linearly interpolating, in the tidy layout); NaN values become zeros.
+pub fn write_wav(
+    filename: &Path,
+    signals: &[SignalConfig],
+    samples: &HashMap<u32, SampleBuffer>,
+    options: &ExportOptions,
+) -> anyhow::Result<()> {
+    let alignment = match options.layout {
+        ExportLayout::Aligned(alignment) => alignment,
+        ExportLayout::Tidy => Alignment::Linear,
+    };
+
+    let signal_samples = signal_samples(signals, samples, options);
+    let timeline = common_timeline(&signal_samples);
+
+    let mut intervals: Vec<f64> = timeline.windows(2).map(|t| t[1] - t[0]).collect();
+    if intervals.is_empty() {
+        anyhow::bail!("at least two samples are needed to export a WAV file");
+    }
+    let middle = intervals.len() / 2;
+    let (_, median_interval, _) = intervals.select_nth_unstable_by(middle, f64::total_cmp);
+    let rate = (1.0 / *median_interval).round().max(1.0);
+    if rate > u32::MAX as f64 {
+        anyhow::bail!("sample rate too high for a WAV file");
+    }
+
+    // a WAV file is at most 4 GiB, which long pauses in the sampling can easily exceed
+    let t0 = timeline[0];
+    let frames = ((timeline[timeline.len() - 1] - t0) * rate).floor() + 1.0;
+    let block_align = u16::try_from(4 * signal_samples.len())
+        .map_err(|_| anyhow::anyhow!("too many signals for a WAV file"))?;
+    let data_size = Some(frames)
+        .filter(|&frames| frames <= u32::MAX as f64)
+        .and_then(|frames| (frames as u32).checked_mul(block_align as u32))
+        .filter(|size| size.checked_add(4 + (8 + 18) + (8 + 4) + 8).is_some());
+    let Some(data_size) = data_size else {
+        anyhow::bail!(
+            "{frames} frames at {rate} Hz are too many for a WAV file, export a shorter range"
+        );
+    };
+    let Some(byte_rate) = (rate as u32).checked_mul(block_align as u32) else {
+        anyhow::bail!("sample rate too high for a WAV file");
+    };
+
+    let n_frames = frames as usize;
+    let frame_times: Vec<f64> = (0..n_frames).map(|i| t0 + i as f64 / rate).collect();
+
+    let channels: Vec<Vec<f64>> = signal_samples
+        .iter()
+        .map(|samples| align(samples, &frame_times, alignment))
+        .collect();
+
+    let n_channels = channels.len() as u16;
+    let rate = rate as u32;
+
+    let file = std::fs::File::create(filename)?;
+    let mut writer = BufWriter::new(&file);
+
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&(4 + (8 + 18) + (8 + 4) + (8 + data_size)).to_le_bytes())?;
+    writer.write_all(b"WAVE")?;
+
+    // format chunk, of IEEE float samples
+    writer.write_all(b"fmt ")?;
+    writer.write_all(&18u32.to_le_bytes())?;
+    writer.write_all(&3u16.to_le_bytes())?;
+    writer.write_all(&n_channels.to_le_bytes())?;
+    writer.write_all(&rate.to_le_bytes())?;
+    writer.write_all(&byte_rate.to_le_bytes())?;
+    writer.write_all(&block_align.to_le_bytes())?;
+    writer.write_all(&32u16.to_le_bytes())?;
+    writer.write_all(&0u16.to_le_bytes())?;
+
+    writer.write_all(b"fact")?;
+    writer.write_all(&4u32.to_le_bytes())?;
+    writer.write_all(&(n_frames as u32).to_le_bytes())?;
+
+    writer.write_all(b"data")?;
+    writer.write_all(&data_size.to_le_bytes())?;
+    for i in 0..n_frames {
+        for channel in &channels {
+            let y = match channel[i] {
+                y if y.is_nan() => 0.0,
+                y => y as f32,
+            };
+            writer.write_all(&y.to_le_bytes())?;
+        }
+    }
+
+    writer.flush()?;
+    drop(writer);
     file.sync_all()?;
 
     Ok(())
 }
+
+/// Export the samples to `filename`, in the given format.
+pub fn write(
+    format: ExportFormat,
+    filename: &Path,
+    signals: &[SignalConfig],
+    samples: &HashMap<u32, SampleBuffer>,
+    options: &ExportOptions,
+) -> anyhow::Result<()> {
+    match format {
+        ExportFormat::Csv => write_csv(filename, signals, samples, options),
+        ExportFormat::Npy => write_npy(filename, signals, samples, options),
+        ExportFormat::Npz => write_npz(filename, signals, samples, options),
+        ExportFormat::ArrowIpc => write_arrow(filename, signals, samples, options),
+        ExportFormat::Mat => write_mat(filename, signals, samples, options),
+        ExportFormat::Wav => write_wav(filename, signals, samples, options),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::temporary_path;
+
+    fn all(layout: ExportLayout) -> ExportOptions {
+        ExportOptions {
+            layout,
+            range: None,
+            decimation: 1,
+        }
+    }
+
+    fn points(points: &[(f64, f64)]) -> Vec<PlotPoint> {
+        points.iter().map(|&(x, y)| PlotPoint::new(x, y)).collect()
+    }
+
+    /// Compare the values, with NaN equal to itself.
+    fn assert_values(values: &[f64], expected: &[f64]) {
+        assert_eq!(values.len(), expected.len());
+        for (value, expected) in values.iter().zip(expected) {
+            assert!(
+                value == expected || (value.is_nan() && expected.is_nan()),
+                "{:?} != {:?}",
+                values,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_align() {
+        let a = points(&[(1.0, 10.0), (3.0, 30.0)]);
+        let b = points(&[(0.0, 0.0), (2.0, 1.0), (2.5, 2.0), (4.0, 4.0)]);
+
+        let timeline = common_timeline(&[&a, &b]);
+        assert_eq!(timeline, [0.0, 1.0, 2.0, 2.5, 3.0, 4.0]);
+
+        let nan = f64::NAN;
+        assert_values(
+            &align(&a, &timeline, Alignment::Linear),
+            &[nan, 10.0, 20.0, 25.0, 30.0, nan],
+        );
+        assert_values(
+            &align(&a, &timeline, Alignment::Hold),
+            &[nan, 10.0, 10.0, 10.0, 30.0, 30.0],
+        );
+        assert_values(
+            &align(&a, &timeline, Alignment::Nearest),
+            &[10.0, 10.0, 10.0, 30.0, 30.0, 30.0],
+        );
+
+        // a signal sampled at all the times is left alone
+        for alignment in [Alignment::Linear, Alignment::Hold, Alignment::Nearest] {
+            assert_values(
+                &align(&b, &common_timeline(&[&b]), alignment),
+                &[0.0, 1.0, 2.0, 4.0],
+            );
+        }
+
+        assert_values(&align(&[], &timeline[..2], Alignment::Nearest), &[nan, nan]);
+    }
+
+    #[test]
+    fn test_tidy_rows() {
+        let a = points(&[(1.0, 10.0), (3.0, 30.0)]);
+        let b = points(&[(0.0, 0.0), (1.0, 1.0)]);
+
+        assert_eq!(
+            tidy_rows(&[&a, &b]),
+            [(0.0, 1, 0.0), (1.0, 0, 10.0), (1.0, 1, 1.0), (3.0, 0, 30.0)]
+        );
+    }
+
+    fn test_signals() -> (Vec<SignalConfig>, HashMap<u32, SampleBuffer>) {
+        let signals = vec![
+            SignalConfig::new(3, "a".into(), None),
+            SignalConfig::new(5, "b".into(), None),
+            SignalConfig::new(7, "not sampled".into(), None),
+        ];
+
+        let mut samples = HashMap::new();
+        let mut a = SampleBuffer::new();
+        a.push(0.0, 1.0);
+        a.push(1.0, 2.0);
+        samples.insert(3, a);
+        let mut b = SampleBuffer::new();
+        b.push(0.5, -1.0);
+        samples.insert(5, b);
+
+        (signals, samples)
+    }
+
+    #[test]
+    fn test_write_csv() {
+        let (signals, samples) = test_signals();
+        let filename = temporary_path("export", "csv");
+
+        write_csv(&filename, &signals, &samples, &all(ExportLayout::Tidy)).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&filename).unwrap(),
+            "time,signal,value\n0,a,1\n0.5,b,-1\n1,a,2\n"
+        );
+
+        write_csv(
+            &filename,
+            &signals,
+            &samples,
+            &all(ExportLayout::Aligned(Alignment::Hold)),
+        )
+        .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&filename).unwrap(),
+            "time,a,b,not sampled\n0,1,NaN,NaN\n0.5,1,-1,NaN\n1,2,-1,NaN\n"
+        );
+
+        // the range is inclusive, and decimation is applied to each signal after it
+        let options = ExportOptions {
+            layout: ExportLayout::Tidy,
+            range: Some((0.5, 1.0)),
+            decimation: 2,
+        };
+        write_csv(&filename, &signals[..1], &samples, &options).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&filename).unwrap(),
+            "time,signal,value\n1,a,2\n"
+        );
+
+        std::fs::remove_file(&filename).unwrap();
+    }
+
+    #[test]
+    fn test_write_npz_and_arrow() {
+        let (signals, samples) = test_signals();
+        let options = all(ExportLayout::Aligned(Alignment::Linear));
+
+        let filename = temporary_path("export", "npz");
+        write_npz(&filename, &signals, &samples, &options).unwrap();
+
+        let mut npz = npyz::npz::NpzArchive::open(&filename).unwrap();
+        let mut names: Vec<_> = npz.array_names().map(str::to_owned).collect();
+        names.sort();
+        assert_eq!(names, ["a", "b", "not sampled", "time"]);
+
+        let time: Vec<f64> = npz.by_name("time").unwrap().unwrap().into_vec().unwrap();
+        assert_eq!(time, [0.0, 0.5, 1.0]);
+        let a: Vec<f64> = npz.by_name("a").unwrap().unwrap().into_vec().unwrap();
+        assert_eq!(a, [1.0, 1.5, 2.0]);
+        std::fs::remove_file(&filename).unwrap();
+
+        let filename = temporary_path("export", "arrow");
+        write_arrow(&filename, &signals, &samples, &options).unwrap();
+
+        let file = std::fs::File::open(&filename).unwrap();
+        let reader = arrow_ipc::reader::FileReader::try_new(file, None).unwrap();
+        let schema = reader.schema();
+        assert_eq!(schema.fields().len(), 4);
+        assert_eq!(schema.field(1).name(), "a");
+        assert_eq!(schema.field(2).metadata()["signal_id"], "5");
+
+        let batches: Vec<_> = reader.map(Result::unwrap).collect();
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].num_rows(), 3);
+        std::fs::remove_file(&filename).unwrap();
+    }
+
+    #[test]
+    fn test_write_mat() {
+        let (signals, samples) = test_signals();
+
+        let filename = temporary_path("export", "mat");
+        write_mat(&filename, &signals, &samples, &all(ExportLayout::Tidy)).unwrap();
+        let contents = std::fs::read(&filename).unwrap();
+        std::fs::remove_file(&filename).unwrap();
+
+        assert!(contents.starts_with(b"MATLAB 5.0 MAT-file"));
+        assert_eq!(&contents[126..128], b"IM");
+
+        // the first variable, the time column with 3 rows
+        let element = &contents[128..];
+        let u32_at = |i: usize| u32::from_le_bytes(element[i..i + 4].try_into().unwrap());
+        assert_eq!(u32_at(0), MAT_MATRIX);
+        assert_eq!(u32_at(4) as usize, 16 + 16 + 16 + 8 + 24);
+        assert_eq!((u32_at(32), u32_at(36)), (3, 1));
+        assert_eq!(&element[48..52], b"time");
+        assert_eq!(u32_at(56), MAT_DOUBLE);
+        assert_eq!(f64::from_le_bytes(element[72..80].try_into().unwrap()), 0.5);
+
+        assert_eq!(mat_variable_name("not sampled"), "not_sampled");
+        assert_eq!(mat_variable_name("0x2000"), "s_0x2000");
+        assert_eq!(
+            unique_names(["a b", "a_b", "a-b"].into_iter(), mat_variable_name),
+            ["a_b", "a_b_1", "a_b_2"]
+        );
+    }
+
+    #[test]
+    fn test_write_wav() {
+        let signals = vec![SignalConfig::new(0, "a".into(), None)];
+
+        // sampled at 1 kHz, with a sample missing
+        let mut buffer = SampleBuffer::new();
+        for i in (0..100).filter(|&i| i != 50) {
+            buffer.push(i as f64 * 1e-3, i as f64);
+        }
+        let samples = HashMap::from([(0, buffer)]);
+
+        let filename = temporary_path("export", "wav");
+        write_wav(&filename, &signals, &samples, &all(ExportLayout::Tidy)).unwrap();
+        let contents = std::fs::read(&filename).unwrap();
+        std::fs::remove_file(&filename).unwrap();
+
+        let u32_at = |i: usize| u32::from_le_bytes(contents[i..i + 4].try_into().unwrap());
+        assert_eq!(&contents[..4], b"RIFF");
+        assert_eq!(u32_at(4) as usize, contents.len() - 8);
+        assert_eq!(&contents[8..16], b"WAVEfmt ");
+        assert_eq!(u32_at(24), 1000);
+        assert_eq!(&contents[38..42], b"fact");
+        assert_eq!(&contents[50..54], b"data");
+        assert_eq!(u32_at(54), 100 * 4);
+
+        // the missing sample is interpolated
+        let value_at =
+            |i: usize| f32::from_le_bytes(contents[58 + 4 * i..62 + 4 * i].try_into().unwrap());
+        assert_eq!(value_at(50), 50.0);
+        assert_eq!(value_at(99), 99.0);
+    }
+
+    #[test]
+    fn test_write_wav_too_long() {
+        let signals = vec![SignalConfig::new(0, "a".into(), None)];
+
+        // at 1 kHz, then a pause of more than 4 GiB worth of frames
+        let mut buffer = SampleBuffer::new();
+        for t in [0.0, 1e-3, 2e-3, 1.2e6] {
+            buffer.push(t, 1.0);
+        }
+        let samples = HashMap::from([(0, buffer)]);
+
+        let filename = temporary_path("export-long", "wav");
+        let error = write_wav(&filename, &signals, &samples, &all(ExportLayout::Tidy)).unwrap_err();
+        assert!(error.to_string().contains("too many"), "{error}");
+        assert!(!filename.exists());
+    }
+}
@@ -1,12 +1,28 @@
+use std::collections::VecDeque;
 use std::net::ToSocketAddrs;
 use std::time::{Duration, Instant};
 
 use thiserror::Error;
 
-use crate::ttstream::{TimestampedTcpStream, Timestamp};
+use crate::ttstream::{Timestamp, TimestampedTcpStream};
+
+#[cfg(test)]
+pub mod mock_stub;
 
 const DEFAULT_TIMEOUT: Duration = Duration::from_millis(200);
 const MAX_PACKET_SIZE: usize = 1024;
+/// Packets with longer contents are considered corrupted, to bound the memory used when the
+/// final `#` goes missing.
+const MAX_PACKET_CONTENTS_SIZE: usize = 64 * 1024;
+/// Number of times a packet is sent again after the server answered with a NAK.
+const MAX_RETRANSMISSIONS: usize = 3;
+/// Longest wait for data when polling for asynchronous events.
+const POLL_TIMEOUT: Duration = Duration::from_millis(1);
+/// Asynchronous events kept around until someone takes them; older ones get dropped.
+const MAX_ASYNC_EVENTS: usize = 1024;
+/// Packet size assumed when the server doesn't advertise one in its `qSupported` reply, the
+/// same default GDB uses
+const DEFAULT_PACKET_SIZE: usize = 400;
 
 pub type Result<T> = std::result::Result<T, GDBRemoteError>;
 
@@ -20,73 +36,467 @@ pub enum GDBRemoteError {
     Timeout,
     #[error("End of stream")]
     EndOfStream,
+    #[error("Unexpected response: {0}")]
+    UnexpectedResponse(String),
+    #[error("Error response: {0}")]
+    ErrorResponse(String),
+    #[error("Packet rejected after {0} retransmissions")]
+    RetransmissionsExhausted(usize),
 }
 
-fn build_gdb_packet(data: &str) -> Vec<u8> {
-    let bytes = data.as_bytes();
+/// Flavor of the GDB remote server on the other end of the connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GDBServerKind {
+    /// OpenOCD, which sends an ACK as soon as the connection is established and always
+    /// supports the no-acknowledgment mode.
+    OpenOCD,
+    /// Any other server speaking the GDB remote protocol, e.g. pyOCD, probe-rs, Black Magic
+    /// Probe, J-Link GDB Server or QEMU.
+    Generic,
+}
+
+/// Features advertised by the server in its reply to `qSupported`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ServerFeatures {
+    pub packet_size: Option<usize>,
+    pub no_ack_mode: bool,
+    /// Memory can be read in binary form with the `x` packet.
+    pub binary_upload: bool,
+    /// The target description can be read with `qXfer:features:read`.
+    pub target_description: bool,
+}
+
+fn parse_supported_features(reply: &[u8]) -> ServerFeatures {
+    let mut features = ServerFeatures::default();
+
+    for feature in String::from_utf8_lossy(reply).split(';') {
+        if let Some(value) = feature.strip_prefix("PacketSize=") {
+            // the value is in hex, and at least QEMU sends it without any prefix
+            features.packet_size = usize::from_str_radix(value, 16).ok();
+        } else if feature == "QStartNoAckMode+" {
+            features.no_ack_mode = true;
+        } else if feature == "binary-upload+" {
+            features.binary_upload = true;
+        } else if feature == "qXfer:features:read+" {
+            features.target_description = true;
+        }
+    }
+
+    features
+}
+
+/// Escape character of the binary data representation, see
+/// <https://sourceware.org/gdb/current/onlinedocs/gdb.html/Overview.html>.
+const ESCAPE_CHAR: u8 = b'}';
+/// Marker of the run-length encoding of the previous character.
+const RUN_LENGTH_CHAR: u8 = b'*';
+/// Offset subtracted from the character following [`RUN_LENGTH_CHAR`] to get the repeat count.
+const RUN_LENGTH_OFFSET: u8 = 29;
+/// Byte sent on its own, out of any packet, to halt a running target.
+const INTERRUPT_CHAR: u8 = 0x03;
+
+fn escape_binary(data: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(data.len());
+
+    for &b in data {
+        if [b'#', b'$', ESCAPE_CHAR, RUN_LENGTH_CHAR].contains(&b) {
+            result.push(ESCAPE_CHAR);
+            result.push(b ^ 0x20);
+        } else {
+            result.push(b);
+        }
+    }
+
+    result
+}
+
+fn unescape_binary(data: &[u8]) -> Result<Vec<u8>> {
+    let mut result = Vec::with_capacity(data.len());
+    let mut bytes = data.iter();
+
+    while let Some(&b) = bytes.next() {
+        if b == ESCAPE_CHAR {
+            let &escaped = bytes.next().ok_or(GDBRemoteError::ParseError(
+                "dangling escape character".into(),
+            ))?;
+            result.push(escaped ^ 0x20);
+        } else {
+            result.push(b);
+        }
+    }
+
+    Ok(result)
+}
+
+/// Expand the run-length encoded sequences of the packet contents; escaped characters are
+/// left as they are, and unescaped separately only for binary packets, like GDB does.
+fn expand_run_lengths(contents: &[u8]) -> Result<Vec<u8>> {
+    let mut result = Vec::with_capacity(contents.len());
+    let mut i = 0;
+
+    while i < contents.len() {
+        match contents[i] {
+            ESCAPE_CHAR if i + 1 < contents.len() => {
+                result.extend_from_slice(&contents[i..i + 2]);
+                i += 2;
+            }
+            RUN_LENGTH_CHAR => {
+                let &repeated = result.last().ok_or(GDBRemoteError::ParseError(
+                    "run-length encoding without a previous character".into(),
+                ))?;
+                let count = contents
+                    .get(i + 1)
+                    .and_then(|c| c.checked_sub(RUN_LENGTH_OFFSET))
+                    .ok_or(GDBRemoteError::ParseError(
+                        "invalid run-length count".into(),
+                    ))?;
 
-    let checksum: u8 = bytes.iter().fold(0x00, |c, &b| c.wrapping_add(b));
+                result.extend(std::iter::repeat_n(repeated, count as usize));
+                i += 2;
+            }
+            b => {
+                result.push(b);
+                i += 1;
+            }
+        }
+    }
 
-    let mut result = Vec::with_capacity(data.len() + 4);
+    Ok(result)
+}
+
+/// Frame already encoded `contents` as a packet, adding the start marker and the checksum.
+fn frame_gdb_packet(contents: &[u8]) -> Vec<u8> {
+    let checksum: u8 = contents.iter().fold(0x00, |c, &b| c.wrapping_add(b));
+
+    let mut result = Vec::with_capacity(contents.len() + 4);
 
     result.push(b'$');
-    result.extend_from_slice(bytes);
+    result.extend_from_slice(contents);
     result.push(b'#');
     result.extend_from_slice(format!("{:02x}", checksum).as_bytes());
 
     result
 }
 
-fn parse_gdb_packet(bytes: &[u8]) -> Result<&[u8]> {
-    if bytes.len() < 4 {
-        return Err(GDBRemoteError::ParseError("packet too short".into()));
+fn build_gdb_packet(data: &str) -> Vec<u8> {
+    frame_gdb_packet(&escape_binary(data.as_bytes()))
+}
+
+/// Item recognized by the [`StreamParser`] in the data received from the server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum StreamItem {
+    Ack,
+    Nak,
+    /// Packet (`$...#xx`), with the run-length encoding expanded.
+    Packet(Vec<u8>),
+    /// Notification (`%...#xx`), with the run-length encoding expanded.
+    Notification(Vec<u8>),
+    /// Packet or notification that was malformed or failed the checksum verification.
+    Corrupted(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ParserState {
+    /// Between packets, waiting for an ACK, a NAK or the start of a packet; anything else
+    /// is garbage and gets discarded.
+    Idle,
+    Contents,
+    /// The previous byte was an escape or a run-length marker, so this one is part of the
+    /// contents whatever its value.
+    ContentsLiteral,
+    ChecksumHigh,
+    ChecksumLow(u8),
+}
+
+/// Incremental parser of the data received from the server, doing a constant amount of work
+/// for each byte; the buffer of the packet contents is reused from one packet to the next.
+struct StreamParser {
+    state: ParserState,
+    notification: bool,
+    contents: Vec<u8>,
+    checksum: u8,
+    discarded: usize,
+}
+
+impl StreamParser {
+    fn new() -> StreamParser {
+        StreamParser {
+            state: ParserState::Idle,
+            notification: false,
+            contents: Vec::with_capacity(MAX_PACKET_SIZE),
+            checksum: 0,
+            discarded: 0,
+        }
     }
 
-    if bytes[0] != b'$' {
-        return Err(GDBRemoteError::ParseError("missing initial $".into()));
+    fn is_idle(&self) -> bool {
+        self.state == ParserState::Idle
     }
 
-    // TODO: we may need to handle escaping
+    fn start(&mut self, notification: bool) {
+        if self.discarded > 0 {
+            log::warn!("discarded {} bytes of garbage", self.discarded);
+            self.discarded = 0;
+        }
 
-    let pound_i = bytes
-        .iter()
-        .position(|&b| b == b'#')
-        .ok_or(GDBRemoteError::ParseError("no final # found".into()))?;
+        self.state = ParserState::Contents;
+        self.notification = notification;
+        self.contents.clear();
+        self.checksum = 0;
+    }
 
-    if bytes.len() < pound_i + 3 {
-        return Err(GDBRemoteError::ParseError(
-            "packet too short (can't hold checksum)".into(),
-        ));
+    fn corrupted(&mut self, reason: String) -> Option<StreamItem> {
+        self.state = ParserState::Idle;
+        Some(StreamItem::Corrupted(reason))
+    }
+
+    fn feed(&mut self, byte: u8) -> Option<StreamItem> {
+        match self.state {
+            ParserState::Idle => match byte {
+                b'+' => return Some(StreamItem::Ack),
+                b'-' => return Some(StreamItem::Nak),
+                b'$' => self.start(false),
+                b'%' => self.start(true),
+                _ => self.discarded += 1,
+            },
+            // a start marker can't appear unescaped in the contents, the packet we were parsing
+            // must have been truncated: drop it and start over with the new one
+            ParserState::Contents
+            | ParserState::ContentsLiteral
+            | ParserState::ChecksumHigh
+            | ParserState::ChecksumLow(_)
+                if byte == b'$' =>
+            {
+                log::warn!("packet truncated by the start of another one");
+                self.start(false);
+            }
+            ParserState::Contents | ParserState::ContentsLiteral
+                if self.contents.len() >= MAX_PACKET_CONTENTS_SIZE =>
+            {
+                return self.corrupted(String::from("packet too long"));
+            }
+            ParserState::Contents => match byte {
+                b'#' => self.state = ParserState::ChecksumHigh,
+                _ => {
+                    if byte == ESCAPE_CHAR || byte == RUN_LENGTH_CHAR {
+                        self.state = ParserState::ContentsLiteral;
+                    }
+                    self.contents.push(byte);
+                    self.checksum = self.checksum.wrapping_add(byte);
+                }
+            },
+            ParserState::ContentsLiteral => {
+                self.state = ParserState::Contents;
+                self.contents.push(byte);
+                self.checksum = self.checksum.wrapping_add(byte);
+            }
+            ParserState::ChecksumHigh => match (byte as char).to_digit(16) {
+                Some(digit) => self.state = ParserState::ChecksumLow(digit as u8),
+                None => return self.corrupted(String::from("invalid checksum digit")),
+            },
+            ParserState::ChecksumLow(high) => {
+                let Some(low) = (byte as char).to_digit(16) else {
+                    return self.corrupted(String::from("invalid checksum digit"));
+                };
+
+                self.state = ParserState::Idle;
+
+                let packet_checksum = high << 4 | low as u8;
+                if packet_checksum != self.checksum {
+                    return self.corrupted(format!(
+                        "checksum didn't match, computed {:02x} but expected {:02x}",
+                        self.checksum, packet_checksum
+                    ));
+                }
+
+                return match expand_run_lengths(&self.contents) {
+                    Ok(contents) if self.notification => Some(StreamItem::Notification(contents)),
+                    Ok(contents) => Some(StreamItem::Packet(contents)),
+                    Err(err) => self.corrupted(err.to_string()),
+                };
+            }
+        }
+
+        None
+    }
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn decode_hex(data: &[u8]) -> Option<Vec<u8>> {
+    if !data.len().is_multiple_of(2) {
+        return None;
+    }
+
+    data.chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect()
+}
+
+/// Kind of access that triggers a watchpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchpointKind {
+    Write,
+    Read,
+    Access,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopKind {
+    /// Stopped because of a signal (`S` and `T` replies).
+    Signal(u8),
+    /// Exited with the given status (`W` reply).
+    Exited(u8),
+    /// Terminated by a signal (`X` reply).
+    Terminated(u8),
+}
+
+/// Stop reply, see <https://sourceware.org/gdb/current/onlinedocs/gdb.html/Stop-Reply-Packets.html>.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StopReply {
+    pub kind: StopKind,
+    pub pc: Option<u32>,
+    pub watchpoint: Option<(WatchpointKind, u32)>,
+    pub breakpoint: bool,
+}
+
+impl std::fmt::Display for StopReply {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.kind {
+            StopKind::Signal(signal) => match signal_name(signal) {
+                Some(name) => write!(f, "stopped by {}", name)?,
+                None => write!(f, "stopped by signal {}", signal)?,
+            },
+            StopKind::Exited(status) => write!(f, "exited with status {}", status)?,
+            StopKind::Terminated(signal) => write!(f, "terminated by signal {}", signal)?,
+        }
+
+        if let Some((kind, address)) = self.watchpoint {
+            write!(f, ", {:?} watchpoint hit at 0x{:08X}", kind, address)?;
+        }
+        if self.breakpoint {
+            write!(f, ", breakpoint hit")?;
+        }
+        if let Some(pc) = self.pc {
+            write!(f, ", PC 0x{:08X}", pc)?;
+        }
+
+        Ok(())
     }
+}
+
+/// Names of the most common GDB signal numbers, which don't necessarily match the host ones.
+fn signal_name(signal: u8) -> Option<&'static str> {
+    match signal {
+        2 => Some("SIGINT"),
+        4 => Some("SIGILL"),
+        5 => Some("SIGTRAP"),
+        6 => Some("SIGABRT"),
+        8 => Some("SIGFPE"),
+        10 => Some("SIGBUS"),
+        11 => Some("SIGSEGV"),
+        _ => None,
+    }
+}
+
+/// Register number of the program counter of ARM targets, in the `T` stop replies.
+const ARM_PC_REGISTER: u32 = 0x0f;
+
+/// Register number of the program counter of the target described by `description`, the XML
+/// target description; only the ARM one is known, the number depends on the architecture.
+fn pc_register(description: &str) -> Option<u32> {
+    let architecture = description
+        .split_once("<architecture>")?
+        .1
+        .split_once("</architecture>")?
+        .0
+        .trim();
 
-    let contents = &bytes[1..pound_i];
+    // "arm", or a variant like "armv7e-m", but not "aarch64"
+    architecture.starts_with("arm").then_some(ARM_PC_REGISTER)
+}
+
+/// Parse a stop reply, reading the program counter from the register `pc_register`, if known.
+fn parse_stop_reply(data: &[u8], pc_register: Option<u32>) -> Option<StopReply> {
+    let (&kind, rest) = data.split_first()?;
+    let text = std::str::from_utf8(rest).ok()?;
+    let number = u8::from_str_radix(text.get(..2)?, 16).ok()?;
+
+    let kind = match kind {
+        b'S' | b'T' => StopKind::Signal(number),
+        b'W' => StopKind::Exited(number),
+        b'X' => StopKind::Terminated(number),
+        _ => return None,
+    };
 
-    let contents_checksum: u8 = contents.iter().fold(0x00, |c, &b| c.wrapping_add(b));
+    let mut reply = StopReply {
+        kind,
+        pc: None,
+        watchpoint: None,
+        breakpoint: false,
+    };
 
-    let packet_checksum_string = String::from_utf8(bytes[pound_i + 1..pound_i + 3].to_vec())
-        .map_err(|_| {
-            GDBRemoteError::ParseError("couldn't parse checksum as UTF-8 string".into())
-        })?;
+    if data[0] != b'T' {
+        return Some(reply);
+    }
+
+    for pair in text[2..].split(';').filter(|pair| !pair.is_empty()) {
+        let (name, value) = pair.split_once(':').unwrap_or((pair, ""));
 
-    let packet_checksum: u8 = u8::from_str_radix(&packet_checksum_string, 16)
-        .map_err(|_| GDBRemoteError::ParseError("couldn't parse checksum as hex string".into()))?;
+        let watchpoint_kind = match name {
+            "watch" => Some(WatchpointKind::Write),
+            "rwatch" => Some(WatchpointKind::Read),
+            "awatch" => Some(WatchpointKind::Access),
+            _ => None,
+        };
 
-    if contents_checksum != packet_checksum {
-        return Err(GDBRemoteError::ParseError(format!(
-            "checksum didn't match, computed {:02x} but expected {:02x}",
-            contents_checksum, packet_checksum
-        )));
+        if let Some(watchpoint_kind) = watchpoint_kind {
+            let address = u64::from_str_radix(value, 16).ok()?;
+            reply.watchpoint = Some((watchpoint_kind, address as u32));
+        } else if name == "swbreak" || name == "hwbreak" {
+            reply.breakpoint = true;
+        } else if pc_register.is_some() && u32::from_str_radix(name, 16).ok() == pc_register {
+            // register values are in target byte order, little endian on ARM
+            let bytes = decode_hex(value.as_bytes())?;
+            reply.pc = Some(u32::from_le_bytes(bytes.get(..4)?.try_into().ok()?));
+        }
     }
 
-    Ok(contents)
+    Some(reply)
+}
+
+/// Event the server notified on its own initiative, instead of in reply to a command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AsyncEvent {
+    /// Text printed on the debugger console, through `O` packets.
+    ConsoleOutput(String),
+    /// The target stopped after being resumed, or a `%Stop` notification was received.
+    Stop(StopReply),
+    /// Notification packet other than `%Stop`; the contents don't include the leading `%`.
+    Notification(Vec<u8>),
 }
 
 pub struct GDBRemote {
     stream: TimestampedTcpStream,
     timeout: Duration,
-    data_buffer: Vec<u8>,
+    parser: StreamParser,
+    no_ack_mode: bool,
+    features: ServerFeatures,
+    /// Register number of the program counter in the stop replies, when the target
+    /// description tells the architecture, and it is a known one.
+    pc_register: Option<u32>,
+
+    /// Items parsed from the stream and not processed yet, along with the timestamp of the
+    /// TCP packet in which they started.
+    received_items: VecDeque<(StreamItem, Timestamp)>,
+    current_item_timestamp: Option<Timestamp>,
+    async_events: VecDeque<(AsyncEvent, Timestamp)>,
+    target_running: bool,
 
-    last_rx_packet_timestamp: Option<Timestamp>,
+    last_sent_packet: Vec<u8>,
+    retransmissions: usize,
 }
 
 #[derive(Debug)]
@@ -102,27 +512,6 @@ impl Response {
             _ => false,
         }
     }
-
-    pub fn is_packet(&self) -> bool {
-        match self {
-            Response::Packet(_) => true,
-            _ => false,
-        }
-    }
-
-    pub fn is_packet_with(&self, contents: &str) -> bool {
-        match self {
-            Response::Packet(data) if &data[..] == contents.as_bytes() => true,
-            _ => false,
-        }
-    }
-
-    pub fn to_string(&self) -> Option<String> {
-        match self {
-            Response::ACK => Some(String::from("<ACK>")),
-            Response::Packet(data) => String::from_utf8(data.to_vec()).ok(),
-        }
-    }
 }
 
 // Private helpers
@@ -144,17 +533,22 @@ impl GDBRemote {
         match self.stream.receive(&mut buffer) {
             Ok((0, _)) => return Err(GDBRemoteError::EndOfStream),
             Ok((n, timestamp)) => {
-                log::trace!("feeding buffer with {n} bytes");
+                log::trace!("feeding parser with {n} bytes");
 
-                let received = &buffer[..n];
+                for &byte in &buffer[..n] {
+                    // keep track of the timestamp of the TCP packet in which each item starts,
+                    // for association to the GDB response
+                    if self.parser.is_idle() {
+                        self.current_item_timestamp = Some(timestamp);
+                    }
 
-                self.data_buffer.extend_from_slice(received);
+                    if let Some(item) = self.parser.feed(byte) {
+                        let timestamp = self
+                            .current_item_timestamp
+                            .expect("the timestamp is set as soon as an item starts");
 
-                if received.contains(&b'$') || received.contains(&b'+') {
-                    // if the start byte or the ACK byte is in the received
-                    // packet data, keep track of the timestamp of this TCP
-                    // packet for association to the GDB response
-                    self.last_rx_packet_timestamp = Some(timestamp);
+                        self.received_items.push_back((item, timestamp));
+                    }
                 }
 
                 Ok(())
@@ -167,25 +561,31 @@ impl GDBRemote {
             Err(err) => {
                 log::error!("unexpected receive error: {err:?}");
                 Err(err.into())
-            },
+            }
         }
     }
 
-    fn eat_ack(&mut self) -> Option<()> {
-        if self.data_buffer.len() > 0 && self.data_buffer[0] == b'+' {
-            self.data_buffer = self.data_buffer[1..].to_vec();
-            Some(())
-        } else {
-            None
+    fn queue_async_event(&mut self, event: AsyncEvent, timestamp: Timestamp) {
+        if self.async_events.len() >= MAX_ASYNC_EVENTS {
+            log::warn!("too many unprocessed asynchronous events, dropping the oldest");
+            self.async_events.pop_front();
         }
+
+        self.async_events.push_back((event, timestamp));
     }
 
-    fn eat_packet(&mut self) -> Result<Vec<u8>> {
-        let packet = parse_gdb_packet(&self.data_buffer)?.to_vec();
+    fn retransmit(&mut self) -> Result<()> {
+        if self.retransmissions >= MAX_RETRANSMISSIONS {
+            return Err(GDBRemoteError::RetransmissionsExhausted(
+                self.retransmissions,
+            ));
+        }
 
-        self.data_buffer = self.data_buffer[packet.len() + 4..].to_vec();
+        log::debug!("got NAK, sending the last packet again");
+        self.retransmissions += 1;
+        self.stream.send(&self.last_sent_packet)?;
 
-        Ok(packet)
+        Ok(())
     }
 }
 
@@ -197,8 +597,16 @@ impl GDBRemote {
         Ok(GDBRemote {
             stream,
             timeout: DEFAULT_TIMEOUT,
-            data_buffer: Vec::new(),
-            last_rx_packet_timestamp: None,
+            parser: StreamParser::new(),
+            no_ack_mode: false,
+            features: ServerFeatures::default(),
+            pc_register: None,
+            received_items: VecDeque::new(),
+            current_item_timestamp: None,
+            async_events: VecDeque::new(),
+            target_running: false,
+            last_sent_packet: Vec::new(),
+            retransmissions: 0,
         })
     }
 
@@ -206,37 +614,945 @@ impl GDBRemote {
         self.timeout = timeout;
     }
 
+    /// Wait for the next ACK or packet from the server. NAKs and corrupted packets are
+    /// handled as the acknowledgment mode requires, while notifications and console output
+    /// are queued, to be retrieved with [`GDBRemote::pop_async_event`]; so are the stop
+    /// replies received while the target runs.
     pub fn read_response(&mut self) -> Result<(Response, Timestamp)> {
-        // TODO: handle corrupted stream
-
         let timeout_at = Instant::now() + self.timeout;
 
         loop {
-            if let Some(()) = self.eat_ack() {
-                let timestamp = self
-                    .last_rx_packet_timestamp
-                    .expect("if we received an ACK packet, the timestamp should be set");
+            let Some((item, timestamp)) = self.received_items.pop_front() else {
+                // nothing parsed yet, keep feeding the parser; errors and timeout will propagate
+                self.feed_buffer_from_stream(timeout_at)?;
+                continue;
+            };
 
-                return Ok((Response::ACK, timestamp));
+            if let Some(response) = self.process_item(item, timestamp)? {
+                return Ok(response);
             }
+        }
+    }
 
-            if let Ok(data) = self.eat_packet() {
-                let timestamp = self
-                    .last_rx_packet_timestamp
-                    .expect("if we received a data packet, the timestamp should be set");
+    /// Process the data the server sent since the last command, if any, without waiting for
+    /// more than [`POLL_TIMEOUT`]; only meant to be used while no command is pending, to get
+    /// the asynchronous events when there are no other exchanges.
+    pub fn poll_async_events(&mut self) -> Result<()> {
+        match self.feed_buffer_from_stream(Instant::now() + POLL_TIMEOUT) {
+            Ok(()) | Err(GDBRemoteError::Timeout) => {}
+            Err(err) => return Err(err),
+        }
 
-                return Ok((Response::Packet(data), timestamp));
+        while let Some((item, timestamp)) = self.received_items.pop_front() {
+            if let Some((response, _)) = self.process_item(item, timestamp)? {
+                log::warn!("dropping unsolicited response {:?}", response);
             }
+        }
 
-            // couldn't eat an ACK nor a packet, keep feeding the buffer;
-            // errors and timeout will propagate
-            self.feed_buffer_from_stream(timeout_at)?;
+        Ok(())
+    }
+
+    /// Handle an item parsed from the stream, returning it if it's a response to a command.
+    fn process_item(
+        &mut self,
+        item: StreamItem,
+        timestamp: Timestamp,
+    ) -> Result<Option<(Response, Timestamp)>> {
+        match item {
+            StreamItem::Ack => {
+                self.retransmissions = 0;
+                return Ok(Some((Response::ACK, timestamp)));
+            }
+            StreamItem::Nak if self.no_ack_mode => {
+                log::warn!("ignoring NAK in no-acknowledgment mode");
+            }
+            StreamItem::Nak => self.retransmit()?,
+            StreamItem::Corrupted(reason) => {
+                log::warn!("received corrupted packet: {}", reason);
+
+                if !self.no_ack_mode {
+                    // ask the server to send it again
+                    self.stream.send(b"-")?;
+                }
+            }
+            StreamItem::Notification(data) => {
+                let event = match data
+                    .strip_prefix(b"Stop:")
+                    .and_then(|stop| parse_stop_reply(stop, self.pc_register))
+                {
+                    Some(stop_reply) => AsyncEvent::Stop(stop_reply),
+                    None => AsyncEvent::Notification(data),
+                };
+
+                self.queue_async_event(event, timestamp);
+            }
+            StreamItem::Packet(data) => {
+                if !self.no_ack_mode {
+                    self.stream.send(b"+")?;
+                }
+
+                // console output is told apart from the "OK" reply like GDB does; the empty
+                // ones are sent by OpenOCD to keep the connection alive while the target runs
+                // https://github.com/openocd-org/openocd/blob/2e60e2eca9d06dcb99a4adb81ebe435a72ab0c7f/src/server/gdb_server.c#L3748
+                if data.first() == Some(&b'O') && data.get(1) != Some(&b'K') {
+                    match decode_hex(&data[1..]) {
+                        Some(text) if !text.is_empty() => {
+                            let text = String::from_utf8_lossy(&text).into_owned();
+                            self.queue_async_event(AsyncEvent::ConsoleOutput(text), timestamp);
+                        }
+                        Some(_) => {}
+                        None => log::warn!("console output is not valid hex: {:?}", data),
+                    }
+                    return Ok(None);
+                }
+
+                // the stop reply to a continue command arrives whenever the target halts,
+                // likely in the middle of other command/reply exchanges
+                if self.target_running {
+                    if let Some(stop_reply) = parse_stop_reply(&data, self.pc_register) {
+                        self.target_running = false;
+                        self.queue_async_event(AsyncEvent::Stop(stop_reply), timestamp);
+                        return Ok(None);
+                    }
+                }
+
+                return Ok(Some((Response::Packet(data), timestamp)));
+            }
         }
+
+        Ok(None)
+    }
+
+    /// Take the oldest event the server notified on its own initiative, if any.
+    pub fn pop_async_event(&mut self) -> Option<(AsyncEvent, Timestamp)> {
+        self.async_events.pop_front()
+    }
+
+    /// Whether the target was resumed and didn't report a stop yet.
+    pub fn is_target_running(&self) -> bool {
+        self.target_running
+    }
+
+    /// Resume the target; its stop reply will be reported as an [`AsyncEvent::Stop`].
+    pub fn continue_target(&mut self) -> Result<Timestamp> {
+        let timestamp = self.send_packet("c")?;
+        self.target_running = true;
+
+        Ok(timestamp)
+    }
+
+    /// Halt the running target, waiting for its stop reply; returns whether the interrupt halted
+    /// it. If the target stopped on its own in the meantime, its stop reply is reported as an
+    /// [`AsyncEvent::Stop`] as usual. Nothing to do if the target isn't running.
+    pub fn interrupt_target(&mut self) -> Result<bool> {
+        if !self.target_running {
+            return Ok(false);
+        }
+
+        self.stream.send(&[INTERRUPT_CHAR])?;
+
+        let timeout_at = Instant::now() + self.timeout;
+
+        while self.target_running {
+            let Some((item, timestamp)) = self.received_items.pop_front() else {
+                self.feed_buffer_from_stream(timeout_at)?;
+                continue;
+            };
+
+            if let Some((response, _)) = self.process_item(item, timestamp)? {
+                log::warn!(
+                    "dropping unexpected response {:?} while interrupting",
+                    response
+                );
+            }
+        }
+
+        // the stop reply is the last event queued, as it ended the loop; the one of the
+        // interrupt reports a SIGINT
+        let interrupted = matches!(
+            self.async_events.back(),
+            Some((
+                AsyncEvent::Stop(StopReply {
+                    kind: StopKind::Signal(2),
+                    ..
+                }),
+                _
+            ))
+        );
+        if interrupted {
+            self.async_events.pop_back();
+        }
+
+        Ok(interrupted)
     }
 
     pub fn send_packet(&mut self, contents: &str) -> Result<Timestamp> {
-        let timestamp = self.stream.send(&build_gdb_packet(contents))?;
+        self.last_sent_packet = build_gdb_packet(contents);
+        self.retransmissions = 0;
+
+        let timestamp = self.stream.send(&self.last_sent_packet)?;
 
         Ok(timestamp)
     }
+
+    /// Perform the initial handshake: negotiate the features supported by the server and
+    /// switch to the no-acknowledgment mode if possible. OpenOCD sends an ACK as soon as the
+    /// connection is established, other servers wait for the client to speak first.
+    pub fn handshake(&mut self, kind: GDBServerKind) -> Result<()> {
+        if kind == GDBServerKind::OpenOCD {
+            if !self.read_response()?.0.is_ack() {
+                return Err(GDBRemoteError::UnexpectedResponse(
+                    "expected initial ACK".into(),
+                ));
+            }
+            log::trace!("got initial GDB ACK");
+        }
+
+        log::trace!("negotiating supported features");
+        self.send_packet("qSupported:binary-upload+")?;
+        let (reply, _) = self.read_reply()?;
+        self.features = parse_supported_features(&reply);
+        log::debug!("server features: {:?}", self.features);
+
+        if self.features.target_description {
+            match self.read_target_description() {
+                Ok(description) => self.pc_register = pc_register(&description),
+                Err(GDBRemoteError::ErrorResponse(err)) => {
+                    log::warn!("failed to read the target description: {}", err)
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        if self.pc_register.is_none() {
+            log::info!("unknown target architecture, the stop replies won't tell the PC");
+        }
+
+        if !self.features.no_ack_mode && kind != GDBServerKind::OpenOCD {
+            log::info!("server doesn't support QStartNoAckMode, staying in acknowledgment mode");
+            return Ok(());
+        }
+
+        log::trace!("asking GDB to QStartNoAckMode");
+        self.send_packet("QStartNoAckMode")?;
+        if self.read_reply()?.0 != b"OK" {
+            return Err(GDBRemoteError::UnexpectedResponse(
+                "expected OK for QStartNoAckMode".into(),
+            ));
+        }
+        self.no_ack_mode = true;
+        log::trace!("got OK for QStartNoAckMode");
+
+        Ok(())
+    }
+
+    /// Read the XML target description, `target.xml`, in as many chunks as needed.
+    fn read_target_description(&mut self) -> Result<String> {
+        let mut description = Vec::new();
+
+        loop {
+            self.send_packet(&format!(
+                "qXfer:features:read:target.xml:{:x},{:x}",
+                description.len(),
+                self.max_memory_read_size()
+            ))?;
+
+            let (reply, _) = self.read_reply()?;
+            match reply.split_first() {
+                // more to read after this chunk, or the last one
+                Some((b'm', chunk)) => description.extend(unescape_binary(chunk)?),
+                Some((b'l', chunk)) => {
+                    description.extend(unescape_binary(chunk)?);
+                    break;
+                }
+                _ => {
+                    return Err(GDBRemoteError::ErrorResponse(
+                        String::from_utf8_lossy(&reply).into_owned(),
+                    ))
+                }
+            }
+        }
+
+        Ok(String::from_utf8_lossy(&description).into_owned())
+    }
+
+    /// Largest number of bytes a single memory read can ask for, so that the reply fits the
+    /// packet size advertised by the server; hex encoded replies take twice the data size, and
+    /// binary ones up to as much, when every byte needs escaping, after their leading 'b'.
+    pub fn max_memory_read_size(&self) -> usize {
+        let packet_size = self.features.packet_size.unwrap_or(DEFAULT_PACKET_SIZE);
+
+        match self.features.binary_upload {
+            true => packet_size.saturating_sub(1) / 2,
+            false => packet_size / 2,
+        }
+    }
+
+    /// Wait for the reply to a command, skipping the ACKs.
+    fn read_reply(&mut self) -> Result<(Vec<u8>, Timestamp)> {
+        loop {
+            match self.read_response()? {
+                (Response::Packet(data), _) if data.len() == 3 && data[0] == b'E' => {
+                    return Err(GDBRemoteError::ErrorResponse(
+                        String::from_utf8_lossy(&data).into_owned(),
+                    ));
+                }
+                (Response::Packet(data), timestamp) => return Ok((data, timestamp)),
+                (Response::ACK, _) => log::debug!("ignoring unexpected ACK"),
+            }
+        }
+    }
+
+    /// Read `length` bytes of target memory starting at `address`.
+    pub fn read_memory(&mut self, address: u32, length: usize) -> Result<(Vec<u8>, Timestamp)> {
+        self.request_memory(address, length)?;
+
+        self.read_memory_reply(length)
+    }
+
+    /// Send a request to read `length` bytes of target memory starting at `address`, without
+    /// waiting for the reply; returns the timestamp of the request.
+    pub fn request_memory(&mut self, address: u32, length: usize) -> Result<Timestamp> {
+        if self.features.binary_upload {
+            self.send_packet(&format!("x{:x},{:x}", address, length))
+        } else {
+            self.send_packet(&format!("m{:x},{:x}", address, length))
+        }
+    }
+
+    /// Wait for the reply to a memory read request of `length` bytes.
+    pub fn read_memory_reply(&mut self, length: usize) -> Result<(Vec<u8>, Timestamp)> {
+        let (data, timestamp) = self.read_reply()?;
+
+        let bytes = if self.features.binary_upload {
+            match data.split_first() {
+                Some((b'b', binary)) => unescape_binary(binary)?,
+                _ => {
+                    return Err(GDBRemoteError::ParseError(
+                        "binary memory read reply doesn't start with 'b'".into(),
+                    ))
+                }
+            }
+        } else {
+            decode_hex(&data).ok_or_else(|| {
+                GDBRemoteError::ParseError("memory read reply is not valid hex".into())
+            })?
+        };
+
+        if bytes.len() != length {
+            return Err(GDBRemoteError::UnexpectedResponse(format!(
+                "asked to read {} bytes, got {}",
+                length,
+                bytes.len()
+            )));
+        }
+
+        Ok((bytes, timestamp))
+    }
+
+    /// Insert a hardware watchpoint on the `length` bytes at `address`; the hit is reported as
+    /// an [`AsyncEvent::Stop`] with the watchpoint details.
+    pub fn insert_watchpoint(
+        &mut self,
+        kind: WatchpointKind,
+        address: u32,
+        length: usize,
+    ) -> Result<()> {
+        self.watchpoint_command('Z', kind, address, length)
+    }
+
+    /// Remove a hardware watchpoint previously inserted with [`GDBRemote::insert_watchpoint`].
+    pub fn remove_watchpoint(
+        &mut self,
+        kind: WatchpointKind,
+        address: u32,
+        length: usize,
+    ) -> Result<()> {
+        self.watchpoint_command('z', kind, address, length)
+    }
+
+    fn watchpoint_command(
+        &mut self,
+        command: char,
+        kind: WatchpointKind,
+        address: u32,
+        length: usize,
+    ) -> Result<()> {
+        let watchpoint_type = match kind {
+            WatchpointKind::Write => 2,
+            WatchpointKind::Read => 3,
+            WatchpointKind::Access => 4,
+        };
+
+        self.send_packet(&format!(
+            "{}{},{:x},{:x}",
+            command, watchpoint_type, address, length
+        ))?;
+
+        match self.read_reply()? {
+            (data, _) if data == b"OK" => Ok(()),
+            (data, _) if data.is_empty() => Err(GDBRemoteError::UnexpectedResponse(format!(
+                "{:?} watchpoints not supported by the server",
+                kind
+            ))),
+            (data, _) => Err(GDBRemoteError::UnexpectedResponse(
+                String::from_utf8_lossy(&data).into_owned(),
+            )),
+        }
+    }
+
+    /// Write `data` to target memory starting at `address`.
+    pub fn write_memory(&mut self, address: u32, data: &[u8]) -> Result<()> {
+        self.send_packet(&format!(
+            "M{:x},{:x}:{}",
+            address,
+            data.len(),
+            encode_hex(data)
+        ))?;
+
+        match self.read_reply()? {
+            (data, _) if data == b"OK" => Ok(()),
+            (data, _) => Err(GDBRemoteError::UnexpectedResponse(
+                String::from_utf8_lossy(&data).into_owned(),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hex_roundtrip() {
+        let bytes = [0x00, 0x01, 0xab, 0xff, 0x7d];
+
+        assert_eq!(encode_hex(&bytes), "0001abff7d");
+        assert_eq!(decode_hex(b"0001abff7d"), Some(bytes.to_vec()));
+        assert_eq!(decode_hex(b"0001ABFF7D"), Some(bytes.to_vec()));
+        assert_eq!(decode_hex(b"000"), None);
+        assert_eq!(decode_hex(b"0g"), None);
+    }
+
+    #[test]
+    fn test_parse_supported_features() {
+        let features =
+            parse_supported_features(b"PacketSize=3fff;qXfer:memory-map:read-;QStartNoAckMode+");
+        assert_eq!(features.packet_size, Some(0x3fff));
+        assert!(features.no_ack_mode);
+
+        let features = parse_supported_features(b"");
+        assert_eq!(features, ServerFeatures::default());
+    }
+
+    #[test]
+    fn test_generic_server_memory_access() {
+        use mock_stub::{MockStub, MockStubConfig};
+
+        let memory = (0..=255).collect();
+        let stub = MockStub::spawn(MockStubConfig::default(), memory);
+
+        let mut gdb = GDBRemote::connect(stub.address).unwrap();
+        gdb.handshake(GDBServerKind::Generic).unwrap();
+
+        assert_eq!(gdb.features.packet_size, Some(0x100));
+        assert_eq!(gdb.max_memory_read_size(), 0x80);
+
+        let (bytes, _) = gdb.read_memory(0x20000010, 4).unwrap();
+        assert_eq!(bytes, [0x10, 0x11, 0x12, 0x13]);
+
+        gdb.write_memory(0x20000011, &[0xaa, 0xbb]).unwrap();
+        assert_eq!(
+            stub.memory.lock().unwrap()[0x10..0x14],
+            [0x10, 0xaa, 0xbb, 0x13]
+        );
+
+        stub.memory.lock().unwrap()[0x13] = 0xcc;
+        let (bytes, _) = gdb.read_memory(0x20000010, 4).unwrap();
+        assert_eq!(bytes, [0x10, 0xaa, 0xbb, 0xcc]);
+
+        let (bytes, _) = gdb
+            .read_memory(0x20000000, gdb.max_memory_read_size())
+            .unwrap();
+        assert_eq!(bytes.len(), 0x80);
+
+        assert!(matches!(
+            gdb.read_memory(0x10000000, 4),
+            Err(GDBRemoteError::ErrorResponse(_))
+        ));
+    }
+
+    #[test]
+    fn test_handshake_without_no_ack_mode() {
+        use mock_stub::{MockStub, MockStubConfig};
+
+        let config = MockStubConfig {
+            supported: String::from("PacketSize=100"),
+            ..Default::default()
+        };
+        let stub = MockStub::spawn(config, vec![0x42; 16]);
+
+        let mut gdb = GDBRemote::connect(stub.address).unwrap();
+        gdb.handshake(GDBServerKind::Generic).unwrap();
+
+        // every reply is acknowledged, and the ACKs sent by the stub are skipped
+        for _ in 0..3 {
+            let (bytes, _) = gdb.read_memory(0x20000000, 2).unwrap();
+            assert_eq!(bytes, [0x42, 0x42]);
+        }
+
+        assert!(!stub
+            .received
+            .lock()
+            .unwrap()
+            .contains(&String::from("QStartNoAckMode")));
+    }
+
+    #[test]
+    fn test_target_description() {
+        use mock_stub::{MockStub, MockStubConfig};
+
+        let handshake = |description: Option<&str>| {
+            let config = MockStubConfig {
+                supported: String::from("PacketSize=100;QStartNoAckMode+;qXfer:features:read+"),
+                target_description: description.map(String::from),
+                ..Default::default()
+            };
+            let stub = MockStub::spawn(config, vec![0; 16]);
+
+            let mut gdb = GDBRemote::connect(stub.address).unwrap();
+            gdb.handshake(GDBServerKind::Generic).unwrap();
+            gdb.pc_register
+        };
+
+        // longer than a packet, read in several chunks
+        let arm = format!(
+            "<?xml version=\"1.0\"?><target><architecture>arm</architecture>{}</target>",
+            "<feature name=\"org.gnu.gdb.arm.m-profile\"></feature>".repeat(10)
+        );
+        assert_eq!(handshake(Some(&arm)), Some(ARM_PC_REGISTER));
+        assert_eq!(
+            handshake(Some(
+                "<target><architecture>riscv:rv32</architecture></target>"
+            )),
+            None
+        );
+        // advertised, but no description to read
+        assert_eq!(handshake(None), None);
+    }
+
+    #[test]
+    fn test_openocd_handshake() {
+        use mock_stub::{MockStub, MockStubConfig};
+
+        let config = MockStubConfig {
+            initial_ack: true,
+            supported: String::from("PacketSize=3fff;QStartNoAckMode+"),
+            ..Default::default()
+        };
+        let stub = MockStub::spawn(config, vec![0x00; 16]);
+
+        let mut gdb = GDBRemote::connect(stub.address).unwrap();
+        gdb.handshake(GDBServerKind::OpenOCD).unwrap();
+
+        assert_eq!(gdb.max_memory_read_size(), 0x1fff);
+        assert_eq!(
+            stub.received.lock().unwrap()[..],
+            [
+                String::from("qSupported:binary-upload+"),
+                String::from("QStartNoAckMode")
+            ]
+        );
+    }
+
+    /// Minimal xorshift generator, to make the randomized tests reproducible.
+    struct XorShift(u64);
+
+    impl XorShift {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        /// Random bytes, with long runs of repeated values and plenty of characters that
+        /// need escaping.
+        fn bytes(&mut self, max_length: usize) -> Vec<u8> {
+            let length = self.next() as usize % (max_length + 1);
+            let mut bytes = Vec::with_capacity(length);
+
+            while bytes.len() < length {
+                let b = match self.next() % 4 {
+                    0 => [b'#', b'$', b'}', b'*', 0x00][self.next() as usize % 5],
+                    _ => self.next() as u8,
+                };
+                let run = 1 + self.next() as usize % 120 * (self.next() % 2) as usize;
+
+                bytes.extend(std::iter::repeat_n(b, run.min(length - bytes.len())));
+            }
+
+            bytes
+        }
+    }
+
+    fn parse_stream(bytes: &[u8]) -> Vec<StreamItem> {
+        let mut parser = StreamParser::new();
+
+        bytes.iter().filter_map(|&b| parser.feed(b)).collect()
+    }
+
+    #[test]
+    fn test_run_length_encoded_packet() {
+        // example from the GDB documentation
+        assert_eq!(
+            parse_stream(b"$0* #7a"),
+            [StreamItem::Packet(b"0000".to_vec())]
+        );
+        assert_eq!(
+            parse_stream(b"$0*\"#7c"),
+            [StreamItem::Packet(b"000000".to_vec())]
+        );
+
+        // the repeat count can be any printable character, even the escape one
+        let packet = frame_gdb_packet(b"0*}");
+        assert_eq!(parse_stream(&packet), [StreamItem::Packet(vec![b'0'; 97])]);
+
+        assert!(matches!(
+            parse_stream(b"$*\"#4c")[..],
+            [StreamItem::Corrupted(_)]
+        ));
+    }
+
+    #[test]
+    fn test_escaped_packet() {
+        // '#' escaped as "}\x03" must not end the packet
+        let packet = frame_gdb_packet(b"b}\x03}]x");
+        let [StreamItem::Packet(contents)] = &parse_stream(&packet)[..] else {
+            panic!("expected a single packet");
+        };
+        assert_eq!(unescape_binary(&contents[1..]).unwrap(), b"#}x");
+
+        assert!(unescape_binary(b"ab}").is_err());
+    }
+
+    #[test]
+    fn test_stream_recovery() {
+        let items = parse_stream(b"+garbage-$OK#9a$bad#00%Stop:T05#99$trunc$OK#9a$OK#zz+");
+        assert_eq!(
+            items[..3],
+            [
+                StreamItem::Ack,
+                StreamItem::Nak,
+                StreamItem::Packet(b"OK".to_vec())
+            ]
+        );
+        assert!(matches!(items[3], StreamItem::Corrupted(_)));
+        assert_eq!(
+            items[4..6],
+            [
+                StreamItem::Notification(b"Stop:T05".to_vec()),
+                StreamItem::Packet(b"OK".to_vec())
+            ]
+        );
+        assert!(matches!(items[6], StreamItem::Corrupted(_)));
+        assert_eq!(items[7], StreamItem::Ack);
+        assert_eq!(items.len(), 8);
+
+        // contents that never end are dropped instead of growing without bounds
+        let mut stream = vec![b'$'];
+        stream.extend(std::iter::repeat_n(b'a', MAX_PACKET_CONTENTS_SIZE + 10));
+        stream.extend_from_slice(b"#00$OK#9a");
+        let items = parse_stream(&stream);
+        assert!(matches!(items[0], StreamItem::Corrupted(_)));
+        assert_eq!(items.last(), Some(&StreamItem::Packet(b"OK".to_vec())));
+    }
+
+    #[test]
+    fn test_framing_roundtrip_fuzz() {
+        use mock_stub::run_length_encode;
+
+        let mut rng = XorShift(0x0123_4567_89ab_cdef);
+
+        for _ in 0..2000 {
+            let data = rng.bytes(300);
+
+            let escaped = escape_binary(&data);
+            assert!(!escaped.contains(&b'#') && !escaped.contains(&b'$'));
+
+            let mut stream = frame_gdb_packet(&run_length_encode(&escaped));
+            let cut = rng.next() as usize % stream.len();
+            stream.extend_from_slice(b"+$OK#9a");
+
+            let items = parse_stream(&stream);
+            assert_eq!(items.len(), 3);
+            assert_eq!(items[0], StreamItem::Packet(escaped.clone()));
+            assert_eq!(items[1], StreamItem::Ack);
+
+            let StreamItem::Packet(contents) = &items[0] else {
+                unreachable!()
+            };
+            assert_eq!(unescape_binary(contents).unwrap(), data);
+
+            // a truncated packet is never complete, and is dropped in favor of the next one
+            let mut truncated = stream[..cut].to_vec();
+            truncated.extend_from_slice(b"$OK#9a");
+            assert_eq!(
+                parse_stream(&truncated).last(),
+                Some(&StreamItem::Packet(b"OK".to_vec()))
+            );
+        }
+    }
+
+    #[test]
+    fn test_parser_garbage_fuzz() {
+        let mut rng = XorShift(0xfeed_beef_dead_cafe);
+
+        for _ in 0..20000 {
+            let mut bytes = rng.bytes(64);
+            if rng.next().is_multiple_of(2) {
+                bytes.insert(0, [b'$', b'%'][rng.next() as usize % 2]);
+            }
+
+            // whatever comes before, the parser recovers at the next packet
+            bytes.extend_from_slice(b"$OK#9a");
+            assert_eq!(
+                parse_stream(&bytes).last(),
+                Some(&StreamItem::Packet(b"OK".to_vec()))
+            );
+        }
+    }
+
+    #[test]
+    fn test_nak_and_corruption_recovery() {
+        use mock_stub::{MockStub, MockStubConfig};
+
+        let config = MockStubConfig {
+            supported: String::from("PacketSize=100"),
+            rejected_commands: 2,
+            corrupted_replies: 2,
+            garbage: b"\x00junk#}".to_vec(),
+            ..Default::default()
+        };
+        let stub = MockStub::spawn(config, (0..16).collect());
+
+        let mut gdb = GDBRemote::connect(stub.address).unwrap();
+        gdb.handshake(GDBServerKind::Generic).unwrap();
+
+        for _ in 0..3 {
+            let (bytes, _) = gdb.read_memory(0x20000004, 4).unwrap();
+            assert_eq!(bytes, [4, 5, 6, 7]);
+        }
+    }
+
+    #[test]
+    fn test_retransmissions_exhausted() {
+        use mock_stub::{MockStub, MockStubConfig};
+
+        let config = MockStubConfig {
+            rejected_commands: usize::MAX,
+            ..Default::default()
+        };
+        let stub = MockStub::spawn(config, vec![0; 16]);
+
+        let mut gdb = GDBRemote::connect(stub.address).unwrap();
+        assert!(matches!(
+            gdb.handshake(GDBServerKind::Generic),
+            Err(GDBRemoteError::RetransmissionsExhausted(
+                MAX_RETRANSMISSIONS
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_async_packets() {
+        use mock_stub::{MockStub, MockStubConfig};
+
+        let config = MockStubConfig {
+            console_output: Some(String::from("hello")),
+            stop_notification: Some(String::from("Stop:T05")),
+            ..Default::default()
+        };
+        let stub = MockStub::spawn(config, vec![0; 16]);
+
+        let mut gdb = GDBRemote::connect(stub.address).unwrap();
+        gdb.handshake(GDBServerKind::Generic).unwrap();
+        gdb.continue_target().unwrap();
+        gdb.read_memory(0x20000000, 4).unwrap();
+
+        let events: Vec<_> = std::iter::from_fn(|| gdb.pop_async_event())
+            .map(|(event, _)| event)
+            .collect();
+        let console_output = AsyncEvent::ConsoleOutput(String::from("hello"));
+        assert_eq!(
+            events,
+            [
+                console_output.clone(),
+                console_output.clone(),
+                AsyncEvent::Stop(StopReply {
+                    kind: StopKind::Signal(5),
+                    pc: None,
+                    watchpoint: None,
+                    breakpoint: false,
+                }),
+                console_output
+            ]
+        );
+    }
+
+    #[test]
+    fn test_binary_and_run_length_encoded_reads() {
+        use mock_stub::{MockStub, MockStubConfig};
+
+        let mut memory = vec![0; 256];
+        memory[0x10..0x18].copy_from_slice(b"#$}*\x00\x00\x00\x00");
+        memory[0x80..].fill(0x7d);
+
+        for supported in [
+            "PacketSize=100;QStartNoAckMode+",
+            "PacketSize=100;binary-upload+",
+        ] {
+            let config = MockStubConfig {
+                supported: String::from(supported),
+                run_length_encoding: true,
+                ..Default::default()
+            };
+            let stub = MockStub::spawn(config, memory.clone());
+
+            let mut gdb = GDBRemote::connect(stub.address).unwrap();
+            gdb.handshake(GDBServerKind::Generic).unwrap();
+
+            let max_read_size = gdb.max_memory_read_size();
+            for offset in (0..memory.len()).step_by(max_read_size) {
+                let length = max_read_size.min(memory.len() - offset);
+                let (bytes, _) = gdb.read_memory(0x20000000 + offset as u32, length).unwrap();
+                assert_eq!(bytes, memory[offset..offset + length]);
+            }
+
+            let command = stub.received.lock().unwrap().last().unwrap().clone();
+            assert!(command.starts_with(if gdb.features.binary_upload { 'x' } else { 'm' }));
+        }
+    }
+
+    #[test]
+    fn test_binary_read_of_escaped_bytes() {
+        use mock_stub::{MockStub, MockStubConfig};
+
+        // every byte needs escaping, doubling the size of the reply
+        let memory = b"}#$*".repeat(64);
+        let config = MockStubConfig {
+            supported: String::from("PacketSize=100;binary-upload+"),
+            ..Default::default()
+        };
+        let stub = MockStub::spawn(config, memory.clone());
+
+        let mut gdb = GDBRemote::connect(stub.address).unwrap();
+        gdb.handshake(GDBServerKind::Generic).unwrap();
+        assert!(gdb.features.binary_upload);
+
+        let max_read_size = gdb.max_memory_read_size();
+        assert_eq!(max_read_size, 0x7f);
+
+        let (bytes, _) = gdb.read_memory(0x20000000, max_read_size).unwrap();
+        assert_eq!(bytes, memory[..max_read_size]);
+
+        // one more byte would overflow the packet
+        assert!(gdb.read_memory(0x20000000, max_read_size + 1).is_err());
+    }
+
+    #[test]
+    fn test_parse_stop_reply() {
+        let reply = parse_stop_reply(
+            b"T05watch:20000010;0f:34120008;thread:1;",
+            Some(ARM_PC_REGISTER),
+        )
+        .unwrap();
+        assert_eq!(
+            reply,
+            StopReply {
+                kind: StopKind::Signal(5),
+                pc: Some(0x08001234),
+                watchpoint: Some((WatchpointKind::Write, 0x20000010)),
+                breakpoint: false,
+            }
+        );
+        assert_eq!(
+            reply.to_string(),
+            "stopped by SIGTRAP, Write watchpoint hit at 0x20000010, PC 0x08001234"
+        );
+
+        // the register numbers depend on the architecture, unknown without a target description
+        let reply = parse_stop_reply(b"T05watch:20000010;0f:34120008;thread:1;", None).unwrap();
+        assert_eq!(reply.pc, None);
+        assert_eq!(reply.watchpoint, Some((WatchpointKind::Write, 0x20000010)));
+
+        let reply = parse_stop_reply(b"T0bhwbreak:;", None).unwrap();
+        assert_eq!(reply.kind, StopKind::Signal(11));
+        assert!(reply.breakpoint);
+
+        assert_eq!(
+            parse_stop_reply(b"W00", None).unwrap().kind,
+            StopKind::Exited(0)
+        );
+        assert_eq!(
+            parse_stop_reply(b"S02", None).unwrap().to_string(),
+            "stopped by SIGINT"
+        );
+        assert_eq!(parse_stop_reply(b"OK", None), None);
+        assert_eq!(parse_stop_reply(b"T", None), None);
+    }
+
+    #[test]
+    fn test_stop_reply_while_running() {
+        use mock_stub::{MockStub, MockStubConfig};
+
+        let stub = MockStub::spawn(MockStubConfig::default(), vec![0; 16]);
+
+        let mut gdb = GDBRemote::connect(stub.address).unwrap();
+        gdb.handshake(GDBServerKind::Generic).unwrap();
+        gdb.continue_target().unwrap();
+
+        // the target halts while we wait for a memory read reply
+        stub.halt("T05awatch:20000004;");
+        let (bytes, _) = gdb.read_memory(0x20000000, 4).unwrap();
+        assert_eq!(bytes, [0; 4]);
+
+        let Some((AsyncEvent::Stop(reply), _)) = gdb.pop_async_event() else {
+            panic!("expected a stop event");
+        };
+        assert_eq!(reply.watchpoint, Some((WatchpointKind::Access, 0x20000004)));
+        assert_eq!(gdb.pop_async_event(), None);
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        use mock_stub::{MockStub, MockStubConfig};
+
+        let stub = MockStub::spawn(MockStubConfig::default(), vec![0; 16]);
+
+        let mut gdb = GDBRemote::connect(stub.address).unwrap();
+        gdb.handshake(GDBServerKind::Generic).unwrap();
+
+        gdb.insert_watchpoint(WatchpointKind::Write, 0x20000004, 4)
+            .unwrap();
+        gdb.insert_watchpoint(WatchpointKind::Access, 0x20000008, 2)
+            .unwrap();
+        gdb.remove_watchpoint(WatchpointKind::Write, 0x20000004, 4)
+            .unwrap();
+        assert_eq!(
+            stub.received.lock().unwrap()[2..],
+            ["Z2,20000004,4", "Z4,20000008,2", "z2,20000004,4"]
+        );
+
+        // the stub supports at most two watchpoints, and can't remove the ones it doesn't have
+        gdb.insert_watchpoint(WatchpointKind::Read, 0x20000000, 4)
+            .unwrap();
+        assert!(matches!(
+            gdb.insert_watchpoint(WatchpointKind::Read, 0x2000000c, 4),
+            Err(GDBRemoteError::ErrorResponse(_))
+        ));
+        assert!(matches!(
+            gdb.remove_watchpoint(WatchpointKind::Write, 0x20000004, 4),
+            Err(GDBRemoteError::ErrorResponse(_))
+        ));
+    }
 }
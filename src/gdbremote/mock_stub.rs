@@ -0,0 +1,366 @@
+//! Minimal GDB remote stub for tests, serving reads and writes of an emulated memory region.
+
+use std::io::{Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use super::{
+    decode_hex, encode_hex, escape_binary, frame_gdb_packet, StreamItem, StreamParser, ESCAPE_CHAR,
+    INTERRUPT_CHAR, RUN_LENGTH_CHAR, RUN_LENGTH_OFFSET,
+};
+
+/// Number of hardware watchpoints the stub supports, like many Cortex-M cores.
+const MAX_WATCHPOINTS: usize = 2;
+
+/// Behavior of the mock stub.
+#[derive(Debug, Clone)]
+pub struct MockStubConfig {
+    /// Send an ACK as soon as the connection is accepted, like OpenOCD does.
+    pub initial_ack: bool,
+    /// Reply to `qSupported`.
+    pub supported: String,
+    /// Packet size the stub can handle; memory reads that don't fit in it fail with `E01`.
+    pub packet_size: usize,
+    /// Address of the first byte of the emulated memory.
+    pub memory_base: u32,
+    /// Run-length encode the replies, like gdbserver does.
+    pub run_length_encoding: bool,
+    /// Number of commands to reject with a NAK the first time they are sent.
+    pub rejected_commands: usize,
+    /// Number of replies to send with a wrong checksum the first time, before the NAK.
+    pub corrupted_replies: usize,
+    /// Bytes to send before each reply, that the client must discard.
+    pub garbage: Vec<u8>,
+    /// Console output packet to send before each reply.
+    pub console_output: Option<String>,
+    /// Notification to send after the continue command.
+    pub stop_notification: Option<String>,
+    /// XML target description, read with `qXfer:features:read:target.xml`.
+    pub target_description: Option<String>,
+}
+
+impl Default for MockStubConfig {
+    fn default() -> Self {
+        MockStubConfig {
+            initial_ack: false,
+            supported: String::from("PacketSize=100;QStartNoAckMode+"),
+            packet_size: 0x100,
+            memory_base: 0x20000000,
+            run_length_encoding: false,
+            rejected_commands: 0,
+            corrupted_replies: 0,
+            garbage: Vec::new(),
+            console_output: None,
+            stop_notification: None,
+            target_description: None,
+        }
+    }
+}
+
+pub struct MockStub {
+    pub address: SocketAddr,
+    /// Emulated memory, shared with the stub thread so that tests can change it on the fly.
+    pub memory: Arc<Mutex<Vec<u8>>>,
+    /// Contents of all the packets received by the stub, in order; interrupts are recorded as
+    /// `"\x03"`.
+    pub received: Arc<Mutex<Vec<String>>>,
+    connection: Arc<Mutex<Option<TcpStream>>>,
+}
+
+impl MockStub {
+    /// Spawn a stub accepting a single connection on a random local port.
+    pub fn spawn(config: MockStubConfig, memory: Vec<u8>) -> MockStub {
+        let listener = TcpListener::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let memory = Arc::new(Mutex::new(memory));
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let connection = Arc::new(Mutex::new(None));
+
+        let stub = MockStub {
+            address,
+            memory: memory.clone(),
+            received: received.clone(),
+            connection: connection.clone(),
+        };
+
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            *connection.lock().unwrap() = Some(stream.try_clone().unwrap());
+
+            // the client is gone when the stub fails to read or write, nothing more to do
+            let _ = serve(stream, config, memory, received);
+        });
+
+        stub
+    }
+}
+
+impl MockStub {
+    /// Send the given stop reply, as if the running target just halted.
+    pub fn halt(&self, stop_reply: &str) {
+        let mut connection = self.connection.lock().unwrap();
+        let stream = connection.as_mut().expect("no client connected yet");
+
+        stream
+            .write_all(&frame_gdb_packet(stop_reply.as_bytes()))
+            .unwrap();
+    }
+}
+
+fn serve(
+    mut stream: TcpStream,
+    mut config: MockStubConfig,
+    memory: Arc<Mutex<Vec<u8>>>,
+    received: Arc<Mutex<Vec<String>>>,
+) -> std::io::Result<()> {
+    let mut parser = StreamParser::new();
+    let mut no_ack_mode = false;
+    let mut last_reply = Vec::new();
+    let mut watchpoints = Vec::new();
+
+    if config.initial_ack {
+        stream.write_all(b"+")?;
+    }
+
+    loop {
+        let mut chunk = [0; 1024];
+        let n = stream.read(&mut chunk)?;
+        if n == 0 {
+            return Ok(());
+        }
+
+        for &byte in &chunk[..n] {
+            // the interrupt comes on its own, between packets, and halts the target
+            if byte == INTERRUPT_CHAR && parser.is_idle() {
+                received.lock().unwrap().push(String::from("\x03"));
+                stream.write_all(&frame_gdb_packet(b"S02"))?;
+                continue;
+            }
+
+            let Some(item) = parser.feed(byte) else {
+                continue;
+            };
+
+            let contents = match item {
+                StreamItem::Packet(contents) => contents,
+                StreamItem::Nak => {
+                    stream.write_all(&last_reply)?;
+                    continue;
+                }
+                _ => continue,
+            };
+            let command = String::from_utf8_lossy(&contents).into_owned();
+
+            if !no_ack_mode && config.rejected_commands > 0 {
+                config.rejected_commands -= 1;
+                stream.write_all(b"-")?;
+                continue;
+            }
+
+            if !no_ack_mode {
+                stream.write_all(b"+")?;
+            }
+
+            received.lock().unwrap().push(command.clone());
+
+            let binary_upload = config.supported.contains("binary-upload+");
+
+            let reply = match command.as_str() {
+                "QStartNoAckMode" => {
+                    no_ack_mode = true;
+                    Some(b"OK".to_vec())
+                }
+                "?" => Some(b"S05".to_vec()),
+                // running until told otherwise, memory can be accessed in the meantime
+                "c" => {
+                    if let Some(notification) = &config.stop_notification {
+                        let mut packet = frame_gdb_packet(notification.as_bytes());
+                        packet[0] = b'%';
+                        stream.write_all(&packet)?;
+                    }
+                    None
+                }
+                command if command.starts_with("qSupported") => {
+                    Some(config.supported.as_bytes().to_vec())
+                }
+                command if command.starts_with("qXfer:features:read:target.xml:") => {
+                    Some(read_target_description(&config, &command[31..]))
+                }
+                command if command.starts_with('m') => Some(read_memory(
+                    &config,
+                    &memory.lock().unwrap(),
+                    &command[1..],
+                    false,
+                )),
+                command if command.starts_with('x') && binary_upload => Some(read_memory(
+                    &config,
+                    &memory.lock().unwrap(),
+                    &command[1..],
+                    true,
+                )),
+                command if command.starts_with('Z') => {
+                    if watchpoints.len() < MAX_WATCHPOINTS {
+                        watchpoints.push(command[1..].to_owned());
+                        Some(b"OK".to_vec())
+                    } else {
+                        Some(b"E0e".to_vec())
+                    }
+                }
+                command if command.starts_with('z') => {
+                    match watchpoints.iter().position(|w| w == &command[1..]) {
+                        Some(i) => {
+                            watchpoints.remove(i);
+                            Some(b"OK".to_vec())
+                        }
+                        None => Some(b"E0e".to_vec()),
+                    }
+                }
+                command if command.starts_with('M') => Some(write_memory(
+                    &config,
+                    &mut memory.lock().unwrap(),
+                    &command[1..],
+                )),
+                _ => Some(Vec::new()),
+            };
+
+            let Some(reply) = reply else {
+                continue;
+            };
+
+            let reply = match config.run_length_encoding {
+                true => run_length_encode(&reply),
+                false => reply,
+            };
+            last_reply = frame_gdb_packet(&reply);
+
+            stream.write_all(&config.garbage)?;
+
+            if let Some(text) = &config.console_output {
+                let console_packet = format!("O{}", encode_hex(text.as_bytes()));
+                stream.write_all(&frame_gdb_packet(console_packet.as_bytes()))?;
+            }
+
+            if config.corrupted_replies > 0 && !no_ack_mode {
+                config.corrupted_replies -= 1;
+
+                let mut corrupted_reply = last_reply.clone();
+                let checksum_i = corrupted_reply.len() - 1;
+                corrupted_reply[checksum_i] = match corrupted_reply[checksum_i] {
+                    b'0' => b'1',
+                    _ => b'0',
+                };
+                stream.write_all(&corrupted_reply)?;
+            } else {
+                stream.write_all(&last_reply)?;
+            }
+        }
+    }
+}
+
+/// Run-length encode a packet payload, avoiding the repeat counts that would produce the `#`
+/// and `$` characters, which are not allowed.
+pub fn run_length_encode(payload: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(payload.len());
+    let mut i = 0;
+
+    while i < payload.len() {
+        let b = payload[i];
+        result.push(b);
+        i += 1;
+
+        // the character following an escape is never the start of a run
+        if b == ESCAPE_CHAR && i < payload.len() {
+            result.push(payload[i]);
+            i += 1;
+            continue;
+        }
+
+        let mut repeats = payload[i..].iter().take_while(|&&c| c == b).count();
+        repeats = repeats.min((b'~' - RUN_LENGTH_OFFSET) as usize);
+        if (6..=7).contains(&repeats) {
+            repeats = 5;
+        }
+
+        if repeats >= 3 {
+            result.push(RUN_LENGTH_CHAR);
+            result.push(repeats as u8 + RUN_LENGTH_OFFSET);
+            i += repeats;
+        }
+    }
+
+    result
+}
+
+fn parse_range(config: &MockStubConfig, memory: &[u8], arguments: &str) -> Option<(usize, usize)> {
+    let (address, length) = arguments.trim().split_once(',')?;
+    let address = u32::from_str_radix(address, 16).ok()?;
+    let length = usize::from_str_radix(length, 16).ok()?;
+
+    let offset = address.checked_sub(config.memory_base)? as usize;
+    if offset + length > memory.len() {
+        return None;
+    }
+
+    Some((offset, length))
+}
+
+fn read_memory(config: &MockStubConfig, memory: &[u8], arguments: &str, binary: bool) -> Vec<u8> {
+    let Some((offset, length)) = parse_range(config, memory, arguments) else {
+        return b"E14".to_vec();
+    };
+
+    let data = &memory[offset..offset + length];
+    let reply = match binary {
+        true => [&b"b"[..], &escape_binary(data)].concat(),
+        false => encode_hex(data).into_bytes(),
+    };
+
+    // like a real stub, which can't send more than its packet buffer holds
+    match reply.len() > config.packet_size {
+        true => b"E01".to_vec(),
+        false => reply,
+    }
+}
+
+fn read_target_description(config: &MockStubConfig, arguments: &str) -> Vec<u8> {
+    let Some(description) = &config.target_description else {
+        return Vec::new();
+    };
+    let Some((offset, length)) = arguments.split_once(',').and_then(|(offset, length)| {
+        Some((
+            usize::from_str_radix(offset, 16).ok()?,
+            usize::from_str_radix(length, 16).ok()?,
+        ))
+    }) else {
+        return b"E01".to_vec();
+    };
+
+    let description = description.as_bytes();
+    let start = offset.min(description.len());
+    let end = (offset + length).min(description.len());
+    let chunk = escape_binary(&description[start..end]);
+
+    match end < description.len() {
+        true => [&b"m"[..], &chunk].concat(),
+        false => [&b"l"[..], &chunk].concat(),
+    }
+}
+
+fn write_memory(config: &MockStubConfig, memory: &mut [u8], arguments: &str) -> Vec<u8> {
+    let Some((range, data)) = arguments.split_once(':') else {
+        return b"E01".to_vec();
+    };
+
+    match (
+        parse_range(config, memory, range),
+        decode_hex(data.as_bytes()),
+    ) {
+        (Some((offset, length)), Some(data)) if data.len() == length => {
+            memory[offset..offset + length].copy_from_slice(&data);
+            b"OK".to_vec()
+        }
+        _ => b"E14".to_vec(),
+    }
+}
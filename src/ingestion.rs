@@ -0,0 +1,572 @@
+// Ingestion of the samples in a dedicated thread, which owns the sampler and the buffers: it drains
+// the channels of the sampler as the samples arrive, feeds the trigger, records the session and
+// keeps the buffers within their limits, whatever the frame rate of the UI. The UI only reads the
+// snapshots of the buffers it publishes, which are cheap to clone.
+
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{mpsc, Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    buffer::SampleBuffer,
+    sampler::{Notification, Sample, Sampler, Status},
+    session::SessionWriter,
+    trigger::{Trigger, TriggerState},
+    SignalConfig,
+};
+
+/// How long to wait for new samples before checking the commands again.
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// Minimum time between two snapshots of the buffers.
+const PUBLISH_INTERVAL: Duration = Duration::from_millis(16);
+
+/// Limits enforced on the buffers after each batch of samples.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BufferLimits {
+    /// Seconds of samples kept, when the buffers get truncated.
+    pub keep_seconds: Option<f64>,
+    /// Memory budget for all the buffers, in bytes.
+    pub memory: Option<usize>,
+    /// Where the samples over the memory budget get spilled, rather than dropped.
+    pub spill_directory: Option<PathBuf>,
+    /// Disk budget for the spilled samples, in bytes.
+    pub disk: usize,
+}
+
+impl Default for BufferLimits {
+    fn default() -> BufferLimits {
+        BufferLimits {
+            keep_seconds: None,
+            memory: None,
+            spill_directory: None,
+            disk: usize::MAX,
+        }
+    }
+}
+
+pub enum Command {
+    /// Start ingesting from `sampler`, stopping the current one.
+    Attach(Box<dyn Sampler>),
+    Detach,
+    Pause,
+    Resume,
+    /// Move the playback, starting the buffers over, since the samples must come in time order.
+    Seek(u64),
+    SetSpeed(f64),
+    SetActiveSignals(Vec<u32>),
+    SetLimits(BufferLimits),
+    /// Signals whose metadata gets recorded along with their first sample.
+    SetSignals(Vec<SignalConfig>),
+    /// Replace the buffers, and the time of the last sample, e.g. with a session read from a file.
+    Load(HashMap<u32, SampleBuffer>, u64),
+    /// Record the session to the given writer from now on, or stop recording.
+    Record(Option<SessionWriter>),
+    /// Record the connection settings and the signals, marking the start of a new segment.
+    RecordConnection(Vec<(String, String)>, Vec<SignalConfig>),
+    Stop,
+}
+
+pub enum Event {
+    /// A notification of the sampler, already recorded.
+    Notification(Notification),
+    /// Recording failed, and was stopped.
+    RecordingError(anyhow::Error),
+    /// Spilling the samples failed, and was disabled.
+    SpillError(String),
+}
+
+/// The buffers as last published by the ingestion thread.
+#[derive(Clone, Default)]
+pub struct Snapshot {
+    pub buffers: HashMap<u32, SampleBuffer>,
+    /// Time of the last sample, in microseconds.
+    pub max_time: u64,
+}
+
+pub struct Ingestion {
+    join_handle: Option<thread::JoinHandle<()>>,
+    command_tx: mpsc::Sender<Command>,
+    events_rx: mpsc::Receiver<Event>,
+    snapshot: Arc<Mutex<Snapshot>>,
+}
+
+impl Ingestion {
+    /// Start the ingestion thread, which calls `wake_up` whenever it sends an event.
+    pub fn start(trigger: Arc<Mutex<Trigger>>, wake_up: impl Fn() + Send + 'static) -> Ingestion {
+        let (command_tx, command_rx) = mpsc::channel();
+        let (events_tx, events_rx) = mpsc::channel();
+        let snapshot = Arc::new(Mutex::new(Snapshot::default()));
+
+        let state = IngestionThread {
+            sampler: None,
+            buffers: HashMap::new(),
+            max_time: 0,
+            changed: false,
+            last_published: Instant::now(),
+            limits: BufferLimits::default(),
+            recorder: None,
+            signals: Vec::new(),
+            trigger,
+            snapshot: snapshot.clone(),
+            events_tx,
+            wake_up: Box::new(wake_up),
+        };
+
+        let join_handle = thread::spawn(move || state.run(command_rx));
+
+        Ingestion {
+            join_handle: Some(join_handle),
+            command_tx,
+            events_rx,
+            snapshot,
+        }
+    }
+
+    pub fn send(&self, command: Command) {
+        if let Err(err) = self.command_tx.send(command) {
+            log::error!("failed to send command to the ingestion thread: {}", err);
+        }
+    }
+
+    /// The events sent since the last call.
+    pub fn events(&self) -> impl Iterator<Item = Event> + '_ {
+        self.events_rx.try_iter()
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        self.snapshot.lock().unwrap().clone()
+    }
+}
+
+impl Drop for Ingestion {
+    fn drop(&mut self) {
+        self.send(Command::Stop);
+
+        if let Some(join_handle) = self.join_handle.take() {
+            if let Err(err) = join_handle.join() {
+                log::warn!("failed to join ingestion thread: {:?}", err);
+            }
+        }
+    }
+}
+
+struct IngestionThread {
+    sampler: Option<Box<dyn Sampler>>,
+    buffers: HashMap<u32, SampleBuffer>,
+    max_time: u64,
+    /// Whether the buffers changed since the last snapshot.
+    changed: bool,
+    last_published: Instant,
+    limits: BufferLimits,
+    recorder: Option<SessionWriter>,
+    signals: Vec<SignalConfig>,
+    trigger: Arc<Mutex<Trigger>>,
+    snapshot: Arc<Mutex<Snapshot>>,
+    events_tx: mpsc::Sender<Event>,
+    wake_up: Box<dyn Fn() + Send>,
+}
+
+impl IngestionThread {
+    fn run(mut self, command_rx: mpsc::Receiver<Command>) {
+        loop {
+            // without a sampler there is nothing to do but wait for the commands, once the last
+            // samples received are published
+            let command = match self.sampler {
+                None => {
+                    self.publish(true);
+                    command_rx.recv().ok()
+                }
+                Some(_) => match command_rx.try_recv() {
+                    Ok(command) => Some(command),
+                    Err(mpsc::TryRecvError::Empty) => {
+                        self.ingest();
+                        self.publish(false);
+                        continue;
+                    }
+                    Err(mpsc::TryRecvError::Disconnected) => None,
+                },
+            };
+
+            match command {
+                None | Some(Command::Stop) => break,
+                Some(command) => self.handle_command(command),
+            }
+        }
+
+        if let Some(sampler) = self.sampler.take() {
+            sampler.stop();
+        }
+        self.stop_recording();
+    }
+
+    fn handle_command(&mut self, command: Command) {
+        match command {
+            Command::Attach(sampler) => {
+                if let Some(previous_sampler) = self.sampler.replace(sampler) {
+                    log::warn!("attaching a new sampler while another one is active, stopping it");
+                    previous_sampler.stop();
+                }
+            }
+            Command::Detach => {
+                if let Some(sampler) = self.sampler.take() {
+                    sampler.stop();
+                }
+            }
+            Command::Pause => self.with_sampler(|sampler| sampler.pause()),
+            Command::Resume => self.with_sampler(|sampler| sampler.resume()),
+            Command::Seek(time) => match &self.sampler {
+                Some(sampler) => {
+                    sampler.seek(time);
+                    self.seek(time);
+                }
+                None => log::warn!("ignoring a sampler command, no sampler is attached"),
+            },
+            Command::SetSpeed(speed) => self.with_sampler(|sampler| sampler.set_speed(speed)),
+            Command::SetActiveSignals(ids) => {
+                self.with_sampler(|sampler| sampler.set_active_signals(&ids))
+            }
+            Command::SetLimits(limits) => self.limits = limits,
+            Command::SetSignals(signals) => self.signals = signals,
+            Command::Load(buffers, max_time) => {
+                self.buffers = buffers;
+                self.max_time = max_time;
+                self.changed = true;
+            }
+            Command::Record(recorder) => {
+                self.stop_recording();
+                self.recorder = recorder;
+            }
+            Command::RecordConnection(settings, signals) => {
+                self.signals = signals;
+
+                let result = self.record_connection(&settings);
+                self.check_recording(result);
+            }
+            Command::Stop => unreachable!("handled by the main loop"),
+        }
+    }
+
+    /// Start the buffers over from `time`, where the playback moved, looking for the trigger
+    /// condition again if it was.
+    fn seek(&mut self, time: u64) {
+        self.buffers.clear();
+        self.max_time = time;
+        self.changed = true;
+
+        let mut trigger = self.trigger.lock().unwrap();
+        let armed = trigger.state() != TriggerState::Disarmed;
+        trigger.reset();
+        if armed {
+            trigger.arm(time as f64 * 1e-6);
+        }
+    }
+
+    fn with_sampler(&self, f: impl FnOnce(&dyn Sampler)) {
+        match &self.sampler {
+            Some(sampler) => f(sampler.as_ref()),
+            None => log::warn!("ignoring a sampler command, no sampler is attached"),
+        }
+    }
+
+    fn send_event(&self, event: Event) {
+        if self.events_tx.send(event).is_ok() {
+            (self.wake_up)();
+        }
+    }
+
+    /// Receive the notifications and the samples from the sampler, waiting a bit for the latter.
+    fn ingest(&mut self) {
+        let Some(sampler) = &self.sampler else {
+            return;
+        };
+
+        let notifications = sampler
+            .notification_channel()
+            .try_iter()
+            .collect::<Vec<_>>();
+        let terminated = notifications.iter().any(|notification| {
+            matches!(notification, Notification::NewStatus(Status::Terminated))
+        });
+
+        let mut samples = Vec::new();
+        if terminated {
+            samples.extend(sampler.sampled_channel().try_iter());
+        } else if let Ok(sample) = sampler.sampled_channel().recv_timeout(POLL_INTERVAL) {
+            samples.push(sample);
+            samples.extend(sampler.sampled_channel().try_iter());
+        }
+
+        if terminated {
+            if let Some(sampler) = self.sampler.take() {
+                sampler.stop();
+            }
+        }
+
+        let result = self.record(&samples, &notifications);
+        self.check_recording(result);
+
+        for notification in notifications {
+            self.send_event(Event::Notification(notification));
+        }
+
+        if !samples.is_empty() {
+            self.push(samples);
+        }
+    }
+
+    fn push(&mut self, samples: Vec<Sample>) {
+        let mut trigger = self.trigger.lock().unwrap();
+
+        for (t, values) in samples {
+            for (id, y) in values {
+                self.buffers
+                    .entry(id)
+                    .or_insert_with(SampleBuffer::new)
+                    .push(t as f64 * 1e-6, y);
+
+                trigger.process_sample(id, t as f64 * 1e-6, y);
+            }
+
+            self.max_time = self.max_time.max(t);
+        }
+
+        // the frame must be captured before the buffers get truncated
+        trigger.update(self.max_time as f64 * 1e-6, &self.buffers);
+        drop(trigger);
+
+        if let Some(keep_seconds) = self.limits.keep_seconds {
+            for buffer in self.buffers.values_mut() {
+                buffer.truncate(keep_seconds);
+            }
+        }
+
+        if let Some(budget) = self.limits.memory {
+            self.enforce_memory_limit(budget);
+        }
+
+        self.changed = true;
+    }
+
+    /// Spills or drops the oldest chunks of samples, across all the signals, until the buffers fit
+    /// in the memory budget, and drops the oldest spilled ones until they fit in the disk budget.
+    fn enforce_memory_limit(&mut self, budget: usize) {
+        let mut capacity: usize = self
+            .buffers
+            .values()
+            .map(|buffer| buffer.memory_footprint().1)
+            .sum();
+
+        while capacity > budget {
+            let Some(directory) = &self.limits.spill_directory else {
+                break;
+            };
+
+            let oldest = self
+                .buffers
+                .values_mut()
+                .filter_map(|buffer| buffer.spillable_since().map(|t| (t, buffer)))
+                .min_by(|(a, _), (b, _)| a.total_cmp(b));
+
+            let Some((_, buffer)) = oldest else {
+                break;
+            };
+
+            match buffer.spill(directory) {
+                Ok(released) => capacity -= released.min(capacity),
+                Err(err) => {
+                    let message = format!("{}: {}", directory.display(), err);
+                    self.limits.spill_directory = None;
+                    self.send_event(Event::SpillError(message));
+                }
+            }
+        }
+
+        let mut disk: usize = self
+            .buffers
+            .values()
+            .map(|buffer| buffer.disk_footprint())
+            .sum();
+
+        while capacity > budget || disk > self.limits.disk {
+            let oldest = self
+                .buffers
+                .values_mut()
+                .filter_map(|buffer| buffer.time_bounds().map(|(t, _)| (t, buffer)))
+                .min_by(|(a, _), (b, _)| a.total_cmp(b));
+
+            match oldest.map(|(_, buffer)| buffer.drop_oldest_chunk()) {
+                Some((memory, disk_space)) if memory > 0 || disk_space > 0 => {
+                    capacity -= memory.min(capacity);
+                    disk -= disk_space.min(disk);
+                }
+                _ => break,
+            }
+        }
+    }
+
+    /// Publish a snapshot of the buffers if they changed, at most every `PUBLISH_INTERVAL` unless
+    /// `force`d.
+    fn publish(&mut self, force: bool) {
+        if !self.changed || (!force && self.last_published.elapsed() < PUBLISH_INTERVAL) {
+            return;
+        }
+
+        let snapshot = Snapshot {
+            buffers: self.buffers.clone(),
+            max_time: self.max_time,
+        };
+        *self.snapshot.lock().unwrap() = snapshot;
+
+        self.changed = false;
+        self.last_published = Instant::now();
+    }
+
+    fn record_connection(&mut self, settings: &[(String, String)]) -> anyhow::Result<()> {
+        let Some(recorder) = &mut self.recorder else {
+            return Ok(());
+        };
+
+        recorder.write_settings(settings)?;
+        for signal in &self.signals {
+            recorder.write_signal(signal)?;
+        }
+
+        recorder.flush()
+    }
+
+    /// Record the samples and the notifications received, with the metadata of the signals not
+    /// recorded yet.
+    fn record(&mut self, samples: &[Sample], notifications: &[Notification]) -> anyhow::Result<()> {
+        let Some(recorder) = &mut self.recorder else {
+            return Ok(());
+        };
+
+        if samples.is_empty() && notifications.is_empty() {
+            return Ok(());
+        }
+
+        for notification in notifications {
+            recorder.write_notification(notification)?;
+        }
+
+        for sample in samples {
+            for &(id, _) in &sample.1 {
+                if !recorder.has_signal(id) {
+                    if let Some(signal) = self.signals.iter().find(|signal| signal.id == id) {
+                        recorder.write_signal(signal)?;
+                    }
+                }
+            }
+
+            recorder.write_sample(sample)?;
+        }
+
+        recorder.flush()
+    }
+
+    /// Stop recording if `result` is an error, reporting it.
+    fn check_recording(&mut self, result: anyhow::Result<()>) {
+        if let Err(err) = result {
+            self.recorder = None;
+            self.send_event(Event::RecordingError(err));
+        }
+    }
+
+    fn stop_recording(&mut self) {
+        if let Some(mut recorder) = self.recorder.take() {
+            if let Err(err) = recorder.flush() {
+                log::error!("failed to flush the session file: {:?}", err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        sampler::{FakeSampler, ReplaySampler},
+        trigger::TriggerSettings,
+        utils::temporary_path,
+    };
+
+    #[test]
+    fn test_ingestion() {
+        let trigger = Arc::new(Mutex::new(Trigger::new(TriggerSettings::default())));
+        let ingestion = Ingestion::start(trigger, || {});
+
+        let sampler = FakeSampler::start(1000.0);
+        sampler.set_active_signals(&[0, 1]);
+        ingestion.send(Command::Attach(Box::new(sampler)));
+
+        // the samples get ingested without the snapshots being read
+        thread::sleep(Duration::from_millis(200));
+
+        let snapshot = ingestion.snapshot();
+        assert!(snapshot.max_time > 0);
+        assert_eq!(snapshot.buffers.len(), 2);
+        assert!(snapshot.buffers.values().all(|buffer| !buffer.is_empty()));
+
+        let statuses = ingestion
+            .events()
+            .filter_map(|event| match event {
+                Event::Notification(Notification::NewStatus(status)) => Some(status),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(statuses, vec![Status::Sampling]);
+
+        ingestion.send(Command::Detach);
+        ingestion.send(Command::Load(HashMap::new(), 0));
+        thread::sleep(Duration::from_millis(50));
+
+        let snapshot = ingestion.snapshot();
+        assert_eq!(snapshot.max_time, 0);
+        assert!(snapshot.buffers.is_empty());
+    }
+
+    #[test]
+    fn test_seek_while_sampling() {
+        let filename = temporary_path("ingestion-seek", "session");
+        {
+            let mut writer = SessionWriter::create(&filename).unwrap();
+            writer.write_settings(&[]).unwrap();
+            writer
+                .write_signal(&SignalConfig::new(1, "x".into(), None))
+                .unwrap();
+            for i in 0..10_000 {
+                writer
+                    .write_sample(&(i * 100, vec![(1, i as f64)]))
+                    .unwrap();
+            }
+            writer.flush().unwrap();
+        }
+
+        let trigger = Arc::new(Mutex::new(Trigger::new(TriggerSettings::default())));
+        let ingestion = Ingestion::start(trigger, || {});
+
+        let sampler = ReplaySampler::start(&filename, 1.0, 1.0).unwrap();
+        std::fs::remove_file(&filename).unwrap();
+        ingestion.send(Command::Attach(Box::new(sampler)));
+
+        thread::sleep(Duration::from_millis(300));
+        assert!(ingestion.snapshot().max_time > 200_000);
+
+        // back to an earlier time, while the samples keep coming
+        ingestion.send(Command::Seek(50_000));
+        thread::sleep(Duration::from_millis(100));
+
+        let snapshot = ingestion.snapshot();
+        assert!(snapshot.max_time >= 50_000 && snapshot.max_time < 200_000);
+
+        let samples = snapshot.buffers[&1].samples().collect::<Vec<_>>();
+        assert_eq!(samples[0].x, 50_000.0 * 1e-6);
+        assert!(samples.windows(2).all(|s| s[0].x < s[1].x));
+    }
+}
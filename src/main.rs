@@ -1,36 +1,97 @@
-use std::path::PathBuf;
-use std::{collections::HashMap, sync::Arc};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
 
 use eframe::egui;
 
 use egui::Color32;
 
-use egui_file_dialog::FileDialog;
+use anyhow::Context;
+use egui_file_dialog::{DialogMode, FileDialog};
 
 mod buffer;
+mod cursors;
 mod export;
 mod gdbremote;
+mod ingestion;
 mod openocd;
+mod panes;
 mod sampler;
+mod session;
+mod spectrogram;
+mod spectrum;
+mod trigger;
 mod ttstream;
 mod utils;
+mod xyplot;
 
 use buffer::SampleBuffer;
-use sampler::{FakeSampler, MemSampler, RTTSampler, Sampler};
+use cursors::{CursorId, Cursors};
+use export::{Alignment, ExportFormat, ExportLayout, ExportOptions};
+use gdbremote::{GDBServerKind, WatchpointKind};
+use ingestion::{BufferLimits, Ingestion};
+use panes::{AxisMapping, PlotPane, YAxis};
+use sampler::{
+    FakeSampler, GDBRTTSampler, GDBRTTSettings, MemSampler, MemSamplerSettings, RTTSampler,
+    RTTSettings, RTTTimeSource, RTTTimestampUnit, ReplaySampler, Sampler, WatchpointCapture,
+};
+use session::{Record, SessionReader, SessionWriter};
+use spectrogram::{Colormap, Spectrogram, SpectrogramSettings};
+use spectrum::{Spectrum, SpectrumSettings, WindowFunction};
+use trigger::{
+    CapturedFrame, Polarity, Slope, Trigger, TriggerCondition, TriggerMode, TriggerSettings,
+    TriggerState, WindowEvent,
+};
+use xyplot::XYPlotSettings;
+
+/// Lines of the log panel kept in memory, older ones get dropped.
+const MAX_LOG_LINES: usize = 10_000;
+
+/// Points drawn at most in the X-Y plot.
+const MAX_XY_POINTS: usize = 20_000;
+
+/// Steps in which the older points of the X-Y plot fade out.
+const XY_FADE_STEPS: usize = 16;
+
+/// Time between two repaints while sampling; the samples are ingested in the background anyway.
+const REPAINT_INTERVAL: Duration = Duration::from_millis(33);
 
 #[derive(Debug, PartialEq, Eq)]
 enum SamplingMethod {
     MemorySamping,
     RTT,
+    RTTOverGDB,
     Simulated,
+    Replay,
 }
 
+/// Time range, settings and number of samples of the signal a spectrum or spectrogram was
+/// computed from, to compute it again only when any of them changes.
+type AnalysisKey<S> = ((f64, f64), S, usize);
+
+/// Time range of the samples to export.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum ExportRange {
+    All,
+    PlotView,
+    /// Range selected dragging on the plot with shift held.
+    Selection,
+    Manual,
+}
+
+#[derive(Clone)]
 pub struct SignalConfig {
     id: u32,
     name: String,
     color: Color32,
     enabled: bool,
     scale: f64,
+    /// Index of the plot pane the signal is drawn in.
+    pane: usize,
+    axis: YAxis,
 }
 
 impl SignalConfig {
@@ -41,6 +102,8 @@ impl SignalConfig {
             color: color.unwrap_or_else(|| utils::color_for_id(id)),
             enabled: false,
             scale: 1.0.into(),
+            pane: 0,
+            axis: YAxis::Left,
         }
     }
 }
@@ -53,14 +116,54 @@ struct OCDScope {
     error_title: String,
     error_message: String,
 
+    show_log_panel: bool,
+    log_lines: Vec<String>,
+    /// Times, in seconds, and descriptions of the target stops, shown as plot markers.
+    stop_markers: Vec<(f64, String)>,
+
     plot_auto_follow: bool,
     plot_auto_follow_time: f64,
+    plot_panes: Vec<PlotPane>,
+    cursors: Cursors,
+
+    show_xy_plot: bool,
+    xy_plot: XYPlotSettings,
+
+    show_spectrum: bool,
+    spectrum: SpectrumSettings,
+    /// Last spectrum computed, kept while not live.
+    spectrum_result: Option<Spectrum>,
+    spectrum_key: Option<AnalysisKey<SpectrumSettings>>,
+
+    show_spectrogram: bool,
+    spectrogram: SpectrogramSettings,
+    /// Last spectrogram computed, with its image in the texture.
+    spectrogram_result: Option<Spectrogram>,
+    spectrogram_key: Option<AnalysisKey<SpectrogramSettings>>,
+    spectrogram_texture: Option<egui::TextureHandle>,
 
     buffer_auto_truncate: bool,
     buffer_auto_truncate_at: f64,
+    buffer_memory_limit: bool,
+    /// Memory budget for all the buffers, in MiB.
+    buffer_memory_limit_at: usize,
+    /// Whether the samples over the memory budget are spilled to disk rather than dropped.
+    buffer_spill: bool,
+    buffer_spill_directory: PathBuf,
+    /// Disk budget for the spilled samples, in GiB.
+    buffer_disk_limit_at: usize,
+
+    trigger_enabled: bool,
+    /// Shared with the ingestion thread, which feeds it the samples.
+    trigger: Arc<Mutex<Trigger>>,
+
+    /// Owns the sampler and the buffers, of which `samples` is the last snapshot.
+    ingestion: Ingestion,
+    /// Limits of the buffers last sent to the ingestion thread.
+    ingestion_limits: BufferLimits,
 
     sampling_method: SamplingMethod,
-    current_sampler: Option<Box<dyn Sampler>>,
+    sampler_connected: bool,
     current_sampler_status: Option<sampler::Status>,
     last_sampler_info: String,
     signals: Vec<SignalConfig>,
@@ -68,37 +171,113 @@ struct OCDScope {
     max_time: u64,
 
     gdb_address: String,
+    gdb_server: GDBServerKind,
+    gdb_resume_target: bool,
+    /// Read nearby variables with a single request, along with the memory between them.
+    gdb_merge_reads: bool,
+    /// Largest gap between the variables merged, in bytes.
+    gdb_max_read_gap: u32,
+    watchpoint_capture_enabled: bool,
+    watchpoint_kind: WatchpointKind,
+    watchpoint_address: u32,
+    /// Bytes watched, as wide as the variable.
+    watchpoint_length: usize,
+    watchpoint_pre_trigger: f64,
+    watchpoint_resume_after_hit: bool,
     elf_file_dialog: FileDialog,
     elf_filename: Option<PathBuf>,
     telnet_address: String,
     sample_rate: f64,
+    rtt_search_address: u32,
+    rtt_search_size: u32,
     rtt_polling_interval: u32,
     rtt_relative_time: bool,
+    rtt_halt_to_synchronize: bool,
+    rtt_halt_to_read: bool,
+    rtt_timestamp_unit: RTTTimestampUnit,
+    rtt_untimed_time_source: RTTTimeSource,
+    replay_file_dialog: FileDialog,
+    replay_filename: Option<PathBuf>,
+    replay_speed: f64,
+    /// Length of the recording being replayed, in seconds.
+    replay_duration: Option<f64>,
 
     memory_address_to_add: u32,
 
     export_file_dialog: FileDialog,
+    export_format: ExportFormat,
+    export_layout: ExportLayout,
+    export_range: ExportRange,
+    export_manual_range: (f64, f64),
+    export_decimation: usize,
+    export_selection: Option<(f64, f64)>,
+    /// Where the drag selecting the export range started.
+    export_selection_start: Option<f64>,
+    /// Time range shown in the plot, at the last frame.
+    plot_view_range: Option<(f64, f64)>,
+
+    /// Used both to choose the file to record a session to and to open a recorded one.
+    session_file_dialog: FileDialog,
+    recording_filename: Option<PathBuf>,
 }
 
 impl OCDScope {
-    pub fn new() -> OCDScope {
+    pub fn new(ctx: egui::Context) -> OCDScope {
+        let trigger = Arc::new(Mutex::new(Trigger::new(TriggerSettings::default())));
+        let ingestion = Ingestion::start(trigger.clone(), move || ctx.request_repaint());
+
         OCDScope {
             show_connect_dialog: true,
             show_add_address_dialog: false,
             show_error_dialog: false,
             error_title: "".into(),
             error_message: "".into(),
+            show_log_panel: false,
+            log_lines: Vec::new(),
+            stop_markers: Vec::new(),
             plot_auto_follow: false,
             plot_auto_follow_time: 1.0,
+            plot_panes: vec![PlotPane::new()],
+            cursors: Cursors::default(),
+            show_xy_plot: false,
+            xy_plot: XYPlotSettings::default(),
+            show_spectrum: false,
+            spectrum: SpectrumSettings::default(),
+            spectrum_result: None,
+            spectrum_key: None,
+            show_spectrogram: false,
+            spectrogram: SpectrogramSettings::default(),
+            spectrogram_result: None,
+            spectrogram_key: None,
+            spectrogram_texture: None,
             buffer_auto_truncate: true,
             buffer_auto_truncate_at: 10.0,
-            current_sampler: None,
+            buffer_memory_limit: true,
+            buffer_memory_limit_at: 2048,
+            buffer_spill: false,
+            buffer_spill_directory: std::env::temp_dir().join("ocdscope"),
+            buffer_disk_limit_at: 64,
+            trigger_enabled: false,
+            trigger,
+            ingestion,
+            ingestion_limits: BufferLimits::default(),
+            sampler_connected: false,
             current_sampler_status: None,
             last_sampler_info: "".into(),
             samples: HashMap::new(),
             max_time: 0,
             sampling_method: SamplingMethod::Simulated,
             gdb_address: "127.0.0.1:3333".into(),
+            gdb_server: GDBServerKind::OpenOCD,
+            gdb_resume_target: true,
+            gdb_merge_reads: false,
+            gdb_max_read_gap: 32,
+            watchpoint_capture_enabled: false,
+            watchpoint_kind: WatchpointKind::Write,
+            watchpoint_address: 0x20000000,
+            watchpoint_length: 4,
+            watchpoint_pre_trigger: 1.0,
+            watchpoint_resume_after_hit: false,
             elf_file_dialog: FileDialog::new()
                 .title("Select an ELF file")
                 .add_file_filter(
@@ -109,19 +288,415 @@ impl OCDScope {
             elf_filename: None,
             telnet_address: "127.0.0.1:4444".into(),
             sample_rate: 1000.0,
+            rtt_search_address: 0x20000000,
+            rtt_search_size: 128 * 1024,
             rtt_polling_interval: 1,
             rtt_relative_time: false,
+            rtt_halt_to_synchronize: true,
+            rtt_halt_to_read: false,
+            rtt_timestamp_unit: RTTTimestampUnit::Microseconds,
+            rtt_untimed_time_source: RTTTimeSource::HostTime,
+            replay_file_dialog: FileDialog::new()
+                .title("Select a recording to replay")
+                .add_file_filter(
+                    "Recordings (*.session, *.csv, *.npy)",
+                    Arc::new(|path| {
+                        matches!(
+                            path.extension().and_then(|s| s.to_str()),
+                            Some("session" | "csv" | "npy")
+                        )
+                    }),
+                )
+                .default_file_filter("Recordings (*.session, *.csv, *.npy)"),
+            replay_filename: None,
+            replay_speed: 1.0,
+            replay_duration: None,
             signals: Vec::new(),
             memory_address_to_add: 0xBEEF1010,
             export_file_dialog: FileDialog::new()
                 .title("Save the exported file")
                 .allow_file_overwrite(true),
+            export_format: ExportFormat::Csv,
+            export_layout: ExportLayout::Aligned(Alignment::Linear),
+            export_range: ExportRange::All,
+            export_manual_range: (0.0, 1.0),
+            export_decimation: 1,
+            export_selection: None,
+            export_selection_start: None,
+            plot_view_range: None,
+            session_file_dialog: FileDialog::new()
+                .add_file_filter(
+                    "Session files (*.session)",
+                    Arc::new(|path| path.extension().unwrap_or_default() == "session"),
+                )
+                .default_file_filter("Session files (*.session)")
+                .allow_file_overwrite(true),
+            recording_filename: None,
         }
     }
 
     fn reset_buffer(&mut self) {
         self.samples.clear();
+        self.stop_markers.clear();
         self.max_time = 0;
+        self.ingestion
+            .send(ingestion::Command::Load(HashMap::new(), 0));
+
+        let mut trigger = self.trigger.lock().unwrap();
+        trigger.reset();
+        if self.trigger_enabled {
+            trigger.arm(0.0);
+        }
+    }
+
+    /// Limits of the buffers, as set in the sidebar.
+    fn buffer_limits(&self) -> BufferLimits {
+        BufferLimits {
+            keep_seconds: self
+                .buffer_auto_truncate
+                .then_some(self.buffer_auto_truncate_at),
+            memory: self
+                .buffer_memory_limit
+                .then_some(self.buffer_memory_limit_at << 20),
+            spill_directory: self
+                .buffer_spill
+                .then(|| self.buffer_spill_directory.clone()),
+            disk: self.buffer_disk_limit_at << 30,
+        }
+    }
+
+    /// Append `text` to the log; the last line is the one still being written, which the
+    /// next text continues until a newline.
+    fn append_log(&mut self, text: &str) {
+        let mut pieces = text.split('\n');
+        let first_piece = pieces.next().unwrap_or_default();
+
+        match self.log_lines.last_mut() {
+            Some(open_line) => open_line.push_str(first_piece),
+            None => self.log_lines.push(first_piece.to_owned()),
+        }
+        self.log_lines.extend(pieces.map(str::to_owned));
+
+        if self.log_lines.len() > MAX_LOG_LINES {
+            self.log_lines.drain(..self.log_lines.len() - MAX_LOG_LINES);
+        }
+    }
+
+    /// Connection settings, as recorded in the sessions.
+    fn connection_settings(&self) -> Vec<(String, String)> {
+        let mut settings = vec![
+            ("sampling_method", format!("{:?}", self.sampling_method)),
+            ("gdb_address", self.gdb_address.clone()),
+            ("gdb_server", format!("{:?}", self.gdb_server)),
+            ("telnet_address", self.telnet_address.clone()),
+            ("sample_rate", self.sample_rate.to_string()),
+            ("rtt_search_address", self.rtt_search_address.to_string()),
+            ("rtt_search_size", self.rtt_search_size.to_string()),
+            (
+                "rtt_polling_interval",
+                self.rtt_polling_interval.to_string(),
+            ),
+            (
+                "rtt_timestamp_unit",
+                format!("{:?}", self.rtt_timestamp_unit),
+            ),
+            (
+                "rtt_untimed_time_source",
+                format!("{:?}", self.rtt_untimed_time_source),
+            ),
+            ("rtt_relative_time", self.rtt_relative_time.to_string()),
+            (
+                "rtt_halt_to_synchronize",
+                self.rtt_halt_to_synchronize.to_string(),
+            ),
+            ("rtt_halt_to_read", self.rtt_halt_to_read.to_string()),
+            ("gdb_resume_target", self.gdb_resume_target.to_string()),
+            ("gdb_merge_reads", self.gdb_merge_reads.to_string()),
+            ("gdb_max_read_gap", self.gdb_max_read_gap.to_string()),
+            (
+                "watchpoint_capture_enabled",
+                self.watchpoint_capture_enabled.to_string(),
+            ),
+            ("watchpoint_kind", format!("{:?}", self.watchpoint_kind)),
+            ("watchpoint_address", self.watchpoint_address.to_string()),
+            ("watchpoint_length", self.watchpoint_length.to_string()),
+            (
+                "watchpoint_pre_trigger",
+                self.watchpoint_pre_trigger.to_string(),
+            ),
+            (
+                "watchpoint_resume_after_hit",
+                self.watchpoint_resume_after_hit.to_string(),
+            ),
+        ];
+
+        if let Some(elf_filename) = &self.elf_filename {
+            settings.push(("elf_filename", elf_filename.display().to_string()));
+        }
+        if let Some(replay_filename) = &self.replay_filename {
+            settings.push(("replay_filename", replay_filename.display().to_string()));
+        }
+        settings.push(("replay_speed", self.replay_speed.to_string()));
+
+        settings
+            .into_iter()
+            .map(|(key, value)| (key.to_owned(), value))
+            .collect()
+    }
+
+    /// Restore the connection settings recorded in a session, ignoring the unknown or invalid
+    /// ones.
+    fn restore_connection_settings(&mut self, settings: &[(String, String)]) {
+        fn restore<T: std::str::FromStr>(setting: &mut T, value: &str) {
+            match value.parse() {
+                Ok(parsed) => *setting = parsed,
+                Err(_) => log::warn!("ignoring invalid session setting value {:?}", value),
+            }
+        }
+
+        for (key, value) in settings {
+            match key.as_str() {
+                "sampling_method" => {
+                    self.sampling_method = match value.as_str() {
+                        "MemorySamping" => SamplingMethod::MemorySamping,
+                        "RTT" => SamplingMethod::RTT,
+                        "RTTOverGDB" => SamplingMethod::RTTOverGDB,
+                        "Simulated" => SamplingMethod::Simulated,
+                        "Replay" => SamplingMethod::Replay,
+                        _ => {
+                            log::warn!("ignoring invalid session setting value {:?}", value);
+                            continue;
+                        }
+                    }
+                }
+                "gdb_address" => self.gdb_address = value.clone(),
+                "gdb_server" => match value.as_str() {
+                    "OpenOCD" => self.gdb_server = GDBServerKind::OpenOCD,
+                    "Generic" => self.gdb_server = GDBServerKind::Generic,
+                    _ => log::warn!("ignoring invalid session setting value {:?}", value),
+                },
+                "telnet_address" => self.telnet_address = value.clone(),
+                "sample_rate" => {
+                    self.sample_rate = value.parse().unwrap_or(self.sample_rate);
+                }
+                "rtt_search_address" => {
+                    self.rtt_search_address = value.parse().unwrap_or(self.rtt_search_address);
+                }
+                "rtt_search_size" => {
+                    self.rtt_search_size = value.parse().unwrap_or(self.rtt_search_size);
+                }
+                "rtt_polling_interval" => {
+                    self.rtt_polling_interval = value.parse().unwrap_or(self.rtt_polling_interval);
+                }
+                "rtt_timestamp_unit" => match utils::parse_variant(value) {
+                    ("Microseconds", None) => {
+                        self.rtt_timestamp_unit = RTTTimestampUnit::Microseconds
+                    }
+                    ("Nanoseconds", None) => {
+                        self.rtt_timestamp_unit = RTTTimestampUnit::Nanoseconds
+                    }
+                    ("CpuCycles", Some(mhz)) => {
+                        self.rtt_timestamp_unit = RTTTimestampUnit::CpuCycles(mhz)
+                    }
+                    _ => log::warn!("ignoring invalid session setting value {:?}", value),
+                },
+                "rtt_untimed_time_source" => match utils::parse_variant(value) {
+                    ("NominalRate", Some(rate)) => {
+                        self.rtt_untimed_time_source = RTTTimeSource::NominalRate(rate)
+                    }
+                    ("HostTime", None) => self.rtt_untimed_time_source = RTTTimeSource::HostTime,
+                    ("SampleIndex", None) => {
+                        self.rtt_untimed_time_source = RTTTimeSource::SampleIndex
+                    }
+                    _ => log::warn!("ignoring invalid session setting value {:?}", value),
+                },
+                "rtt_relative_time" => restore(&mut self.rtt_relative_time, value),
+                "rtt_halt_to_synchronize" => restore(&mut self.rtt_halt_to_synchronize, value),
+                "rtt_halt_to_read" => restore(&mut self.rtt_halt_to_read, value),
+                "gdb_resume_target" => restore(&mut self.gdb_resume_target, value),
+                "gdb_merge_reads" => restore(&mut self.gdb_merge_reads, value),
+                "gdb_max_read_gap" => restore(&mut self.gdb_max_read_gap, value),
+                "watchpoint_capture_enabled" => {
+                    restore(&mut self.watchpoint_capture_enabled, value)
+                }
+                "watchpoint_kind" => match value.as_str() {
+                    "Write" => self.watchpoint_kind = WatchpointKind::Write,
+                    "Read" => self.watchpoint_kind = WatchpointKind::Read,
+                    "Access" => self.watchpoint_kind = WatchpointKind::Access,
+                    _ => log::warn!("ignoring invalid session setting value {:?}", value),
+                },
+                "watchpoint_address" => restore(&mut self.watchpoint_address, value),
+                "watchpoint_length" => restore(&mut self.watchpoint_length, value),
+                "watchpoint_pre_trigger" => restore(&mut self.watchpoint_pre_trigger, value),
+                "watchpoint_resume_after_hit" => {
+                    restore(&mut self.watchpoint_resume_after_hit, value)
+                }
+                "elf_filename" => self.elf_filename = Some(PathBuf::from(value)),
+                "replay_filename" => self.replay_filename = Some(PathBuf::from(value)),
+                "replay_speed" => restore(&mut self.replay_speed, value),
+                _ => log::warn!("ignoring unknown session setting {:?}", key),
+            }
+        }
+    }
+
+    fn start_recording(&mut self, filename: PathBuf) -> anyhow::Result<()> {
+        let recorder = SessionWriter::create(&filename)?;
+
+        log::info!("recording session to {:?}", filename);
+
+        self.ingestion
+            .send(ingestion::Command::Record(Some(recorder)));
+        self.recording_filename = Some(filename);
+
+        self.record_connection();
+
+        Ok(())
+    }
+
+    /// Record the connection settings and the signals; each connection starts over the time
+    /// base of the samples, so a new settings record also marks the start of a new segment.
+    fn record_connection(&mut self) {
+        self.ingestion.send(ingestion::Command::RecordConnection(
+            self.connection_settings(),
+            self.signals.clone(),
+        ));
+    }
+
+    fn stop_recording(&mut self) {
+        self.ingestion.send(ingestion::Command::Record(None));
+        self.recording_filename = None;
+    }
+
+    /// Load a recorded session into the plot, replacing the current data.
+    fn open_session(&mut self, filename: &Path) -> anyhow::Result<()> {
+        let records = SessionReader::open(filename)?.collect::<anyhow::Result<Vec<_>>>()?;
+
+        self.reset_buffer();
+        self.signals.clear();
+        self.log_lines.clear();
+
+        // the segments recorded after a reconnection are placed one after the other
+        let mut time_offset = 0;
+
+        for record in records {
+            match record {
+                Record::Settings(settings) => {
+                    time_offset = self.max_time;
+                    self.restore_connection_settings(&settings);
+                }
+                Record::Signal(signal) => {
+                    // the metadata of a signal might be recorded again after a reconnection
+                    self.signals.retain(|s| s.id != signal.id);
+
+                    let mut config = SignalConfig::new(signal.id, signal.name, Some(signal.color));
+                    config.scale = signal.scale;
+                    config.enabled = signal.enabled;
+                    self.signals.push(config);
+                }
+                Record::Sample((t, values)) => {
+                    let t = t + time_offset;
+                    for (id, y) in values {
+                        self.samples
+                            .entry(id)
+                            .or_insert_with(SampleBuffer::new)
+                            .push(t as f64 * 1e-6, y);
+                    }
+
+                    self.max_time = self.max_time.max(t);
+                }
+                Record::Notification(notification) => match notification {
+                    sampler::Notification::NewStatus(_) => {}
+                    sampler::Notification::Info(message) => self.last_sampler_info = message,
+                    sampler::Notification::Error(message) => {
+                        self.append_log(&format!("Sampler error: {}\n", message));
+                    }
+                    sampler::Notification::ConsoleOutput(text) => self.append_log(&text),
+                    sampler::Notification::TargetStopped(time, description) => {
+                        self.log_target_stop(time.map(|t| t + time_offset), description)
+                    }
+                },
+            }
+        }
+
+        self.ingestion.send(ingestion::Command::Load(
+            self.samples.clone(),
+            self.max_time,
+        ));
+        self.ingestion
+            .send(ingestion::Command::SetSignals(self.signals.clone()));
+
+        log::info!(
+            "opened session {:?}, with {} signals",
+            filename,
+            self.signals.len()
+        );
+
+        Ok(())
+    }
+
+    fn log_target_stop(&mut self, time: Option<u64>, description: String) {
+        match time {
+            Some(time) => {
+                let time = time as f64 * 1e-6;
+                self.append_log(&format!("[{:.6} s] {}\n", time, description));
+                self.stop_markers.push((time, description.clone()));
+            }
+            None => self.append_log(&format!("{}\n", description)),
+        }
+
+        self.last_sampler_info = description;
+    }
+
+    /// Export the enabled signals, in the chosen format.
+    fn export(&mut self, filename: &Path) {
+        let range = match self.export_range {
+            ExportRange::All => None,
+            ExportRange::PlotView => self.plot_view_range,
+            ExportRange::Selection => match self.export_selection {
+                Some(selection) => Some(selection),
+                None => {
+                    self.show_error(
+                        "Nothing to export".into(),
+                        "Select the time range to export dragging on the plot with shift held"
+                            .into(),
+                    );
+                    return;
+                }
+            },
+            ExportRange::Manual => Some(self.export_manual_range),
+        };
+
+        let options = ExportOptions {
+            layout: self.export_layout,
+            range,
+            decimation: self.export_decimation,
+        };
+
+        let signals = self
+            .signals
+            .iter()
+            .filter(|signal| signal.enabled)
+            .cloned()
+            .collect::<Vec<_>>();
+
+        // the format is the chosen one, whatever the extension; it's added only when missing
+        let format = self.export_format;
+        let mut filename = filename.to_path_buf();
+        if filename.extension().is_none() {
+            filename.set_extension(format.extension());
+        }
+
+        log::info!("exporting {} to {:?}", format.description(), filename);
+
+        match export::write(format, &filename, &signals, &self.samples, &options) {
+            Ok(_) => log::info!("export successful"),
+            Err(err) => {
+                self.show_error(
+                    format!("{} export error", format.description()),
+                    format!("{:?}", err),
+                );
+            }
+        }
     }
 
     fn any_dialog_visible(&self) -> bool {
@@ -153,16 +728,23 @@ impl OCDScope {
     }
 
     fn handle_messages(&mut self, ctx: &egui::Context) {
-        let mut sampler_terminated = false;
+        let limits = self.buffer_limits();
+        if limits != self.ingestion_limits {
+            self.ingestion
+                .send(ingestion::Command::SetLimits(limits.clone()));
+            self.ingestion_limits = limits;
+        }
 
-        if let Some(sampler) = self.current_sampler.take() {
-            while let Ok(notification) = sampler.notification_channel().try_recv() {
-                match notification {
+        let events = self.ingestion.events().collect::<Vec<_>>();
+
+        for event in events {
+            match event {
+                ingestion::Event::Notification(notification) => match notification {
                     sampler::Notification::NewStatus(status) => {
                         self.current_sampler_status = Some(status);
 
                         if status == sampler::Status::Terminated {
-                            sampler_terminated = true;
+                            self.sampler_connected = false;
                         }
                     }
                     sampler::Notification::Info(message) => {
@@ -171,87 +753,1239 @@ impl OCDScope {
                     sampler::Notification::Error(message) => {
                         self.show_error("Sampler error".into(), message);
                     }
+                    sampler::Notification::ConsoleOutput(text) => {
+                        self.append_log(&text);
+                    }
+                    sampler::Notification::TargetStopped(time, description) => {
+                        self.log_target_stop(time, description);
+                    }
+                },
+                ingestion::Event::RecordingError(err) => {
+                    self.recording_filename = None;
+                    self.show_error("Session recording error".into(), format!("{:?}", err));
+                }
+                ingestion::Event::SpillError(message) => {
+                    self.buffer_spill = false;
+                    self.show_error("Cannot spill samples to disk".into(), message);
+                }
+            }
+        }
+
+        let snapshot = self.ingestion.snapshot();
+        self.samples = snapshot.buffers;
+        self.max_time = snapshot.max_time;
+
+        // the ingestion thread wakes the UI up on its events, the plot just needs refreshing
+        if self.sampler_connected {
+            ctx.request_repaint_after(REPAINT_INTERVAL);
+        }
+    }
+
+    fn rtt_settings(&self) -> RTTSettings {
+        RTTSettings {
+            search_address: self.rtt_search_address,
+            search_size: self.rtt_search_size,
+            polling_interval: self.rtt_polling_interval,
+            timestamp_unit: self.rtt_timestamp_unit,
+            untimed_time_source: self.rtt_untimed_time_source,
+            relative_time: self.rtt_relative_time,
+        }
+    }
+
+    fn try_connect_sampler(&mut self) -> anyhow::Result<Box<dyn Sampler>> {
+        self.replay_duration = None;
+
+        let sampler: Box<dyn Sampler> = match self.sampling_method {
+            SamplingMethod::Simulated => Box::new(FakeSampler::start(self.sample_rate)),
+            SamplingMethod::MemorySamping => Box::new(MemSampler::start(
+                &self.gdb_address,
+                (self.gdb_server == GDBServerKind::OpenOCD).then_some(&self.telnet_address),
+                self.elf_filename.clone(),
+                MemSamplerSettings {
+                    gdb_server: self.gdb_server,
+                    resume_target: self.gdb_resume_target,
+                    rate: self.sample_rate,
+                    max_read_gap: match self.gdb_merge_reads {
+                        true => self.gdb_max_read_gap,
+                        false => 0,
+                    },
+                    watchpoint_capture: self.watchpoint_capture_enabled.then_some(
+                        WatchpointCapture {
+                            kind: self.watchpoint_kind,
+                            address: self.watchpoint_address,
+                            length: self.watchpoint_length,
+                            pre_trigger: std::time::Duration::from_secs_f64(
+                                self.watchpoint_pre_trigger,
+                            ),
+                            resume_after_hit: self.watchpoint_resume_after_hit,
+                        },
+                    ),
+                },
+            )?),
+            SamplingMethod::RTT => Box::new(RTTSampler::start(
+                &self.telnet_address,
+                self.rtt_halt_to_synchronize,
+                self.rtt_settings(),
+            )?),
+            SamplingMethod::RTTOverGDB => Box::new(GDBRTTSampler::start(
+                &self.gdb_address,
+                GDBRTTSettings {
+                    gdb_server: self.gdb_server,
+                    halt_to_read: self.rtt_halt_to_read,
+                    rtt: self.rtt_settings(),
+                },
+            )?),
+            SamplingMethod::Replay => {
+                let filename = self
+                    .replay_filename
+                    .as_ref()
+                    .context("no recording selected to replay")?;
+                let sampler = ReplaySampler::start(filename, self.sample_rate, self.replay_speed)?;
+                self.replay_duration = Some(sampler.duration() as f64 * 1e-6);
+
+                Box::new(sampler)
+            }
+        };
+
+        self.reset_buffer();
+
+        // we expect that there is currently no sampler active, we assert
+        // this in debug mode and try to fix the incident in release mode
+        debug_assert!(!self.sampler_connected);
+        if self.sampler_connected {
+            log::warn!("found an active sampler before connecting a new one, trying to stop it");
+            self.ingestion.send(ingestion::Command::Detach);
+        }
+
+        self.signals = sampler
+            .available_signals()
+            .into_iter()
+            .map(|(id, name)| SignalConfig::new(id, name, None))
+            .collect();
+
+        if let Some(first) = self.signals.iter_mut().next() {
+            first.enabled = true;
+            sampler.set_active_signals(&[first.id]);
+        }
+
+        Ok(sampler)
+    }
+
+    /// Remove a plot pane, moving its signals to the one above, if any.
+    fn remove_plot_pane(&mut self, index: usize) {
+        self.plot_panes.remove(index);
+
+        for signal in &mut self.signals {
+            if signal.pane >= index && signal.pane > 0 {
+                signal.pane -= 1;
+            }
+        }
+    }
+
+    /// Show the signals of a pane, which can be dragged to another one; returns whether the pane
+    /// has to be removed.
+    fn show_pane_header(&mut self, ui: &mut egui::Ui, index: usize) -> bool {
+        let mut remove = false;
+
+        ui.horizontal(|ui| {
+            let mut empty = true;
+
+            for signal in &self.signals {
+                if !signal.enabled || signal.pane != index {
+                    continue;
+                }
+
+                let label = match signal.axis {
+                    YAxis::Left => signal.name.clone(),
+                    YAxis::Right => format!("{} (right axis)", signal.name),
+                };
+
+                let id = egui::Id::new(("pane-signal", signal.id));
+                ui.dnd_drag_source(id, signal.id, |ui| ui.colored_label(signal.color, label))
+                    .response
+                    .on_hover_text("Drag onto another pane to move the signal there");
+
+                empty = false;
+            }
+
+            if empty {
+                ui.weak("Drag signals here from the sidebar or the other panes");
+            }
+
+            if self.plot_panes.len() > 1 {
+                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                    remove = ui.small_button("Remove pane").clicked();
+                });
+            }
+        });
+
+        remove
+    }
+
+    fn show_plot_pane(
+        &mut self,
+        ui: &mut egui::Ui,
+        index: usize,
+        height: f32,
+        frame: Option<&CapturedFrame>,
+        selecting: bool,
+        reset: bool,
+    ) {
+        use egui_plot::{
+            AxisHints, HLine, HPlacement, Legend, Line, Plot, PlotBounds, PlotPoint, PlotPoints,
+            Polygon, Text, VLine,
+        };
+
+        let right_axis = self.plot_panes[index].right_axis;
+        let has_right_axis = self
+            .signals
+            .iter()
+            .any(|signal| signal.enabled && signal.pane == index && signal.axis == YAxis::Right);
+
+        // TODO: a vector with linear search might be more efficient, investigate
+        let signal_scales = self
+            .signals
+            .iter()
+            .filter(|signal| signal.pane == index)
+            .map(|signal| {
+                let mapping = match signal.axis {
+                    YAxis::Left => AxisMapping::IDENTITY,
+                    YAxis::Right => right_axis,
+                };
+                (signal.name.clone(), (signal.scale, mapping))
+            })
+            .collect::<HashMap<_, _>>();
+
+        // a drag starting on a cursor moves it, instead of panning
+        let grabbable_cursor = self.cursor_under_pointer(ui, index);
+        let moving_cursor = grabbable_cursor.is_some() || self.cursors.grabbed.is_some();
+
+        let mut plot = Plot::new(("plot-pane", index));
+
+        if has_right_axis {
+            plot = plot.custom_y_axes(vec![
+                AxisHints::new_y(),
+                AxisHints::new_y()
+                    .placement(HPlacement::Right)
+                    .formatter(move |mark, _range| {
+                        let step =
+                            (right_axis.invert(mark.step_size) - right_axis.invert(0.0)).abs();
+                        let decimals = (-step.log10().round()).max(0.0) as usize;
+                        format!("{:.*}", decimals, right_axis.invert(mark.value))
+                    }),
+            ]);
+        }
+
+        let mut plot = plot
+            .height(height)
+            .legend(Legend::default())
+            .allow_zoom([true, false])
+            .allow_drag(!selecting && !moving_cursor)
+            .y_axis_width(2)
+            .auto_bounds([true, true].into())
+            .link_axis("plot-panes", [true, false])
+            .link_cursor("plot-panes", [true, false].into())
+            .label_formatter(move |name, value| {
+                if let Some((scale, mapping)) = signal_scales.get(name) {
+                    format!(
+                        "{}\nx: {}\ny: {}",
+                        name,
+                        value.x,
+                        mapping.invert(value.y) / scale
+                    )
+                } else {
+                    "".to_owned()
+                }
+            });
+
+        if reset {
+            plot = plot.reset();
+        }
+
+        let response = plot.show(ui, |plot_ui| {
+            let bounds = plot_ui.plot_bounds();
+            let (x_min, x_max) = (bounds.min()[0], bounds.max()[0]);
+
+            let mut lines = Vec::new();
+            for signal in self.signals.iter() {
+                if !signal.enabled || signal.pane != index {
+                    continue;
+                }
+
+                let points = match frame {
+                    Some(frame) => frame.signals.get(&signal.id).map(|points| {
+                        points
+                            .iter()
+                            .map(|p| PlotPoint::new(p.x - frame.reference_time, p.y * signal.scale))
+                            .collect::<Vec<_>>()
+                    }),
+                    None => self.samples.get(&signal.id).map(|buffer| {
+                        // the buffer hands out at most a min/max pair per pixel column of the
+                        // visible range, keeping the glitches visible when zoomed out
+                        let width = x_max - x_min;
+                        debug_assert!(width >= 0.0);
+                        let margin = if width == 0.0 { 0.1 } else { width };
+
+                        // two points (a min/max pair) per pixel column; with the margin, the
+                        // requested range is about twice as wide as the plot
+                        let pixels = 2.0 * plot_ui.response().rect.width();
+                        buffer
+                            .plot_points(
+                                x_min - margin / 2.0,
+                                x_max + margin / 2.0,
+                                signal.scale,
+                                2 * pixels.ceil() as usize,
+                            )
+                            .points()
+                            .to_vec()
+                    }),
+                };
+
+                if let Some(points) = points {
+                    lines.push((signal, points));
+                }
+            }
+
+            // the signals on the right axis are stretched over the range of the ones on the left
+            let axis_range = |axis| {
+                lines
+                    .iter()
+                    .filter(|(signal, _)| signal.axis == axis)
+                    .fold(None, |range, (_, points)| {
+                        panes::merge_ranges(range, panes::value_range(points))
+                    })
+            };
+            let right_axis = match (axis_range(YAxis::Right), axis_range(YAxis::Left)) {
+                (Some(right), Some(left)) => AxisMapping::fit(right, left),
+                _ => AxisMapping::IDENTITY,
+            };
+
+            for (signal, mut points) in lines {
+                if signal.axis == YAxis::Right {
+                    for point in &mut points {
+                        point.y = right_axis.apply(point.y);
+                    }
+                }
+
+                plot_ui.line(
+                    Line::new(PlotPoints::Owned(points))
+                        .name(signal.name.clone())
+                        .color(signal.color),
+                );
+            }
+
+            self.plot_panes[index].right_axis = right_axis;
+
+            if frame.is_some_and(|frame| frame.trigger_time.is_some()) {
+                plot_ui.vline(
+                    VLine::new(0.0)
+                        .name("Trigger")
+                        .color(Color32::GRAY)
+                        .style(egui_plot::LineStyle::dashed_loose()),
+                );
+            }
+
+            let marker_color = Color32::from_rgb(0xe0, 0x40, 0x40);
+            let top = bounds.max()[1];
+            let time_offset = frame.map_or(0.0, |frame| frame.reference_time);
+
+            self.plot_view_range = Some((x_min + time_offset, x_max + time_offset));
+
+            if let Some((from_t, to_t)) = self.export_selection {
+                let (from_t, to_t) = (from_t - time_offset, to_t - time_offset);
+                let (bottom, top) = (bounds.min()[1], bounds.max()[1]);
+
+                plot_ui.polygon(
+                    Polygon::new(vec![
+                        [from_t, bottom],
+                        [to_t, bottom],
+                        [to_t, top],
+                        [from_t, top],
+                    ])
+                    .name("Export selection")
+                    .fill_color(Color32::from_rgba_unmultiplied(0x40, 0x80, 0xe0, 0x30))
+                    .stroke(egui::Stroke::NONE)
+                    .allow_hover(false),
+                );
+            }
+            for (time, description) in &self.stop_markers {
+                let time = time - time_offset;
+                plot_ui.vline(
+                    VLine::new(time)
+                        .name("Target stops")
+                        .color(marker_color)
+                        .style(egui_plot::LineStyle::dashed_loose()),
+                );
+                plot_ui.text(
+                    Text::new([time, top].into(), description)
+                        .anchor(egui::Align2::LEFT_TOP)
+                        .color(marker_color),
+                );
+            }
+
+            let response = plot_ui.response();
+
+            if response.clicked() || response.secondary_clicked() {
+                self.plot_auto_follow = false;
+            }
+
+            let pointer_time = plot_ui.pointer_coordinate().map(|p| p.x + time_offset);
+            if selecting && response.drag_started() {
+                self.export_selection_start = pointer_time;
+            }
+            if let (Some(start), Some(end)) = (self.export_selection_start, pointer_time) {
+                if response.dragged() {
+                    self.export_selection = Some((start.min(end), start.max(end)));
+                }
+            }
+            if response.drag_stopped() {
+                self.export_selection_start = None;
+            }
+
+            if self.cursors.enabled {
+                let cursor_color = Color32::from_rgb(0xe0, 0xc0, 0x40);
+                let (bottom, left) = (bounds.min()[1], bounds.min()[0]);
+
+                for (i, &time) in self.cursors.times.iter().enumerate() {
+                    plot_ui.vline(VLine::new(time).name("Cursors").color(cursor_color));
+                    plot_ui.text(
+                        Text::new([time, bottom].into(), format!("T{}", i + 1))
+                            .anchor(egui::Align2::LEFT_BOTTOM)
+                            .color(cursor_color),
+                    );
+                }
+                for (i, &value) in self.plot_panes[index].cursor_values.iter().enumerate() {
+                    plot_ui.hline(
+                        HLine::new(value)
+                            .name("Cursors")
+                            .color(cursor_color)
+                            .style(egui_plot::LineStyle::dashed_dense()),
+                    );
+                    plot_ui.text(
+                        Text::new([left, value].into(), format!("Y{}", i + 1))
+                            .anchor(egui::Align2::LEFT_BOTTOM)
+                            .color(cursor_color),
+                    );
+                }
+
+                let response = plot_ui.response();
+                if response.drag_started() && !selecting {
+                    self.cursors.grabbed = grabbable_cursor.map(|cursor| (index, cursor));
+                }
+                if response.drag_stopped() {
+                    self.cursors.grabbed = None;
+                }
+
+                if let (Some((pane, cursor)), Some(pointer)) =
+                    (self.cursors.grabbed, plot_ui.pointer_coordinate())
+                {
+                    if pane == index {
+                        self.move_cursor(index, cursor, pointer, time_offset);
+                    }
                 }
             }
 
-            // we temporarely took ownership of the sampler by moving it out of `self`,
-            // now we put it back
-            self.current_sampler = Some(sampler);
+            // each pane keeps fitting its own Y range while following the last samples
+            if self.plot_auto_follow && frame.is_none() {
+                let x_max = self.max_time as f64 * 1e-6;
+                let x_min = x_max - self.plot_auto_follow_time;
+                plot_ui.set_plot_bounds(PlotBounds::from_min_max(
+                    [x_min, bounds.min()[1]],
+                    [x_max, bounds.max()[1]],
+                ));
+                plot_ui.set_auto_bounds([false, true].into());
+            }
+        });
+
+        self.plot_panes[index].transform = Some(response.transform);
+    }
+
+    /// The cursor that a drag would grab in a pane, if any is close to the pointer.
+    fn cursor_under_pointer(&self, ui: &egui::Ui, index: usize) -> Option<CursorId> {
+        let transform = self.plot_panes[index].transform?;
+        let pointer = ui.input(|input| input.pointer.hover_pos())?;
+
+        if !self.cursors.enabled || !transform.frame().contains(pointer) {
+            return None;
         }
 
-        if sampler_terminated {
-            debug_assert!(self.current_sampler.is_some());
+        let times_x = self
+            .cursors
+            .times
+            .map(|time| transform.position_from_point_x(time));
+        let values_y = self.plot_panes[index]
+            .cursor_values
+            .map(|value| transform.position_from_point_y(value));
+
+        cursors::grabbable(pointer, times_x, values_y)
+    }
+
+    /// Move a cursor dragged in a pane to the pointer or, when snapping, to the closest sample of
+    /// the signals in the pane.
+    fn move_cursor(
+        &mut self,
+        index: usize,
+        cursor: CursorId,
+        pointer: egui_plot::PlotPoint,
+        time_offset: f64,
+    ) {
+        let right_axis = self.plot_panes[index].right_axis;
+        let samples = self
+            .signals
+            .iter()
+            .filter(|signal| signal.enabled && signal.pane == index)
+            .filter_map(|signal| {
+                let buffer = self.samples.get(&signal.id)?;
+                let sample = buffer.nearest_sample(pointer.x + time_offset)?;
+
+                // in plot coordinates, as the signal is drawn
+                let y = sample.y * signal.scale;
+                let y = match signal.axis {
+                    YAxis::Left => y,
+                    YAxis::Right => right_axis.apply(y),
+                };
+
+                Some((sample.x - time_offset, y))
+            })
+            .collect::<Vec<_>>();
+
+        match cursor {
+            CursorId::Time(i) => {
+                let snapped = cursors::closest(pointer.x, samples.iter().map(|&(t, _)| t));
+                self.cursors.times[i] = match self.cursors.snap {
+                    true => snapped.unwrap_or(pointer.x),
+                    false => pointer.x,
+                };
+            }
+            CursorId::Value(i) => {
+                let snapped = cursors::closest(pointer.y, samples.iter().map(|&(_, y)| y));
+                self.plot_panes[index].cursor_values[i] = match self.cursors.snap {
+                    true => snapped.unwrap_or(pointer.y),
+                    false => pointer.y,
+                };
+            }
+        }
+    }
+
+    /// Place the cursors at a third and two thirds of the ranges shown in each pane.
+    fn place_cursors(&mut self) {
+        for pane in &mut self.plot_panes {
+            let Some(transform) = pane.transform else {
+                continue;
+            };
+
+            let bounds = transform.bounds();
+            let (bottom, height) = (bounds.min()[1], bounds.height());
+            pane.cursor_values = [bottom + height / 3.0, bottom + 2.0 * height / 3.0];
+
+            let (left, width) = (bounds.min()[0], bounds.width());
+            self.cursors.times = [left + width / 3.0, left + 2.0 * width / 3.0];
+        }
+    }
+
+    /// Show the times and values at the cursors, with their differences.
+    fn show_cursor_readout(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            if ui
+                .checkbox(&mut self.cursors.enabled, "Show cursors")
+                .changed()
+                && self.cursors.enabled
+            {
+                self.place_cursors();
+            }
+
+            ui.checkbox(&mut self.cursors.snap, "Snap to samples");
+
+            if ui.button("Place").clicked() {
+                self.place_cursors();
+            }
+        });
+
+        if !self.cursors.enabled {
+            return;
+        }
+
+        // while a captured frame is shown, the times are relative to its trigger
+        let time_offset = match self.trigger_enabled {
+            true => self
+                .trigger
+                .lock()
+                .unwrap()
+                .frame()
+                .map_or(0.0, |frame| frame.reference_time),
+            false => 0.0,
+        };
+
+        let [t1, t2] = self.cursors.times;
+        let delta_time = self.cursors.delta_time();
+
+        egui::Grid::new("cursor-times")
+            .num_columns(2)
+            .striped(true)
+            .show(ui, |ui| {
+                for (label, value) in [
+                    ("T1", format!("{:.6} s", t1)),
+                    ("T2", format!("{:.6} s", t2)),
+                    ("ΔT", format!("{:.6} s", delta_time)),
+                    (
+                        "1/ΔT",
+                        match delta_time {
+                            0.0 => "-".to_owned(),
+                            _ => format!("{:.3} Hz", 1.0 / delta_time.abs()),
+                        },
+                    ),
+                ] {
+                    ui.label(label);
+                    ui.label(value);
+                    ui.end_row();
+                }
+            });
+
+        ui.separator();
+
+        egui::Grid::new("cursor-values")
+            .num_columns(4)
+            .striped(true)
+            .show(ui, |ui| {
+                ui.label("");
+                ui.label("at T1");
+                ui.label("at T2");
+                ui.label("Δ");
+                ui.end_row();
+
+                // the values as plotted, scaled
+                for signal in self.signals.iter().filter(|signal| signal.enabled) {
+                    let Some(buffer) = self.samples.get(&signal.id) else {
+                        continue;
+                    };
+
+                    let y1 = buffer.value_at(t1 + time_offset) * signal.scale;
+                    let y2 = buffer.value_at(t2 + time_offset) * signal.scale;
+
+                    ui.colored_label(signal.color, &signal.name);
+                    ui.label(format!("{:.6}", y1));
+                    ui.label(format!("{:.6}", y2));
+                    ui.label(format!("{:.6}", y2 - y1));
+                    ui.end_row();
+                }
+
+                for (index, pane) in self.plot_panes.iter().enumerate() {
+                    let [y1, y2] = pane.cursor_values;
+
+                    ui.label(format!("Y1, Y2 of pane {}", index + 1));
+                    ui.label(format!("{:.6}", y1));
+                    ui.label(format!("{:.6}", y2));
+                    ui.label(format!("{:.6}", y2 - y1));
+                    ui.end_row();
+                }
+            });
+    }
+
+    /// Plot a signal against another one, over the chosen time window.
+    fn show_xy_plot(&mut self, ui: &mut egui::Ui) {
+        use egui_plot::{Line, Plot};
+
+        let signal_name = |id: Option<u32>| {
+            self.signals
+                .iter()
+                .find(|signal| Some(signal.id) == id)
+                .map(|signal| signal.name.clone())
+                .unwrap_or_default()
+        };
+        let (x_name, y_name) = (
+            signal_name(self.xy_plot.x_signal),
+            signal_name(self.xy_plot.y_signal),
+        );
+
+        let settings = &mut self.xy_plot;
+
+        ui.horizontal(|ui| {
+            for (label, selected, name) in [
+                ("X", &mut settings.x_signal, x_name),
+                ("Y", &mut settings.y_signal, y_name),
+            ] {
+                egui::ComboBox::from_label(label)
+                    .selected_text(name)
+                    .show_ui(ui, |ui| {
+                        for signal in &self.signals {
+                            ui.selectable_value(selected, Some(signal.id), &signal.name);
+                        }
+                    });
+            }
+        });
+
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut settings.follow_view, "Time plot view");
+            ui.add_enabled_ui(!settings.follow_view, |ui| {
+                ui.label("Last ");
+                ui.add(
+                    egui::DragValue::new(&mut settings.window)
+                        .range(0.001..=3600.0)
+                        .suffix(" s")
+                        .speed(0.01),
+                );
+            });
+        });
+
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut settings.fade, "Fade older points");
+            ui.checkbox(&mut settings.equal_axes, "Equal axes");
+        });
+
+        let (from_t, to_t) = match (settings.follow_view, self.plot_view_range) {
+            (true, Some(range)) => range,
+            _ => {
+                let last = self.max_time as f64 * 1e-6;
+                (last - settings.window, last)
+            }
+        };
+
+        let signal = |id: Option<u32>| {
+            let signal = self.signals.iter().find(|signal| Some(signal.id) == id)?;
+            Some((signal, self.samples.get(&signal.id)?))
+        };
+
+        let mut plot = Plot::new("xy-plot");
+        if settings.equal_axes {
+            plot = plot.data_aspect(1.0);
+        }
+
+        plot.show(ui, |plot_ui| {
+            let (Some((x, x_buffer)), Some((y, y_buffer))) =
+                (signal(settings.x_signal), signal(settings.y_signal))
+            else {
+                return;
+            };
+
+            let points = xyplot::pair_by_time(x_buffer, y_buffer, from_t, to_t, MAX_XY_POINTS)
+                .into_iter()
+                .map(|[px, py]| [px * x.scale, py * y.scale])
+                .collect::<Vec<_>>();
+
+            let name = format!("{} vs {}", y.name, x.name);
+            let steps = if settings.fade { XY_FADE_STEPS } else { 1 };
+
+            for (opacity, segment) in xyplot::fade_segments(&points, steps) {
+                plot_ui.line(
+                    Line::new(segment)
+                        .name(&name)
+                        .color(y.color.gamma_multiply(opacity)),
+                );
+            }
+        });
+    }
+
+    /// Show the spectrum of a signal, over the chosen time window.
+    fn show_spectrum(&mut self, ui: &mut egui::Ui) {
+        use egui_plot::{AxisHints, Line, MarkerShape, Plot, PlotPoint, Points, Text};
+
+        let signal_name = self
+            .signals
+            .iter()
+            .find(|signal| Some(signal.id) == self.spectrum.signal)
+            .map(|signal| signal.name.clone())
+            .unwrap_or_default();
+
+        let settings = &mut self.spectrum;
+        let mut update = false;
+
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_label("Signal")
+                .selected_text(signal_name)
+                .show_ui(ui, |ui| {
+                    for signal in &self.signals {
+                        ui.selectable_value(&mut settings.signal, Some(signal.id), &signal.name);
+                    }
+                });
+
+            ui.checkbox(&mut settings.live, "Live");
+            if !settings.live {
+                update = ui.button("Update").clicked();
+            }
+        });
+
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut settings.follow_view, "Time plot view");
+            ui.add_enabled_ui(!settings.follow_view, |ui| {
+                ui.label("Last ");
+                ui.add(
+                    egui::DragValue::new(&mut settings.duration)
+                        .range(0.001..=3600.0)
+                        .suffix(" s")
+                        .speed(0.01),
+                );
+            });
+        });
 
-            let sampler = self.current_sampler.take().unwrap();
-            sampler.stop();
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_label("Window")
+                .selected_text(settings.window.description())
+                .show_ui(ui, |ui| {
+                    for window in WindowFunction::ALL {
+                        ui.selectable_value(&mut settings.window, window, window.description());
+                    }
+                });
+
+            egui::ComboBox::from_label("FFT size")
+                .selected_text(settings.fft_size.to_string())
+                .show_ui(ui, |ui| {
+                    for size in (8..=16).map(|bits| 1 << bits) {
+                        ui.selectable_value(&mut settings.fft_size, size, size.to_string());
+                    }
+                });
+        });
 
-            debug_assert!(self.current_sampler.is_none());
+        ui.horizontal(|ui| {
+            ui.label("Averages: ");
+            ui.add(egui::DragValue::new(&mut settings.averages).range(1..=64));
+            ui.label("Peaks: ");
+            ui.add(egui::DragValue::new(&mut settings.peaks).range(0..=10));
+            ui.checkbox(&mut settings.decibels, "dB");
+            ui.checkbox(&mut settings.log_frequency, "Log frequency");
+        });
+
+        if settings.live || update {
+            let (from_t, to_t) = match (settings.follow_view, self.plot_view_range) {
+                (true, Some(range)) => range,
+                _ => {
+                    let last = self.max_time as f64 * 1e-6;
+                    (last - settings.duration, last)
+                }
+            };
+
+            let buffer = settings.signal.and_then(|id| self.samples.get(&id));
+            let key = (
+                (from_t, to_t),
+                settings.clone(),
+                buffer.map_or(0, SampleBuffer::len),
+            );
+
+            if update || self.spectrum_key.as_ref() != Some(&key) {
+                self.spectrum_result =
+                    buffer.and_then(|buffer| Spectrum::compute(buffer, from_t, to_t, settings));
+                self.spectrum_key = Some(key);
+            }
         }
 
-        if let Some(sampler) = &self.current_sampler {
-            debug_assert!(!sampler_terminated);
+        let Some(spectrum) = &self.spectrum_result else {
+            ui.label("Not enough samples of the signal in the time window");
+            return;
+        };
+
+        ui.label(format!(
+            "Sample rate: {:.1} Hz, resolution: {:.3} Hz",
+            spectrum.sample_rate,
+            spectrum.resolution()
+        ));
+
+        // egui_plot has no logarithmic axes, the frequencies get plotted as their logarithm
+        let log_frequency = settings.log_frequency;
+        let to_x = move |frequency: f64| match log_frequency {
+            true => frequency.log10(),
+            false => frequency,
+        };
+
+        let frequency_axis =
+            AxisHints::new_x()
+                .label("Frequency (Hz)")
+                .formatter(move |mark, _range| match log_frequency {
+                    true => format!("{:.4}", 10f64.powf(mark.value)),
+                    false => format!("{}", mark.value),
+                });
+
+        let amplitude_label = match settings.decibels {
+            true => "Amplitude (dB)",
+            false => "Amplitude",
+        };
 
-            while let Ok((t, samples)) = sampler.sampled_channel().try_recv() {
-                for (id, y) in samples.into_iter() {
-                    self.samples
-                        .entry(id)
-                        .or_insert_with(|| SampleBuffer::new())
-                        .push(t as f64 * 1e-6, y);
+        let points = spectrum
+            .bins
+            .iter()
+            .filter(|bin| !log_frequency || bin.x > 0.0)
+            .map(|bin| [to_x(bin.x), bin.y])
+            .collect::<Vec<_>>();
+
+        let peaks = spectrum.peaks(settings.peaks);
+        let color = self
+            .signals
+            .iter()
+            .find(|signal| Some(signal.id) == settings.signal)
+            .map_or(Color32::LIGHT_BLUE, |signal| signal.color);
+
+        Plot::new("spectrum")
+            .custom_x_axes(vec![frequency_axis])
+            .y_axis_label(amplitude_label)
+            .label_formatter(move |_name, value| {
+                let frequency = match log_frequency {
+                    true => 10f64.powf(value.x),
+                    false => value.x,
+                };
+                format!("{:.3} Hz\n{:.4}", frequency, value.y)
+            })
+            .show(ui, |plot_ui| {
+                plot_ui.line(Line::new(points).color(color));
+
+                for peak in peaks {
+                    let position = PlotPoint::new(to_x(peak.x), peak.y);
+
+                    plot_ui.points(
+                        Points::new([position.x, position.y])
+                            .shape(MarkerShape::Down)
+                            .radius(4.0)
+                            .color(Color32::YELLOW),
+                    );
+                    plot_ui.text(
+                        Text::new(position, format!("{:.2} Hz", peak.x))
+                            .anchor(egui::Align2::LEFT_BOTTOM)
+                            .color(Color32::YELLOW),
+                    );
                 }
+            });
+    }
+
+    /// Show the spectrogram of a signal over the time range of the time plot, with the time axis
+    /// linked to it.
+    fn show_spectrogram(&mut self, ui: &mut egui::Ui) {
+        use egui_plot::{Plot, PlotImage, PlotPoint};
+
+        let signal_name = self
+            .signals
+            .iter()
+            .find(|signal| Some(signal.id) == self.spectrogram.signal)
+            .map(|signal| signal.name.clone())
+            .unwrap_or_default();
+
+        let settings = &mut self.spectrogram;
+
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_label("Signal")
+                .selected_text(signal_name)
+                .show_ui(ui, |ui| {
+                    for signal in &self.signals {
+                        ui.selectable_value(&mut settings.signal, Some(signal.id), &signal.name);
+                    }
+                });
+
+            egui::ComboBox::from_label("Colormap")
+                .selected_text(settings.colormap.description())
+                .show_ui(ui, |ui| {
+                    for colormap in Colormap::ALL {
+                        ui.selectable_value(
+                            &mut settings.colormap,
+                            colormap,
+                            colormap.description(),
+                        );
+                    }
+                });
+        });
+
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_label("FFT size")
+                .selected_text(settings.fft_size.to_string())
+                .show_ui(ui, |ui| {
+                    for size in (6..=14).map(|bits| 1 << bits) {
+                        ui.selectable_value(&mut settings.fft_size, size, size.to_string());
+                    }
+                });
 
-                if t > self.max_time {
-                    self.max_time = t;
+            egui::ComboBox::from_label("Window")
+                .selected_text(settings.window.description())
+                .show_ui(ui, |ui| {
+                    for window in WindowFunction::ALL {
+                        ui.selectable_value(&mut settings.window, window, window.description());
+                    }
+                });
+
+            ui.label("Hop: ");
+            ui.add(
+                egui::DragValue::new(&mut settings.hop)
+                    .range(1..=settings.fft_size)
+                    .suffix(" samples"),
+            );
+
+            ui.label("Range: ");
+            ui.add(
+                egui::DragValue::new(&mut settings.dynamic_range)
+                    .range(10.0..=200.0)
+                    .suffix(" dB"),
+            );
+        });
+
+        // the time range is the one of the time plot, which scrolls along while following
+        let buffer = settings.signal.and_then(|id| self.samples.get(&id));
+        let key = self
+            .plot_view_range
+            .map(|range| (range, settings.clone(), buffer.map_or(0, SampleBuffer::len)));
+
+        if self.spectrogram_key != key {
+            self.spectrogram_result = match (buffer, self.plot_view_range) {
+                (Some(buffer), Some((from_t, to_t))) => {
+                    Spectrogram::compute(buffer, from_t, to_t, settings)
+                }
+                _ => None,
+            };
+            self.spectrogram_key = key;
+
+            if let Some(spectrogram) = &self.spectrogram_result {
+                let image = spectrogram.image(settings.colormap, settings.dynamic_range);
+                match &mut self.spectrogram_texture {
+                    Some(texture) => texture.set(image, egui::TextureOptions::NEAREST),
+                    None => {
+                        self.spectrogram_texture = Some(ui.ctx().load_texture(
+                            "spectrogram",
+                            image,
+                            egui::TextureOptions::NEAREST,
+                        ))
+                    }
                 }
             }
+        }
+
+        let (Some(spectrogram), Some(texture)) =
+            (&self.spectrogram_result, &self.spectrogram_texture)
+        else {
+            ui.label("Not enough samples of the signal in the time range of the plot");
+            return;
+        };
+
+        // the rows are centered on their frequencies, from DC to Nyquist
+        let (from_t, to_t) = spectrogram.time_range();
+        let resolution = spectrogram.resolution();
+        let height = (spectrogram.fft_size / 2 + 1) as f64 * resolution;
+
+        let image = PlotImage::new(
+            texture.id(),
+            PlotPoint::new((from_t + to_t) / 2.0, height / 2.0 - resolution / 2.0),
+            [(to_t - from_t) as f32, height as f32],
+        );
+
+        Plot::new("spectrogram")
+            .link_axis("plot-panes", [true, false])
+            .y_axis_label("Frequency (Hz)")
+            .include_y(0.0)
+            .include_y(spectrogram.sample_rate / 2.0)
+            .allow_zoom([true, false])
+            .label_formatter(|_name, value| format!("{:.6} s\n{:.1} Hz", value.x, value.y))
+            .show(ui, |plot_ui| plot_ui.image(image));
+    }
+
+    fn show_trigger_controls(&mut self, ui: &mut egui::Ui) {
+        let now = self.max_time as f64 * 1e-6;
 
-            if self.buffer_auto_truncate {
-                for (_, buffer) in self.samples.iter_mut() {
-                    buffer.truncate(self.buffer_auto_truncate_at);
+        if ui
+            .checkbox(&mut self.trigger_enabled, "Trigger enabled")
+            .changed()
+        {
+            match self.trigger_enabled {
+                true => self.trigger.lock().unwrap().arm(now),
+                false => self.trigger.lock().unwrap().disarm(),
+            }
+        }
+
+        let mut trigger = self.trigger.lock().unwrap();
+        let settings = &mut trigger.settings;
+
+        let signal_name = self
+            .signals
+            .iter()
+            .find(|signal| signal.id == settings.signal_id)
+            .map(|signal| signal.name.clone())
+            .unwrap_or_default();
+
+        egui::ComboBox::from_label("Signal")
+            .selected_text(signal_name)
+            .show_ui(ui, |ui| {
+                for signal in &self.signals {
+                    ui.selectable_value(&mut settings.signal_id, signal.id, &signal.name);
                 }
+            });
+
+        // switching the condition kind keeps the level, to avoid surprises
+        let level = match settings.condition {
+            TriggerCondition::Edge { level, .. } => level,
+            TriggerCondition::PulseWidth { level, .. } => level,
+            TriggerCondition::Window { low, .. } => low,
+        };
+        let hysteresis = 0.1 * level.abs().max(1.0);
+
+        let edge = TriggerCondition::Edge {
+            slope: Slope::Rising,
+            level,
+            hysteresis,
+        };
+        let pulse_width = TriggerCondition::PulseWidth {
+            polarity: Polarity::Positive,
+            level,
+            hysteresis,
+            min_width: 0.0,
+            max_width: 0.01,
+        };
+        let window = TriggerCondition::Window {
+            event: WindowEvent::Exit,
+            low: level,
+            high: level + 1.0,
+        };
+
+        ui.horizontal(|ui| {
+            let condition = &mut settings.condition;
+            if ui
+                .radio(matches!(condition, TriggerCondition::Edge { .. }), "Edge")
+                .clicked()
+            {
+                *condition = edge;
+            }
+            if ui
+                .radio(
+                    matches!(condition, TriggerCondition::PulseWidth { .. }),
+                    "Pulse width",
+                )
+                .clicked()
+            {
+                *condition = pulse_width;
+            }
+            if ui
+                .radio(
+                    matches!(condition, TriggerCondition::Window { .. }),
+                    "Window",
+                )
+                .clicked()
+            {
+                *condition = window;
+            }
+        });
+
+        match &mut settings.condition {
+            TriggerCondition::Edge {
+                slope,
+                level,
+                hysteresis,
+            } => {
+                ui.horizontal(|ui| {
+                    ui.radio_value(slope, Slope::Rising, "Rising");
+                    ui.radio_value(slope, Slope::Falling, "Falling");
+                    ui.radio_value(slope, Slope::Either, "Either");
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Level");
+                    ui.add(egui::DragValue::new(level).speed(0.1));
+                    ui.label("Hysteresis");
+                    ui.add(
+                        egui::DragValue::new(hysteresis)
+                            .range(0.0..=f64::MAX)
+                            .speed(0.01),
+                    );
+                });
+            }
+            TriggerCondition::PulseWidth {
+                polarity,
+                level,
+                hysteresis,
+                min_width,
+                max_width,
+            } => {
+                ui.horizontal(|ui| {
+                    ui.radio_value(polarity, Polarity::Positive, "Positive");
+                    ui.radio_value(polarity, Polarity::Negative, "Negative");
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Level");
+                    ui.add(egui::DragValue::new(level).speed(0.1));
+                    ui.label("Hysteresis");
+                    ui.add(
+                        egui::DragValue::new(hysteresis)
+                            .range(0.0..=f64::MAX)
+                            .speed(0.01),
+                    );
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Width from");
+                    ui.add(
+                        egui::DragValue::new(min_width)
+                            .range(0.0..=*max_width)
+                            .suffix(" s")
+                            .speed(0.001),
+                    );
+                    ui.label("to");
+                    ui.add(
+                        egui::DragValue::new(max_width)
+                            .range(*min_width..=3600.0)
+                            .suffix(" s")
+                            .speed(0.001),
+                    );
+                });
+            }
+            TriggerCondition::Window { event, low, high } => {
+                ui.horizontal(|ui| {
+                    ui.radio_value(event, WindowEvent::Enter, "Enter");
+                    ui.radio_value(event, WindowEvent::Exit, "Exit");
+                });
+                ui.horizontal(|ui| {
+                    ui.label("From");
+                    ui.add(egui::DragValue::new(low).range(f64::MIN..=*high).speed(0.1));
+                    ui.label("to");
+                    ui.add(egui::DragValue::new(high).range(*low..=f64::MAX).speed(0.1));
+                });
             }
-
-            // TODO: might use `request_repaint_after` to reduce CPU usage
-            ctx.request_repaint();
         }
-    }
 
-    fn try_connect_sampler(&mut self) -> anyhow::Result<Box<dyn Sampler>> {
-        let sampler: Box<dyn Sampler> = match self.sampling_method {
-            SamplingMethod::Simulated => Box::new(FakeSampler::start(self.sample_rate)),
-            SamplingMethod::MemorySamping => Box::new(MemSampler::start(
-                &self.gdb_address,
-                &self.telnet_address,
-                self.sample_rate,
-                self.elf_filename.clone(),
-            )?),
-            SamplingMethod::RTT => Box::new(RTTSampler::start(
-                &self.telnet_address,
-                self.rtt_polling_interval,
-            )?),
-        };
+        ui.horizontal(|ui| {
+            ui.radio_value(&mut settings.mode, TriggerMode::Single, "Single");
+            ui.radio_value(&mut settings.mode, TriggerMode::Normal, "Normal");
+            ui.radio_value(&mut settings.mode, TriggerMode::Auto, "Auto");
+        });
 
-        self.reset_buffer();
+        ui.horizontal(|ui| {
+            ui.label("Pre");
+            ui.add(
+                egui::DragValue::new(&mut settings.pre_trigger)
+                    .range(0.0..=3600.0)
+                    .suffix(" s")
+                    .speed(0.01),
+            );
+            ui.label("Post");
+            ui.add(
+                egui::DragValue::new(&mut settings.post_trigger)
+                    .range(0.001..=3600.0)
+                    .suffix(" s")
+                    .speed(0.01),
+            );
+        });
 
-        // we expect that there is currently no sampler active, we assert
-        // this in debug mode and try to fix the incident in release mode
-        debug_assert!(self.current_sampler.is_none());
-        if let Some(previous_sampler) = self.current_sampler.take() {
-            log::warn!("found an active sampler before connecting a new one, trying to stop it");
-            previous_sampler.stop();
+        if settings.mode == TriggerMode::Single {
+            ui.horizontal(|ui| {
+                let mut rearm = settings.rearm_delay.is_some();
+                ui.checkbox(&mut rearm, "Arm again after");
+
+                let mut delay = settings.rearm_delay.unwrap_or(1.0);
+                ui.add_enabled(
+                    rearm,
+                    egui::DragValue::new(&mut delay)
+                        .range(0.0..=3600.0)
+                        .suffix(" s")
+                        .speed(0.1),
+                );
+
+                settings.rearm_delay = rearm.then_some(delay);
+            });
         }
 
-        self.signals = sampler
-            .available_signals()
-            .into_iter()
-            .map(|(id, name)| SignalConfig::new(id, name, None))
-            .collect();
-
-        if let Some(first) = self.signals.iter_mut().next() {
-            first.enabled = true;
-            sampler.set_active_signals(&[first.id]);
-        }
+        ui.add_enabled_ui(self.trigger_enabled, |ui| {
+            ui.horizontal(|ui| {
+                if ui.button("Arm").clicked() {
+                    trigger.arm(now);
+                }
 
-        Ok(sampler)
+                ui.label(match trigger.state() {
+                    TriggerState::Disarmed => "Disarmed".to_owned(),
+                    TriggerState::Armed { .. } => "Armed".to_owned(),
+                    TriggerState::Triggered { at } => format!("Triggered at {:.6} s", at),
+                    TriggerState::Captured { rearm_at: None } => "Captured".to_owned(),
+                    TriggerState::Captured {
+                        rearm_at: Some(rearm_at),
+                    } => format!("Captured, arming again at {:.3} s", rearm_at),
+                });
+            });
+        });
     }
 }
 
@@ -269,32 +2003,78 @@ impl eframe::App for OCDScope {
                 toolbar_group.horizontal(|toolbar| {
                     // TODO: fancy icons
 
-                    if self.current_sampler.is_none() {
+                    toolbar.toggle_value(&mut self.show_log_panel, "Log");
+                    toolbar.toggle_value(&mut self.show_xy_plot, "X-Y plot");
+                    toolbar.toggle_value(&mut self.show_spectrum, "Spectrum");
+                    toolbar.toggle_value(&mut self.show_spectrogram, "Spectrogram");
+
+                    match &self.recording_filename {
+                        None => {
+                            if toolbar.button("Record...").clicked() {
+                                self.session_file_dialog.save_file();
+                            }
+                        }
+                        Some(filename) => {
+                            let recording_label = format!("Stop recording to {:?}", filename);
+                            if toolbar.button(recording_label).clicked() {
+                                self.stop_recording();
+                            }
+                        }
+                    }
+
+                    if !self.sampler_connected {
                         if toolbar.button("Connect...").clicked() {
                             self.show_connect_dialog = true;
                         }
+                        if toolbar.button("Open session...").clicked() {
+                            self.session_file_dialog.pick_file();
+                        }
                     } else {
                         if toolbar.button("Disconnect").clicked() {
-                            let sampler = self.current_sampler.take().unwrap();
-                            sampler.stop();
-
-                            debug_assert!(self.current_sampler.is_none());
+                            self.ingestion.send(ingestion::Command::Detach);
+                            self.sampler_connected = false;
                         }
 
                         match self.current_sampler_status {
                             Some(sampler::Status::Sampling) => {
                                 if toolbar.button("Pause").clicked() {
-                                    self.current_sampler.as_ref().unwrap().pause();
+                                    self.ingestion.send(ingestion::Command::Pause);
                                 }
                             }
                             Some(sampler::Status::Paused) => {
                                 if toolbar.button("Resume").clicked() {
-                                    self.current_sampler.as_ref().unwrap().resume();
+                                    self.ingestion.send(ingestion::Command::Resume);
                                 }
                             }
                             _ => {}
                         }
 
+                        if let Some(duration) = self.replay_duration {
+                            // the ingestion starts the buffers over from the new position
+                            let mut position = self.max_time as f64 * 1e-6;
+                            let seek_slider = toolbar.add(
+                                egui::Slider::new(&mut position, 0.0..=duration)
+                                    .suffix(" s")
+                                    .text("position"),
+                            );
+                            if seek_slider.drag_stopped() || seek_slider.lost_focus() {
+                                self.stop_markers.clear();
+                                self.ingestion
+                                    .send(ingestion::Command::Seek((position * 1e6) as u64));
+                            }
+
+                            let speed_value = toolbar.add(
+                                egui::DragValue::new(&mut self.replay_speed)
+                                    .range(0.01..=1000.0)
+                                    .speed(0.1)
+                                    .prefix("x"),
+                            );
+                            if speed_value.changed() {
+                                self.ingestion
+                                    .send(ingestion::Command::SetSpeed(self.replay_speed));
+                            }
+                        }
+
                         if let Some(status) = self.current_sampler_status {
                             toolbar.label(format!("{status:?}"));
                         }
@@ -305,6 +2085,21 @@ impl eframe::App for OCDScope {
             });
         });
 
+        self.session_file_dialog.update(ctx);
+
+        if let Some(filename) = self.session_file_dialog.take_picked() {
+            let result = match self.session_file_dialog.mode() {
+                DialogMode::SaveFile => self.start_recording(filename).inspect_err(|_| {
+                    self.stop_recording();
+                }),
+                _ => self.open_session(&filename),
+            };
+
+            if let Err(err) = result {
+                self.show_error("Session error".into(), format!("{:?}", err));
+            }
+        }
+
         let mut reset_plot = false;
 
         egui::panel::SidePanel::left("sidebar")
@@ -320,10 +2115,102 @@ impl eframe::App for OCDScope {
                     });
 
                     ui.label(format!("Size: {}", utils::human_readable_size(used)));
-                    ui.label(format!(
-                        "Capacity: {}",
-                        utils::human_readable_size(capacity)
-                    ));
+                    if self.buffer_memory_limit {
+                        ui.label(format!(
+                            "Capacity: {} of {}",
+                            utils::human_readable_size(capacity),
+                            utils::human_readable_size(self.buffer_memory_limit_at << 20)
+                        ));
+                    } else {
+                        ui.label(format!(
+                            "Capacity: {}",
+                            utils::human_readable_size(capacity)
+                        ));
+                    }
+
+                    let disk: usize = self.samples.values().map(|b| b.disk_footprint()).sum();
+                    if disk > 0 || self.buffer_spill {
+                        ui.label(format!(
+                            "On disk: {} of {}",
+                            utils::human_readable_size(disk),
+                            utils::human_readable_size(self.buffer_disk_limit_at << 30)
+                        ));
+                    }
+                });
+
+                ui.horizontal(|ui| {
+                    ui.label("Export format: ");
+                    egui::ComboBox::from_id_salt("export-format")
+                        .selected_text(self.export_format.description())
+                        .show_ui(ui, |ui| {
+                            for format in ExportFormat::ALL {
+                                ui.selectable_value(
+                                    &mut self.export_format,
+                                    format,
+                                    format.description(),
+                                );
+                            }
+                        });
+                });
+
+                ui.horizontal(|ui| {
+                    ui.label("Export layout: ");
+                    egui::ComboBox::from_id_salt("export-layout")
+                        .selected_text(match self.export_layout {
+                            ExportLayout::Tidy => "Tidy (time, signal, value)",
+                            ExportLayout::Aligned(Alignment::Linear) => "Aligned, interpolated",
+                            ExportLayout::Aligned(Alignment::Hold) => "Aligned, hold",
+                            ExportLayout::Aligned(Alignment::Nearest) => "Aligned, nearest",
+                        })
+                        .show_ui(ui, |ui| {
+                            ui.selectable_value(
+                                &mut self.export_layout,
+                                ExportLayout::Tidy,
+                                "Tidy (time, signal, value)",
+                            );
+                            ui.selectable_value(
+                                &mut self.export_layout,
+                                ExportLayout::Aligned(Alignment::Linear),
+                                "Aligned, interpolated",
+                            );
+                            ui.selectable_value(
+                                &mut self.export_layout,
+                                ExportLayout::Aligned(Alignment::Hold),
+                                "Aligned, hold",
+                            );
+                            ui.selectable_value(
+                                &mut self.export_layout,
+                                ExportLayout::Aligned(Alignment::Nearest),
+                                "Aligned, nearest",
+                            );
+                        });
+                });
+
+                ui.horizontal(|ui| {
+                    ui.label("Range: ");
+                    ui.radio_value(&mut self.export_range, ExportRange::All, "All");
+                    ui.radio_value(&mut self.export_range, ExportRange::PlotView, "Plot view");
+                    ui.radio_value(&mut self.export_range, ExportRange::Selection, "Selection")
+                        .on_hover_text("Drag on the plot with shift held to select");
+                    ui.radio_value(&mut self.export_range, ExportRange::Manual, "Manual");
+                });
+                if self.export_range == ExportRange::Manual {
+                    let (from_t, to_t) = &mut self.export_manual_range;
+                    ui.horizontal(|ui| {
+                        ui.label("From ");
+                        ui.add(egui::DragValue::new(from_t).suffix(" s").speed(0.01));
+                        ui.label("to ");
+                        ui.add(
+                            egui::DragValue::new(to_t)
+                                .range(*from_t..=f64::MAX)
+                                .suffix(" s")
+                                .speed(0.01),
+                        );
+                    });
+                }
+                ui.horizontal(|ui| {
+                    ui.label("Keep one sample every ");
+                    ui.add(egui::DragValue::new(&mut self.export_decimation).range(1..=1_000_000));
                 });
 
                 if ui.button("Export data...").clicked() {
@@ -333,43 +2220,7 @@ impl eframe::App for OCDScope {
                 self.export_file_dialog.update(ctx);
 
                 if let Some(filename) = self.export_file_dialog.take_picked() {
-                    match filename
-                        .extension()
-                        .map(|s| s.to_str().unwrap().to_ascii_lowercase())
-                    {
-                        Some(ext) if ext == "csv" => {
-                            log::info!("exporting CSV file to {:?}", filename);
-
-                            match export::write_csv(&filename, &self.signals, &self.samples) {
-                                Ok(_) => log::info!("export successful"),
-                                Err(err) => {
-                                    self.show_error(
-                                        "CSV export error".into(),
-                                        format!("{:?}", err),
-                                    );
-                                }
-                            }
-                        }
-                        Some(ext) if ext == "npy" => {
-                            log::info!("exporting NumPy file to {:?}", filename);
-                            match export::write_npy(&filename, &self.signals, &self.samples) {
-                                Ok(_) => log::info!("export successful"),
-                                Err(err) => {
-                                    self.show_error(
-                                        "NumPy export error".into(),
-                                        format!("{:?}", err),
-                                    );
-                                }
-                            }
-                        }
-                        Some(ext) => {
-                            self.show_error(
-                                "Unsupported export format".into(),
-                                format!("Cannot export file with extension {ext:?}"),
-                            );
-                        }
-                        None => {} // operation was cancelled
-                    }
+                    self.export(&filename);
                 }
 
                 ui.separator();
@@ -404,6 +2255,50 @@ impl eframe::App for OCDScope {
                     })
                 });
 
+                ui.checkbox(&mut self.buffer_memory_limit, "Buffer memory limit");
+                ui.add_enabled_ui(self.buffer_memory_limit, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.label("Keep at most ");
+                        ui.add(
+                            egui::DragValue::new(&mut self.buffer_memory_limit_at)
+                                .range(16..=1 << 20)
+                                .suffix(" MiB")
+                                .speed(16)
+                                .update_while_editing(false),
+                        );
+                    });
+
+                    ui.checkbox(&mut self.buffer_spill, "Spill older samples to disk")
+                        .on_hover_text(format!(
+                            "Samples over the memory limit are moved to {} instead of being dropped",
+                            self.buffer_spill_directory.display()
+                        ));
+                    ui.add_enabled_ui(self.buffer_spill, |ui| {
+                        ui.horizontal(|ui| {
+                            ui.label("Keep at most ");
+                            ui.add(
+                                egui::DragValue::new(&mut self.buffer_disk_limit_at)
+                                    .range(1..=1 << 16)
+                                    .suffix(" GiB")
+                                    .update_while_editing(false),
+                            );
+                            ui.label(" on disk");
+                        });
+                    });
+                });
+
+                ui.separator();
+
+                egui::CollapsingHeader::new(egui::RichText::new("Trigger").strong())
+                    .default_open(false)
+                    .show(ui, |ui| self.show_trigger_controls(ui));
+
+                ui.separator();
+
+                egui::CollapsingHeader::new(egui::RichText::new("Cursors").strong())
+                    .default_open(false)
+                    .show(ui, |ui| self.show_cursor_readout(ui));
+
                 ui.separator();
 
                 ui.label(egui::RichText::new("Signals").strong());
@@ -414,6 +2309,11 @@ impl eframe::App for OCDScope {
                     .show(ui, |ui| {
                         for signal in self.signals.iter_mut() {
                             ui.horizontal(|item| {
+                                let id = egui::Id::new(("signal-drag", signal.id));
+                                item.dnd_drag_source(id, signal.id, |item| item.label("≡"))
+                                    .response
+                                    .on_hover_text("Drag onto a plot pane to draw it there");
+
                                 some_enable_changed |=
                                     item.checkbox(&mut signal.enabled, "").changed();
 
@@ -424,6 +2324,18 @@ impl eframe::App for OCDScope {
                                         .speed(0.1),
                                 );
 
+                                let mut right_axis = signal.axis == YAxis::Right;
+                                if item
+                                    .toggle_value(&mut right_axis, "R")
+                                    .on_hover_text("Draw against the right Y axis of its pane")
+                                    .changed()
+                                {
+                                    signal.axis = match right_axis {
+                                        true => YAxis::Right,
+                                        false => YAxis::Left,
+                                    };
+                                }
+
                                 egui::TextEdit::singleline(&mut signal.name)
                                     .id(egui::Id::new(format!("signal-name-{}", signal.id)))
                                     .show(item);
@@ -437,132 +2349,131 @@ impl eframe::App for OCDScope {
                     }
                 }
 
-                if some_enable_changed {
-                    if let Some(sampler) = &self.current_sampler {
-                        let active_ids = self
-                            .signals
-                            .iter()
-                            .filter_map(|signal| {
-                                if signal.enabled {
-                                    Some(signal.id)
-                                } else {
-                                    None
-                                }
-                            })
-                            .collect::<Vec<_>>();
+                if ui.button("Add plot pane").clicked() {
+                    self.plot_panes.push(PlotPane::new());
+                }
 
-                        sampler.set_active_signals(&active_ids);
-                    }
+                if some_enable_changed && self.sampler_connected {
+                    let active_ids = self
+                        .signals
+                        .iter()
+                        .filter_map(|signal| {
+                            if signal.enabled {
+                                Some(signal.id)
+                            } else {
+                                None
+                            }
+                        })
+                        .collect::<Vec<_>>();
+
+                    self.ingestion.send(ingestion::Command::SetActiveSignals(active_ids));
                 }
             });
 
-        egui::CentralPanel::default()
-            .show(ctx, |ui| {
-                use egui_plot::{Legend, Line, Plot, PlotBounds};
-
-                // TODO: a vector with linear search might be more efficient, investigate
-                let signal_scales = self
-                    .signals
-                    .iter()
-                    .map(|signal| (signal.name.clone(), signal.scale))
-                    .collect::<HashMap<_, _>>();
-
-                let mut plot = Plot::new("main")
-                    .legend(Legend::default())
-                    .allow_zoom([true, false])
-                    .y_axis_width(2)
-                    .auto_bounds([true, true].into())
-                    .label_formatter(move |name, value| {
-                        if let Some(scale) = signal_scales.get(name) {
-                            format!("{}\nx: {}\ny: {}", name, value.x, value.y / scale)
-                        } else {
-                            "".to_owned()
+        if self.show_log_panel {
+            egui::TopBottomPanel::bottom("log")
+                .resizable(true)
+                .default_height(150.0)
+                .show(ctx, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.label(egui::RichText::new("Log").strong());
+                        if ui.button("Clear").clicked() {
+                            self.log_lines.clear();
                         }
                     });
 
-                if reset_plot {
-                    plot = plot.reset();
-                    self.plot_auto_follow = false;
-                }
+                    egui::ScrollArea::vertical()
+                        .auto_shrink([false, false])
+                        .stick_to_bottom(true)
+                        .show(ui, |ui| {
+                            for line in &self.log_lines {
+                                ui.monospace(line);
+                            }
+                        });
+                });
+        }
 
-                plot.show(ui, |plot_ui| {
-                    for signal in &self.signals {
-                        if signal.enabled {
-                            if let Some(buffer) = self.samples.get(&signal.id) {
-                                // TODO: constructing PlotPoints from an explicit callback makes the Plot widget
-                                // only ask for visible points; figure out how to provide the points in a memory and
-                                // computationally efficient way (some clever data structure might be needed), so that
-                                // we make use of this fact; moreover:
-                                // - try to dynamically adjust the resolution of the plotted signal (`points` parameter)
-                                // - figure out if and how we need to resample the signal, especially if the resolution
-                                //   changes dynamically
-                                // - find a way to handle memory: we might need some `Sync` data structure since the
-                                //   callback must be `'static`
-
-                                /*
-                                let t0 = points[0][0];
-                                let tf = points[points.len() - 1][0];
-                                let count = points.len();
-
-                                let points = points.clone();
-
-                                let plot_points = PlotPoints::from_explicit_callback(
-                                    move |x| {
-                                        // TODO: binary search, or some optimized underlying data structure
-                                        let mut res = points[0][1];
-                                        for &[t, y] in &points {
-                                            res = y;
-                                            if t > x {
-                                                break;
-                                            }
-                                        }
-                                        res
-                                    },
-                                    t0..tf,
-                                    count,
-                                );
-                                */
-
-                                // TODO: handle the auto follow mode
-                                let bounds = plot_ui.plot_bounds();
-                                let (x_min, x_max) = (bounds.min()[0], bounds.max()[0]);
-                                let width = x_max - x_min;
-                                debug_assert!(width >= 0.0);
-                                let margin = if width == 0.0 { 0.1 } else { width };
-
-                                let plot_points = buffer.plot_points(
-                                    x_min - margin / 2.0,
-                                    x_max + margin / 2.0,
-                                    signal.scale,
-                                );
-                                // buffer.plot_points_generator(x_min - margin / 2.0, x_max + margin / 2.0, 1000);
+        egui::CentralPanel::default().show(ctx, |ui| {
+            if reset_plot {
+                self.plot_auto_follow = false;
+            }
 
-                                let line = Line::new(plot_points)
-                                    .name(signal.name.clone())
-                                    .color(signal.color);
+            // dragging with shift held selects the time range to export, instead of panning
+            let selecting = ui.input(|input| input.modifiers.shift);
 
-                                plot_ui.line(line);
-                            }
-                        }
-                    }
+            // while triggering, the captured frame is shown instead of the live signals, with
+            // the times relative to the trigger
+            let captured_frame = match self.trigger_enabled {
+                true => self.trigger.lock().unwrap().frame().cloned(),
+                false => None,
+            };
 
-                    let response = plot_ui.response();
+            // the panes share the height left, each below its header
+            let pane_count = self.plot_panes.len();
+            let header_height = ui.spacing().interact_size.y + 2.0 * ui.spacing().item_spacing.y;
+            let pane_height = (ui.available_height() / pane_count as f32 - header_height).max(40.0);
 
-                    if response.clicked() || response.secondary_clicked() {
-                        self.plot_auto_follow = false;
-                    }
+            let mut moved_signal = None;
+            let mut removed_pane = None;
 
-                    if self.plot_auto_follow {
-                        let x_max = self.max_time as f64 * 1e-6;
-                        let x_min = x_max - self.plot_auto_follow_time;
-                        plot_ui.set_plot_bounds(PlotBounds::from_min_max(
-                            [x_min, -10.0],
-                            [x_max, 10.0],
-                        ))
+            for index in 0..pane_count {
+                let (_, dropped) = ui.dnd_drop_zone::<u32, _>(egui::Frame::none(), |ui| {
+                    if self.show_pane_header(ui, index) {
+                        removed_pane = Some(index);
                     }
+
+                    self.show_plot_pane(
+                        ui,
+                        index,
+                        pane_height,
+                        captured_frame.as_ref(),
+                        selecting,
+                        reset_plot,
+                    );
                 });
-            })
-            .inner;
+
+                if let Some(id) = dropped {
+                    moved_signal = Some((*id, index));
+                }
+            }
+
+            if let Some((id, index)) = moved_signal {
+                if let Some(signal) = self.signals.iter_mut().find(|signal| signal.id == id) {
+                    signal.pane = index;
+                }
+            }
+
+            if let Some(index) = removed_pane {
+                self.remove_plot_pane(index);
+            }
+        });
+
+        if self.show_xy_plot {
+            let mut open = true;
+            egui::Window::new("X-Y plot")
+                .open(&mut open)
+                .default_size([400.0, 400.0])
+                .show(ctx, |ui| self.show_xy_plot(ui));
+            self.show_xy_plot = open;
+        }
+
+        if self.show_spectrum {
+            let mut open = true;
+            egui::Window::new("Spectrum")
+                .open(&mut open)
+                .default_size([500.0, 400.0])
+                .show(ctx, |ui| self.show_spectrum(ui));
+            self.show_spectrum = open;
+        }
+
+        if self.show_spectrogram {
+            let mut open = true;
+            egui::Window::new("Spectrogram")
+                .open(&mut open)
+                .default_size([600.0, 300.0])
+                .show(ctx, |ui| self.show_spectrogram(ui));
+            self.show_spectrogram = open;
+        }
 
         if self.show_add_address_dialog {
             egui::Window::new("Add memory address")
@@ -590,6 +2501,8 @@ impl eframe::App for OCDScope {
                                 format!("0x{:08x}", self.memory_address_to_add),
                                 None,
                             ));
+                            self.ingestion
+                                .send(ingestion::Command::SetSignals(self.signals.clone()));
 
                             self.show_add_address_dialog = false;
                         }
@@ -608,28 +2521,104 @@ impl eframe::App for OCDScope {
                         "Memory sampling",
                     );
                     ui.radio_value(&mut self.sampling_method, SamplingMethod::RTT, "RTT");
+                    ui.radio_value(
+                        &mut self.sampling_method,
+                        SamplingMethod::RTTOverGDB,
+                        "RTT over GDB memory reads",
+                    );
                     ui.radio_value(
                         &mut self.sampling_method,
                         SamplingMethod::Simulated,
                         "Simulated fake data",
                     );
+                    ui.radio_value(
+                        &mut self.sampling_method,
+                        SamplingMethod::Replay,
+                        "Replay a recording",
+                    );
 
                     ui.separator();
 
-                    if matches!(
-                        self.sampling_method,
-                        SamplingMethod::MemorySamping | SamplingMethod::RTT
-                    ) {
+                    if matches!(self.sampling_method, SamplingMethod::Replay) {
+                        ui.horizontal(|ui| {
+                            if let Some(path) = self.replay_file_dialog.update(ctx).picked() {
+                                self.replay_filename = Some(path.to_path_buf());
+                            }
+
+                            let replay_label_text = match &self.replay_filename {
+                                Some(path) => {
+                                    path.file_name().unwrap().to_string_lossy().into_owned()
+                                }
+                                None => "<no recording>".into(),
+                            };
+                            ui.label(replay_label_text);
+
+                            if ui.button("Open..").clicked() {
+                                self.replay_file_dialog.pick_file();
+                            }
+                        });
+                        ui.horizontal(|ui| {
+                            ui.label("Speed: ");
+                            ui.add(
+                                egui::DragValue::new(&mut self.replay_speed)
+                                    .range(0.01..=1000.0)
+                                    .speed(0.1)
+                                    .prefix("x"),
+                            );
+                        });
+                    }
+
+                    if matches!(self.sampling_method, SamplingMethod::RTT)
+                        || (matches!(self.sampling_method, SamplingMethod::MemorySamping)
+                            && self.gdb_server == GDBServerKind::OpenOCD)
+                    {
                         ui.horizontal(|ui| {
                             ui.label("OpenOCD Telnet endpoint: ");
                             ui.text_edit_singleline(&mut self.telnet_address);
                         });
                     }
-                    if matches!(self.sampling_method, SamplingMethod::MemorySamping) {
+                    if matches!(
+                        self.sampling_method,
+                        SamplingMethod::MemorySamping | SamplingMethod::RTTOverGDB
+                    ) {
                         ui.horizontal(|ui| {
-                            ui.label("OpenOCD GDB endpoint: ");
+                            ui.label("GDB endpoint: ");
                             ui.text_edit_singleline(&mut self.gdb_address);
                         });
+                        ui.horizontal(|ui| {
+                            ui.label("GDB server: ");
+                            ui.radio_value(&mut self.gdb_server, GDBServerKind::OpenOCD, "OpenOCD");
+                            ui.radio_value(
+                                &mut self.gdb_server,
+                                GDBServerKind::Generic,
+                                "Generic GDB remote",
+                            );
+                        });
+                    }
+                    if matches!(self.sampling_method, SamplingMethod::MemorySamping) {
+                        ui.checkbox(
+                            &mut self.gdb_resume_target,
+                            "Resume target after connecting",
+                        )
+                        .on_hover_text(
+                            "Some GDB servers, like QEMU, can't read memory while the target runs",
+                        );
+                        ui.horizontal(|ui| {
+                            ui.checkbox(&mut self.gdb_merge_reads, "Read across gaps up to");
+                            ui.add_enabled(
+                                self.gdb_merge_reads,
+                                egui::DragValue::new(&mut self.gdb_max_read_gap)
+                                    .range(4..=1024)
+                                    .suffix(" bytes"),
+                            );
+                        })
+                        .response
+                        .on_hover_text(
+                            "Fewer requests, but the memory between the variables gets read too, \
+                            which may have side effects on peripheral registers",
+                        );
+                    }
+                    if matches!(self.sampling_method, SamplingMethod::MemorySamping) {
                         ui.horizontal(|ui| {
                             if let Some(path) = self.elf_file_dialog.update(ctx).picked() {
                                 self.elf_filename = Some(path.to_path_buf());
@@ -650,7 +2639,9 @@ impl eframe::App for OCDScope {
                     }
                     if matches!(
                         self.sampling_method,
-                        SamplingMethod::MemorySamping | SamplingMethod::Simulated
+                        SamplingMethod::MemorySamping
+                            | SamplingMethod::Simulated
+                            | SamplingMethod::Replay
                     ) {
                         ui.horizontal(|ui| {
                             ui.label("Sampling rate [Hz]: ");
@@ -662,7 +2653,78 @@ impl eframe::App for OCDScope {
                             );
                         });
                     }
-                    if matches!(self.sampling_method, SamplingMethod::RTT) {
+                    if matches!(self.sampling_method, SamplingMethod::MemorySamping) {
+                        ui.checkbox(
+                            &mut self.watchpoint_capture_enabled,
+                            "Capture on watchpoint hit",
+                        );
+                        if self.watchpoint_capture_enabled {
+                            ui.horizontal(|ui| {
+                                egui::ComboBox::from_id_salt("watchpoint-kind")
+                                    .selected_text(format!("{:?}", self.watchpoint_kind))
+                                    .show_ui(ui, |ui| {
+                                        for kind in [
+                                            WatchpointKind::Write,
+                                            WatchpointKind::Read,
+                                            WatchpointKind::Access,
+                                        ] {
+                                            ui.selectable_value(
+                                                &mut self.watchpoint_kind,
+                                                kind,
+                                                format!("{:?}", kind),
+                                            );
+                                        }
+                                    });
+                                ui.label("watchpoint at");
+                                ui.add(
+                                    egui::DragValue::new(&mut self.watchpoint_address)
+                                        .hexadecimal(8, false, true)
+                                        .range(0..=u32::MAX),
+                                );
+                                egui::ComboBox::from_id_salt("watchpoint-length")
+                                    .selected_text(format!("{} bit", 8 * self.watchpoint_length))
+                                    .show_ui(ui, |ui| {
+                                        for length in [1, 2, 4] {
+                                            ui.selectable_value(
+                                                &mut self.watchpoint_length,
+                                                length,
+                                                format!("{} bit", 8 * length),
+                                            );
+                                        }
+                                    });
+                            });
+                            ui.horizontal(|ui| {
+                                ui.label("Pre-trigger history [s]: ");
+                                ui.add(
+                                    egui::DragValue::new(&mut self.watchpoint_pre_trigger)
+                                        .range(0.001..=3600.0)
+                                        .speed(0.1),
+                                );
+                            });
+                            ui.checkbox(
+                                &mut self.watchpoint_resume_after_hit,
+                                "Resume target after hit",
+                            );
+                        }
+                    }
+                    if matches!(
+                        self.sampling_method,
+                        SamplingMethod::RTT | SamplingMethod::RTTOverGDB
+                    ) {
+                        ui.horizontal(|ui| {
+                            ui.label("Control block search: ");
+                            ui.add(
+                                egui::DragValue::new(&mut self.rtt_search_address)
+                                    .hexadecimal(8, false, true)
+                                    .range(0..=u32::MAX),
+                            );
+                            ui.label("+");
+                            ui.add(
+                                egui::DragValue::new(&mut self.rtt_search_size)
+                                    .range(1..=u32::MAX)
+                                    .suffix(" bytes"),
+                            );
+                        });
                         ui.horizontal(|ui| {
                             ui.label("Polling interval [ms]: ");
                             ui.add(
@@ -670,6 +2732,87 @@ impl eframe::App for OCDScope {
                             );
                         });
                         ui.checkbox(&mut self.rtt_relative_time, "Relative timestamp");
+                        if matches!(self.sampling_method, SamplingMethod::RTT) {
+                            ui.checkbox(
+                                &mut self.rtt_halt_to_synchronize,
+                                "Halt target to synchronize stream",
+                            );
+                        }
+                        if matches!(self.sampling_method, SamplingMethod::RTTOverGDB) {
+                            ui.checkbox(&mut self.rtt_halt_to_read, "Halt target to read buffer")
+                                .on_hover_text(
+                                    "Some GDB servers, like QEMU, can't read memory while the target runs",
+                                );
+                        }
+
+                        ui.horizontal(|ui| {
+                            ui.label("Timestamp unit: ");
+                            egui::ComboBox::from_id_salt("rtt-timestamp-unit")
+                                .selected_text(match self.rtt_timestamp_unit {
+                                    RTTTimestampUnit::Microseconds => "µs",
+                                    RTTTimestampUnit::Nanoseconds => "ns",
+                                    RTTTimestampUnit::CpuCycles(_) => "CPU cycles",
+                                })
+                                .show_ui(ui, |ui| {
+                                    ui.selectable_value(
+                                        &mut self.rtt_timestamp_unit,
+                                        RTTTimestampUnit::Microseconds,
+                                        "µs",
+                                    );
+                                    ui.selectable_value(
+                                        &mut self.rtt_timestamp_unit,
+                                        RTTTimestampUnit::Nanoseconds,
+                                        "ns",
+                                    );
+                                    let cycles = matches!(
+                                        self.rtt_timestamp_unit,
+                                        RTTTimestampUnit::CpuCycles(_)
+                                    );
+                                    if ui.selectable_label(cycles, "CPU cycles").clicked() {
+                                        self.rtt_timestamp_unit =
+                                            RTTTimestampUnit::CpuCycles(100.0);
+                                    }
+                                });
+                            if let RTTTimestampUnit::CpuCycles(mhz) = &mut self.rtt_timestamp_unit {
+                                ui.label("at");
+                                ui.add(
+                                    egui::DragValue::new(mhz)
+                                        .range(0.001..=10_000.0)
+                                        .suffix(" MHz"),
+                                );
+                            }
+                        });
+
+                        ui.label("Time base for channels without timestamp:");
+                        ui.horizontal(|ui| {
+                            let nominal_rate = matches!(
+                                self.rtt_untimed_time_source,
+                                RTTTimeSource::NominalRate(_)
+                            );
+                            if ui.radio(nominal_rate, "Nominal rate").clicked() {
+                                self.rtt_untimed_time_source =
+                                    RTTTimeSource::NominalRate(self.sample_rate);
+                            }
+                            if let RTTTimeSource::NominalRate(rate) =
+                                &mut self.rtt_untimed_time_source
+                            {
+                                ui.add(
+                                    egui::DragValue::new(rate)
+                                        .range(0.01..=1_000_000.0)
+                                        .suffix(" Hz"),
+                                );
+                            }
+                        });
+                        ui.radio_value(
+                            &mut self.rtt_untimed_time_source,
+                            RTTTimeSource::HostTime,
+                            "Host receive time",
+                        );
+                        ui.radio_value(
+                            &mut self.rtt_untimed_time_source,
+                            RTTTimeSource::SampleIndex,
+                            "Sample index",
+                        );
                     }
 
                     ui.separator();
@@ -679,13 +2822,15 @@ impl eframe::App for OCDScope {
                             self.show_connect_dialog = false;
                         }
                         if ui.button("Connect").clicked() {
-                            debug_assert!(self.current_sampler.is_none());
+                            debug_assert!(!self.sampler_connected);
 
                             self.show_connect_dialog = false;
 
                             match self.try_connect_sampler() {
                                 Ok(sampler) => {
-                                    self.current_sampler = Some(sampler);
+                                    self.record_connection();
+                                    self.ingestion.send(ingestion::Command::Attach(sampler));
+                                    self.sampler_connected = true;
                                 }
                                 Err(err) => {
                                     self.show_error(
@@ -740,7 +2885,7 @@ fn main() {
             };
             creation_context.egui_ctx.set_style(style);
 
-            let app = OCDScope::new();
+            let app = OCDScope::new(creation_context.egui_ctx.clone());
             Ok(Box::new(app))
         }),
     )
@@ -0,0 +1,123 @@
+// The plot is split in vertically stacked panes, with linked time axes: each signal is drawn in one
+// of them, against either the left Y axis or the secondary right one.
+//
+// egui_plot has a single transform per plot, so the signals on the right axis are mapped onto the
+// left one, stretching them over the range of the signals on the left, and the right axis labels
+// are mapped back.
+
+use egui_plot::{PlotPoint, PlotTransform};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum YAxis {
+    #[default]
+    Left,
+    Right,
+}
+
+/// Linear mapping of the values on the right axis onto the left one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AxisMapping {
+    scale: f64,
+    offset: f64,
+}
+
+impl AxisMapping {
+    pub const IDENTITY: AxisMapping = AxisMapping {
+        scale: 1.0,
+        offset: 0.0,
+    };
+
+    /// The mapping stretching the range `from` over `to`; an empty range only gets centered.
+    pub fn fit(from: (f64, f64), to: (f64, f64)) -> AxisMapping {
+        let (from_span, to_span) = (from.1 - from.0, to.1 - to.0);
+
+        let scale = if from_span > 0.0 && to_span > 0.0 {
+            to_span / from_span
+        } else {
+            1.0
+        };
+        let offset = (to.0 + to.1) / 2.0 - scale * (from.0 + from.1) / 2.0;
+
+        AxisMapping { scale, offset }
+    }
+
+    pub fn apply(&self, y: f64) -> f64 {
+        self.scale * y + self.offset
+    }
+
+    pub fn invert(&self, y: f64) -> f64 {
+        (y - self.offset) / self.scale
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PlotPane {
+    /// Mapping of the right axis, as computed drawing the last frame.
+    pub right_axis: AxisMapping,
+    /// Values of the horizontal measurement cursors, on the left axis.
+    pub cursor_values: [f64; 2],
+    /// Transform between the plot and the screen, as of the last frame.
+    pub transform: Option<PlotTransform>,
+}
+
+impl PlotPane {
+    pub fn new() -> PlotPane {
+        PlotPane {
+            right_axis: AxisMapping::IDENTITY,
+            cursor_values: [0.0; 2],
+            transform: None,
+        }
+    }
+}
+
+/// Range of the values of `points`, if any.
+pub fn value_range(points: &[PlotPoint]) -> Option<(f64, f64)> {
+    points
+        .iter()
+        .map(|point| point.y)
+        .filter(|y| y.is_finite())
+        .fold(None, |range, y| match range {
+            None => Some((y, y)),
+            Some((min, max)) => Some((y.min(min), y.max(max))),
+        })
+}
+
+/// Union of two optional ranges.
+pub fn merge_ranges(a: Option<(f64, f64)>, b: Option<(f64, f64)>) -> Option<(f64, f64)> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some((a.0.min(b.0), a.1.max(b.1))),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_axis_mapping() {
+        let mapping = AxisMapping::fit((0.0, 10_000.0), (0.0, 3.3));
+        assert!((mapping.apply(10_000.0) - 3.3).abs() < 1e-12);
+        assert!((mapping.apply(5_000.0) - 1.65).abs() < 1e-12);
+        assert!((mapping.invert(1.65) - 5_000.0).abs() < 1e-9);
+
+        // a constant signal is centered over the other ones, without scaling
+        let mapping = AxisMapping::fit((5.0, 5.0), (-1.0, 1.0));
+        assert_eq!(mapping.apply(5.0), 0.0);
+        assert_eq!(mapping.apply(6.0), 1.0);
+
+        let points = [
+            PlotPoint::new(0.0, 2.0),
+            PlotPoint::new(1.0, f64::NAN),
+            PlotPoint::new(2.0, -1.0),
+        ];
+        assert_eq!(value_range(&points), Some((-1.0, 2.0)));
+        assert_eq!(value_range(&[]), None);
+        assert_eq!(
+            merge_ranges(Some((0.0, 1.0)), Some((-1.0, 0.5))),
+            Some((-1.0, 1.0))
+        );
+        assert_eq!(merge_ranges(None, Some((0.0, 1.0))), Some((0.0, 1.0)));
+    }
+}
@@ -0,0 +1,691 @@
+use std::{
+    net::{SocketAddr, ToSocketAddrs},
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+
+use crate::{
+    gdbremote::{GDBRemote, GDBServerKind},
+    sampler::{Notification, Sample, Sampler, Status},
+};
+
+use super::rttstream::{
+    is_scope_channel_name, parse_scope_packet_structure, RTTSampleStream, RTTScopePacketStructure,
+    RTTSettings,
+};
+
+const SAMPLE_BUFFER_SIZE: usize = 10000;
+
+// layout of the RTT control block (`SEGGER_RTT_CB`) on 32-bit targets
+const RTT_CONTROL_BLOCK_ID: &[u8] = b"SEGGER RTT";
+const RTT_CONTROL_BLOCK_HEADER_SIZE: u32 = 24;
+const RTT_BUFFER_DESCRIPTOR_SIZE: u32 = 24;
+const RTT_MAX_CHANNEL_NAME_LENGTH: usize = 64;
+
+// TODO:
+// - use the `_SEGGER_RTT` symbol address from the ELF file, if provided, instead of searching
+// - let user specify RTT channel ID or name, if wanted
+
+#[derive(Debug)]
+enum ThreadCommand {
+    Pause,
+    Resume,
+    Stop,
+}
+
+/// Reads the RTT ring buffers directly from target memory, through the GDB remote protocol,
+/// instead of relying on the RTT server of OpenOCD.
+pub struct GDBRTTSampler {
+    join_handle: thread::JoinHandle<()>,
+    command_tx: mpsc::Sender<ThreadCommand>,
+    notifications_rx: mpsc::Receiver<Notification>,
+    sampled_rx: mpsc::Receiver<Sample>,
+    available_signals: Vec<(u32, String)>,
+}
+
+/// Settings of the [`GDBRTTSampler`]: how to talk to the GDB server, and the RTT ones.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GDBRTTSettings {
+    pub gdb_server: GDBServerKind,
+    /// Halt the target to read the ring buffer, for the all-stop GDB servers which can't access
+    /// memory while the target runs, like QEMU or a GDB stub on the target.
+    pub halt_to_read: bool,
+    pub rtt: RTTSettings,
+}
+
+/// Descriptor of an up-buffer of the RTT control block (`SEGGER_RTT_BUFFER_UP`).
+#[derive(Debug, Clone)]
+struct RTTUpBuffer {
+    descriptor_address: u32,
+    name: String,
+    buffer_address: u32,
+    size: u32,
+}
+
+impl RTTUpBuffer {
+    fn write_offset_address(&self) -> u32 {
+        self.descriptor_address + 12
+    }
+
+    fn read_offset_address(&self) -> u32 {
+        self.descriptor_address + 16
+    }
+}
+
+impl GDBRTTSampler {
+    pub fn start<A: ToSocketAddrs>(
+        gdb_address: A,
+        settings: GDBRTTSettings,
+    ) -> anyhow::Result<GDBRTTSampler> {
+        let (sampled_tx, sampled_rx) = mpsc::sync_channel(SAMPLE_BUFFER_SIZE);
+        let (command_tx, command_rx) = mpsc::channel();
+        let (notifications_tx, notifications_rx) = mpsc::channel();
+
+        let gdb_address: SocketAddr = gdb_address
+            .to_socket_addrs()?
+            .next()
+            .context("no addresses provided")?;
+
+        let mut gdb = GDBRemote::connect(gdb_address).context("failed to connect to GDB")?;
+
+        gdb.set_timeout(Duration::from_millis(2000));
+        gdb.handshake(settings.gdb_server)
+            .context("GDB handshake failed")?;
+
+        let control_block_address = find_control_block(
+            &mut gdb,
+            settings.rtt.search_address,
+            settings.rtt.search_size,
+        )
+        .context("failed to find RTT control block")?;
+        log::debug!("found RTT control block at 0x{:08X}", control_block_address);
+
+        let up_buffers = read_up_buffers(&mut gdb, control_block_address)
+            .context("failed to read RTT up-buffers")?;
+        log::debug!("RTT up-buffers {:?}", up_buffers);
+
+        // TODO: we could handle multiple RTT channels, in the future, if wanted
+        let up_buffer = up_buffers
+            .into_iter()
+            .find(|buffer| is_scope_channel_name(&buffer.name))
+            .context("no suitable RTT channels found")?;
+
+        log::debug!("picked RTT up-buffer {:?}", up_buffer);
+
+        let packet_structure = parse_scope_packet_structure(&up_buffer.name)
+            .context("failed to parse RTT channel name into a packet structure")?;
+
+        log::debug!("parsed scope packet structure {:?}", packet_structure);
+
+        let available_signals = packet_structure.available_signals();
+        log::debug!("available signals {:?}", &available_signals);
+
+        let join_handle = thread::spawn(move || {
+            let result = sampler_thread(
+                gdb,
+                up_buffer,
+                packet_structure,
+                settings,
+                sampled_tx,
+                command_rx,
+                notifications_tx.clone(),
+            );
+
+            if let Err(err) = result {
+                log::error!("sampler thread returned with error: {:?}", err);
+                log::debug!("sending error notification and switch to terminated state");
+
+                // ignore the send errors instead of unwrapping, at this point if even sending to
+                // `notifications_tx` fails, the situation is sort of unrecoverable
+                if let Err(e) = notifications_tx.send(Notification::Error(format!("{:?}", err))) {
+                    log::error!("error notification send failed: {:?}", e);
+                }
+                if let Err(e) = notifications_tx.send(Notification::NewStatus(Status::Terminated)) {
+                    log::error!("new status notification send failed: {:?}", e);
+                }
+            }
+        });
+
+        let sampler = GDBRTTSampler {
+            join_handle,
+            command_tx,
+            sampled_rx,
+            notifications_rx,
+            available_signals,
+        };
+
+        Ok(sampler)
+    }
+}
+
+impl Sampler for GDBRTTSampler {
+    fn available_signals(&self) -> Vec<(u32, String)> {
+        self.available_signals.clone()
+    }
+
+    fn set_active_signals(&self, _ids: &[u32]) {
+        // do nothing, since we don't decide what signals we receive
+    }
+
+    fn sampled_channel(&self) -> &mpsc::Receiver<Sample> {
+        &self.sampled_rx
+    }
+
+    fn notification_channel(&self) -> &mpsc::Receiver<Notification> {
+        &self.notifications_rx
+    }
+
+    fn pause(&self) {
+        if let Err(err) = self.command_tx.send(ThreadCommand::Pause) {
+            log::error!("failed to send pause command: {:?}", err);
+        }
+    }
+
+    fn resume(&self) {
+        if let Err(err) = self.command_tx.send(ThreadCommand::Resume) {
+            log::error!("failed to send resume command: {:?}", err);
+        }
+    }
+
+    fn stop(self: Box<Self>) {
+        if let Err(err) = self.command_tx.send(ThreadCommand::Stop) {
+            log::debug!("asked to stop sampler but thread seems to already be dead (command send failed: {:?})", err);
+
+            debug_assert!(self.join_handle.is_finished());
+        }
+
+        // TODO: if there are implementation errors in the sampler thread, and the Stop command is not processed,
+        // this can block indefinitely
+        if let Err(err) = self.join_handle.join() {
+            log::warn!("failed to join sampler thread: {:?}", err);
+        }
+    }
+}
+
+fn sampler_thread(
+    mut gdb: GDBRemote,
+    up_buffer: RTTUpBuffer,
+    packet_structure: RTTScopePacketStructure,
+    settings: GDBRTTSettings,
+    sampled_tx: mpsc::SyncSender<Sample>,
+    command_rx: mpsc::Receiver<ThreadCommand>,
+    notifications_tx: mpsc::Sender<Notification>,
+) -> anyhow::Result<()> {
+    let info = |message: &str| {
+        log::info!("{}", message);
+        if let Err(err) = notifications_tx.send(Notification::Info(message.to_string())) {
+            log::error!("Failed to send info notification: {:?}", err);
+        }
+    };
+
+    let polling_period = Duration::from_millis(settings.rtt.polling_interval as u64);
+
+    let mut status = Status::Initializing;
+
+    let mut stream = RTTSampleStream::new(packet_structure, &settings.rtt);
+
+    let mut last_polled_at = Instant::now();
+
+    loop {
+        let mut maybe_new_status = None;
+
+        match status {
+            Status::Initializing => {
+                // start reading from where the target is currently writing, while it's still
+                // halted after connecting: the RTT writes in the ring-buffer are atomic, so we
+                // begin at a packet boundary
+                let (offsets, _) = gdb.read_memory(up_buffer.write_offset_address(), 4)?;
+                gdb.write_memory(up_buffer.read_offset_address(), &offsets)?;
+
+                info("RTT stream synchronized");
+
+                // make target continue
+                log::trace!("sending GDB continue command");
+                gdb.continue_target()?;
+                log::trace!("target resumed");
+
+                stream.restart_rate_measurement();
+                last_polled_at = Instant::now();
+                maybe_new_status = Some(Status::Sampling);
+            }
+            Status::Sampling | Status::Paused => {
+                // 1. process commands, if any
+                match command_rx.try_recv() {
+                    Ok(ThreadCommand::Stop) => {
+                        maybe_new_status = Some(Status::Terminated);
+                    }
+                    Ok(ThreadCommand::Pause) if matches!(status, Status::Sampling) => {
+                        maybe_new_status = Some(Status::Paused);
+                    }
+                    Ok(ThreadCommand::Resume) if matches!(status, Status::Paused) => {
+                        maybe_new_status = Some(Status::Sampling);
+                    }
+                    Ok(other) => {
+                        log::warn!("Unexpected command in state {:?}: {:?}", status, other);
+                    }
+                    Err(mpsc::TryRecvError::Empty) => {}
+                    Err(mpsc::TryRecvError::Disconnected) => {
+                        anyhow::bail!("thread command channel closed TX end")
+                    }
+                }
+
+                // 2. wait for the next polling time
+                let elapsed = last_polled_at.elapsed();
+                if elapsed < polling_period {
+                    thread::sleep(polling_period - elapsed);
+                }
+                last_polled_at = Instant::now();
+
+                // 3. drain the ring buffer; a target that stopped on its own is left halted
+                let interrupted = settings.halt_to_read && gdb.interrupt_target()?;
+
+                let data = read_ring_buffer(&mut gdb, &up_buffer)?;
+                let received_at = Instant::now();
+
+                if interrupted {
+                    gdb.continue_target()?;
+                }
+
+                // the time of the events can't be related to the target timestamps of the samples
+                while let Some((event, _)) = gdb.pop_async_event() {
+                    notifications_tx.send(Notification::from_gdb_event(event, None))?;
+                }
+
+                stream.process(
+                    &data,
+                    received_at,
+                    matches!(status, Status::Sampling),
+                    &sampled_tx,
+                    &notifications_tx,
+                )?;
+            }
+            Status::Terminated => {
+                // break the main loop, finishing this thread
+                break;
+            }
+        }
+
+        match maybe_new_status {
+            Some(new_status) if new_status != status => {
+                notifications_tx.send(Notification::NewStatus(new_status))?;
+                status = new_status;
+            }
+            _ => {}
+        }
+    }
+
+    log::info!("sampler thread gracefully finished");
+
+    Ok(())
+}
+
+/// Read an arbitrarily long memory region, splitting it in multiple requests.
+fn read_memory_chunked(
+    gdb: &mut GDBRemote,
+    address: u32,
+    length: usize,
+) -> anyhow::Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(length);
+
+    while data.len() < length {
+        let chunk_length = usize::min(gdb.max_memory_read_size(), length - data.len());
+        let (chunk, _) = gdb.read_memory(address + data.len() as u32, chunk_length)?;
+        data.extend_from_slice(&chunk);
+    }
+
+    Ok(data)
+}
+
+fn find_control_block(gdb: &mut GDBRemote, address: u32, size: u32) -> anyhow::Result<u32> {
+    let end = address as u64 + size as u64;
+
+    // keep the tail of the previous chunk around, in case the ID spans two chunks
+    let mut window = Vec::new();
+    let mut window_address = address as u64;
+    let mut chunk_address = address as u64;
+
+    while chunk_address < end {
+        let chunk_length =
+            u64::min(gdb.max_memory_read_size() as u64, end - chunk_address) as usize;
+        let (chunk, _) = gdb.read_memory(chunk_address as u32, chunk_length)?;
+        window.extend_from_slice(&chunk);
+        chunk_address += chunk_length as u64;
+
+        if let Some(i) = find_subslice(&window, RTT_CONTROL_BLOCK_ID) {
+            return Ok((window_address + i as u64) as u32);
+        }
+
+        let keep = usize::min(window.len(), RTT_CONTROL_BLOCK_ID.len() - 1);
+        window_address += (window.len() - keep) as u64;
+        window.drain(..window.len() - keep);
+    }
+
+    anyhow::bail!("no control block found in 0x{:08X}..0x{:08X}", address, end);
+}
+
+fn find_subslice(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_up_buffers(
+    gdb: &mut GDBRemote,
+    control_block_address: u32,
+) -> anyhow::Result<Vec<RTTUpBuffer>> {
+    let header = read_memory_chunked(
+        gdb,
+        control_block_address,
+        RTT_CONTROL_BLOCK_HEADER_SIZE as usize,
+    )?;
+    let max_up_buffers = read_u32(&header, 16);
+
+    // sanity check, to avoid reading garbage if the control block is not initialized yet
+    if max_up_buffers > 32 {
+        anyhow::bail!("implausible number of up-buffers ({})", max_up_buffers);
+    }
+
+    let descriptors_address = control_block_address + RTT_CONTROL_BLOCK_HEADER_SIZE;
+    let descriptors = read_memory_chunked(
+        gdb,
+        descriptors_address,
+        (max_up_buffers * RTT_BUFFER_DESCRIPTOR_SIZE) as usize,
+    )?;
+
+    let mut up_buffers = Vec::new();
+
+    for (i, descriptor) in descriptors
+        .chunks(RTT_BUFFER_DESCRIPTOR_SIZE as usize)
+        .enumerate()
+    {
+        let name_address = read_u32(descriptor, 0);
+        let buffer_address = read_u32(descriptor, 4);
+        let size = read_u32(descriptor, 8);
+
+        if name_address == 0 || buffer_address == 0 || size == 0 {
+            // unused buffer
+            continue;
+        }
+
+        up_buffers.push(RTTUpBuffer {
+            descriptor_address: descriptors_address + i as u32 * RTT_BUFFER_DESCRIPTOR_SIZE,
+            name: read_c_string(gdb, name_address)?,
+            buffer_address,
+            size,
+        });
+    }
+
+    Ok(up_buffers)
+}
+
+fn read_c_string(gdb: &mut GDBRemote, address: u32) -> anyhow::Result<String> {
+    let mut bytes = Vec::new();
+
+    // read in small steps, so that we don't go past the end of valid memory
+    while bytes.len() < RTT_MAX_CHANNEL_NAME_LENGTH {
+        let (chunk, _) = gdb.read_memory(address + bytes.len() as u32, 16)?;
+
+        if let Some(nul) = chunk.iter().position(|&b| b == 0) {
+            bytes.extend_from_slice(&chunk[..nul]);
+            break;
+        }
+
+        bytes.extend_from_slice(&chunk);
+    }
+
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Read all the data available in an up-buffer, and advance its read offset accordingly.
+fn read_ring_buffer(gdb: &mut GDBRemote, up_buffer: &RTTUpBuffer) -> anyhow::Result<Vec<u8>> {
+    // write offset and read offset are contiguous, read both at once
+    let (offsets, _) = gdb.read_memory(up_buffer.write_offset_address(), 8)?;
+    let write_offset = read_u32(&offsets, 0);
+    let read_offset = read_u32(&offsets, 4);
+
+    if write_offset >= up_buffer.size || read_offset >= up_buffer.size {
+        anyhow::bail!(
+            "RTT ring buffer offsets out of range (write {}, read {}, size {})",
+            write_offset,
+            read_offset,
+            up_buffer.size
+        );
+    }
+
+    if write_offset == read_offset {
+        return Ok(Vec::new());
+    }
+
+    let mut data = Vec::new();
+
+    if write_offset > read_offset {
+        data.extend(read_memory_chunked(
+            gdb,
+            up_buffer.buffer_address + read_offset,
+            (write_offset - read_offset) as usize,
+        )?);
+    } else {
+        // the data wraps around the end of the ring buffer
+        data.extend(read_memory_chunked(
+            gdb,
+            up_buffer.buffer_address + read_offset,
+            (up_buffer.size - read_offset) as usize,
+        )?);
+        data.extend(read_memory_chunked(
+            gdb,
+            up_buffer.buffer_address,
+            write_offset as usize,
+        )?);
+    }
+
+    gdb.write_memory(up_buffer.read_offset_address(), &write_offset.to_le_bytes())?;
+
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::gdbremote::mock_stub::{MockStub, MockStubConfig};
+    use crate::sampler::{RTTTimeSource, RTTTimestampUnit};
+
+    const MEMORY_BASE: u32 = 0x2000_0000;
+    /// The ID straddles the end of the first read, as the stub reads at most 0x80 bytes at once.
+    const CONTROL_BLOCK: u32 = MEMORY_BASE + 0x7c;
+    const DESCRIPTORS: u32 = CONTROL_BLOCK + RTT_CONTROL_BLOCK_HEADER_SIZE;
+    const SCOPE_DESCRIPTOR: u32 = DESCRIPTORS + 2 * RTT_BUFFER_DESCRIPTOR_SIZE;
+    const SCOPE_BUFFER: u32 = MEMORY_BASE + 0x1c0;
+
+    fn put_u32(memory: &mut [u8], address: u32, value: u32) {
+        let offset = (address - MEMORY_BASE) as usize;
+        memory[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn put_bytes(memory: &mut [u8], address: u32, bytes: &[u8]) {
+        let offset = (address - MEMORY_BASE) as usize;
+        memory[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    /// Memory with a control block of three up-buffers: a terminal, an unused one and a scope.
+    fn rtt_memory() -> Vec<u8> {
+        let mut memory = vec![0; 0x200];
+
+        put_bytes(&mut memory, CONTROL_BLOCK, RTT_CONTROL_BLOCK_ID);
+        put_u32(&mut memory, CONTROL_BLOCK + 16, 3);
+
+        put_u32(&mut memory, DESCRIPTORS, MEMORY_BASE + 0x170);
+        put_u32(&mut memory, DESCRIPTORS + 4, MEMORY_BASE + 0x1e0);
+        put_u32(&mut memory, DESCRIPTORS + 8, 16);
+        put_bytes(&mut memory, MEMORY_BASE + 0x170, b"Terminal\0");
+
+        put_u32(&mut memory, SCOPE_DESCRIPTOR, MEMORY_BASE + 0x180);
+        put_u32(&mut memory, SCOPE_DESCRIPTOR + 4, SCOPE_BUFFER);
+        put_u32(&mut memory, SCOPE_DESCRIPTOR + 8, 32);
+        put_bytes(&mut memory, MEMORY_BASE + 0x180, b"JScope_F4\0");
+
+        memory
+    }
+
+    fn connect(stub: &MockStub) -> GDBRemote {
+        let mut gdb = GDBRemote::connect(stub.address).unwrap();
+        gdb.handshake(GDBServerKind::Generic).unwrap();
+        gdb
+    }
+
+    #[test]
+    fn test_find_control_block() {
+        let stub = MockStub::spawn(MockStubConfig::default(), rtt_memory());
+        let mut gdb = connect(&stub);
+
+        assert_eq!(gdb.max_memory_read_size(), 0x80);
+        assert_eq!(
+            find_control_block(&mut gdb, MEMORY_BASE, 0x200).unwrap(),
+            CONTROL_BLOCK
+        );
+
+        // the ID must lie entirely in the searched region
+        assert!(find_control_block(&mut gdb, MEMORY_BASE, 0x80).is_err());
+        assert!(find_control_block(&mut gdb, MEMORY_BASE + 0x100, 0x100).is_err());
+    }
+
+    #[test]
+    fn test_read_up_buffers() {
+        let stub = MockStub::spawn(MockStubConfig::default(), rtt_memory());
+        let mut gdb = connect(&stub);
+
+        let up_buffers = read_up_buffers(&mut gdb, CONTROL_BLOCK).unwrap();
+        assert_eq!(up_buffers.len(), 2);
+
+        assert_eq!(up_buffers[0].name, "Terminal");
+        assert_eq!(up_buffers[0].descriptor_address, DESCRIPTORS);
+        assert_eq!(up_buffers[0].buffer_address, MEMORY_BASE + 0x1e0);
+        assert_eq!(up_buffers[0].size, 16);
+
+        // the unused descriptor is skipped
+        assert_eq!(up_buffers[1].name, "JScope_F4");
+        assert_eq!(up_buffers[1].descriptor_address, SCOPE_DESCRIPTOR);
+        assert_eq!(up_buffers[1].buffer_address, SCOPE_BUFFER);
+        assert_eq!(up_buffers[1].size, 32);
+        assert_eq!(up_buffers[1].write_offset_address(), SCOPE_DESCRIPTOR + 12);
+        assert_eq!(up_buffers[1].read_offset_address(), SCOPE_DESCRIPTOR + 16);
+
+        // an uninitialized control block is rejected
+        put_u32(
+            &mut stub.memory.lock().unwrap(),
+            CONTROL_BLOCK + 16,
+            0xffff_ffff,
+        );
+        assert!(read_up_buffers(&mut gdb, CONTROL_BLOCK).is_err());
+    }
+
+    #[test]
+    fn test_read_ring_buffer() {
+        let stub = MockStub::spawn(MockStubConfig::default(), rtt_memory());
+        let mut gdb = connect(&stub);
+
+        let up_buffer = read_up_buffers(&mut gdb, CONTROL_BLOCK)
+            .unwrap()
+            .pop()
+            .unwrap();
+
+        // the data wraps around the end of the ring buffer
+        {
+            let mut memory = stub.memory.lock().unwrap();
+            put_bytes(&mut memory, SCOPE_BUFFER + 28, &[1, 2, 3, 4]);
+            put_bytes(&mut memory, SCOPE_BUFFER, &[5, 6, 7, 8]);
+            put_u32(&mut memory, up_buffer.write_offset_address(), 4);
+            put_u32(&mut memory, up_buffer.read_offset_address(), 28);
+        }
+
+        let data = read_ring_buffer(&mut gdb, &up_buffer).unwrap();
+        assert_eq!(data, [1, 2, 3, 4, 5, 6, 7, 8]);
+
+        // the read offset is advanced to the write one, nothing more to read
+        let read_offset = up_buffer.read_offset_address() - MEMORY_BASE;
+        assert_eq!(
+            stub.memory.lock().unwrap()[read_offset as usize..][..4],
+            4u32.to_le_bytes()
+        );
+        assert!(read_ring_buffer(&mut gdb, &up_buffer).unwrap().is_empty());
+
+        // without wrapping around
+        {
+            let mut memory = stub.memory.lock().unwrap();
+            put_bytes(&mut memory, SCOPE_BUFFER + 4, &[9, 10]);
+            put_u32(&mut memory, up_buffer.write_offset_address(), 6);
+        }
+        assert_eq!(read_ring_buffer(&mut gdb, &up_buffer).unwrap(), [9, 10]);
+
+        put_u32(
+            &mut stub.memory.lock().unwrap(),
+            up_buffer.write_offset_address(),
+            32,
+        );
+        assert!(read_ring_buffer(&mut gdb, &up_buffer).is_err());
+    }
+
+    #[test]
+    fn test_sampling_halted_target() {
+        let stub = MockStub::spawn(MockStubConfig::default(), rtt_memory());
+
+        let settings = GDBRTTSettings {
+            gdb_server: GDBServerKind::Generic,
+            halt_to_read: true,
+            rtt: RTTSettings {
+                search_address: MEMORY_BASE,
+                search_size: 0x200,
+                polling_interval: 1,
+                timestamp_unit: RTTTimestampUnit::Microseconds,
+                untimed_time_source: RTTTimeSource::SampleIndex,
+                relative_time: false,
+            },
+        };
+        let sampler = GDBRTTSampler::start(stub.address, settings).unwrap();
+
+        // wait for the stream to be synchronized, before the target writes
+        while !matches!(
+            sampler
+                .notification_channel()
+                .recv_timeout(Duration::from_secs(2))
+                .unwrap(),
+            Notification::NewStatus(Status::Sampling)
+        ) {}
+
+        {
+            let mut memory = stub.memory.lock().unwrap();
+            put_bytes(&mut memory, SCOPE_BUFFER, &2.5f32.to_le_bytes());
+            put_u32(&mut memory, SCOPE_DESCRIPTOR + 12, 4);
+        }
+
+        let (_, values) = sampler
+            .sampled_channel()
+            .recv_timeout(Duration::from_secs(2))
+            .unwrap();
+        assert_eq!(values, [(0, 2.5)]);
+
+        Box::new(sampler).stop();
+
+        // the memory is only accessed while the target is halted
+        let received = stub.received.lock().unwrap().clone();
+        let mut halted = true;
+        for command in &received {
+            match command.as_str() {
+                "c" => halted = false,
+                "\x03" => halted = true,
+                command if command.starts_with(['m', 'M']) => {
+                    assert!(halted, "{} sent while the target runs", command)
+                }
+                _ => {}
+            }
+        }
+        assert!(received.iter().filter(|&c| c == "\x03").count() > 1);
+    }
+}
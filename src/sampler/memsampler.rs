@@ -1,4 +1,5 @@
 use std::{
+    collections::VecDeque,
     net::{SocketAddr, ToSocketAddrs},
     path::PathBuf,
     sync::mpsc,
@@ -10,7 +11,7 @@ use anyhow::Context;
 
 use crate::sampler::{Notification, Sample, Sampler, Status};
 use crate::{
-    gdbremote::{self, GDBRemote},
+    gdbremote::{AsyncEvent, GDBRemote, GDBRemoteError, GDBServerKind, WatchpointKind},
     openocd::TelnetInterface,
 };
 
@@ -19,6 +20,37 @@ const SAMPLE_BUFFER_SIZE: usize = 1024;
 // TODO:
 // - maximize probe clock
 
+/// Capture triggered by a hardware watchpoint: until it hits, the samples are kept in a
+/// rolling pre-trigger history instead of being sent; on hit, the history and a final snapshot
+/// of the active variables are sent, and sampling pauses, freezing the capture.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WatchpointCapture {
+    pub kind: WatchpointKind,
+    pub address: u32,
+    /// Bytes watched from `address`: 1, 2 or 4, as the variable is wide.
+    pub length: usize,
+    /// Length of the history sent along with the final snapshot.
+    pub pre_trigger: Duration,
+    /// Resume the target after the hit, instead of leaving it halted.
+    pub resume_after_hit: bool,
+}
+
+/// Settings of the [`MemSampler`]: how to talk to the GDB server and how to read the memory.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MemSamplerSettings {
+    pub gdb_server: GDBServerKind,
+    /// Resume the target after connecting; some servers (e.g. QEMU) can't access memory while
+    /// the target runs.
+    pub resume_target: bool,
+    /// Samples per second.
+    pub rate: f64,
+    /// Largest gap between two sampled variables that is still read in a single memory access,
+    /// along with the memory in between; zero merges only adjacent variables, since reading
+    /// peripheral registers may have side effects, and unmapped memory may fault.
+    pub max_read_gap: u32,
+    pub watchpoint_capture: Option<WatchpointCapture>,
+}
+
 #[derive(Debug)]
 enum ThreadCommand {
     SetActiveAddresses(Vec<u32>),
@@ -36,11 +68,13 @@ pub struct MemSampler {
 }
 
 impl MemSampler {
+    /// Start sampling through the GDB server at `gdb_address`; the Telnet interface is only
+    /// used with OpenOCD, to maximize the adapter speed, and may be omitted.
     pub fn start<AG: ToSocketAddrs, AT: ToSocketAddrs>(
         gdb_address: AG,
-        telnet_address: AT,
-        rate: f64,
+        telnet_address: Option<AT>,
         maybe_elf_filename: Option<PathBuf>,
+        settings: MemSamplerSettings,
     ) -> anyhow::Result<MemSampler> {
         let (sampled_tx, sampled_rx) = mpsc::sync_channel(SAMPLE_BUFFER_SIZE);
         let (command_tx, command_rx) = mpsc::channel();
@@ -51,16 +85,21 @@ impl MemSampler {
             .next()
             .context("no addresses provided")?;
 
-        let telnet_address = telnet_address
-            .to_socket_addrs()?
-            .next()
-            .context("no addresses provided")?;
+        let telnet_address = match telnet_address {
+            Some(address) => Some(
+                address
+                    .to_socket_addrs()?
+                    .next()
+                    .context("no addresses provided")?,
+            ),
+            None => None,
+        };
 
         let join_handle = thread::spawn(move || {
             let result = sampler_thread(
                 gdb_address,
                 telnet_address,
-                rate,
+                settings,
                 sampled_tx,
                 command_rx,
                 notifications_tx.clone(),
@@ -174,54 +213,66 @@ impl Sampler for MemSampler {
 
 fn sampler_thread(
     gdb_address: SocketAddr,
-    telnet_address: SocketAddr,
-    rate: f64,
+    telnet_address: Option<SocketAddr>,
+    settings: MemSamplerSettings,
     sampled_tx: mpsc::SyncSender<Sample>,
     command_rx: mpsc::Receiver<ThreadCommand>,
     notifications_tx: mpsc::Sender<Notification>,
 ) -> anyhow::Result<()> {
+    let info = |message: &str| {
+        log::info!("{}", message);
+        if let Err(err) = notifications_tx.send(Notification::Info(message.to_string())) {
+            log::error!("Failed to send info notification: {:?}", err);
+        }
+    };
+
     // try to maximize the adapter clock speed; don't quit if this fails
-    match maximize_adapter_speed(telnet_address) {
-        Err(err) => log::warn!("failed to maximize adapter speed: {:?}", err),
-        Ok(freq) => log::info!("set adapter speed to {} kHz", freq),
+    if let Some(telnet_address) = telnet_address {
+        match maximize_adapter_speed(telnet_address) {
+            Err(err) => log::warn!("failed to maximize adapter speed: {:?}", err),
+            Ok(freq) => log::info!("set adapter speed to {} kHz", freq),
+        }
     }
 
     let mut gdb = GDBRemote::connect(gdb_address)?;
 
     gdb.set_timeout(Duration::from_millis(2000));
 
-    if !gdb.read_response()?.0.is_ack() {
-        anyhow::bail!("expected initial ACK");
-    }
-    log::trace!("got initial GDB ACK");
+    gdb.handshake(settings.gdb_server)?;
 
-    log::trace!("asking GDB to QStartNoAckMode");
-    gdb.send_packet("QStartNoAckMode")?;
-    if !gdb.read_response()?.0.is_ack() {
-        anyhow::bail!("expected ACK for QStartNoAckMode");
-    }
-    log::trace!("got ACK for QStartNoAckMode");
-    if !gdb.read_response()?.0.is_packet_with("OK") {
-        anyhow::bail!("expected OK for QStartNoAckMode");
-    }
-    log::trace!("got OK for QStartNoAckMode");
+    let max_read_size = gdb.max_memory_read_size();
 
-    let period = Duration::from_secs_f64(1.0 / rate);
+    let period = Duration::from_secs_f64(1.0 / settings.rate);
 
     let mut status = Status::Initializing;
     let mut last_sampled_at = Instant::now();
     let start = SystemTime::now();
-    let mut active_memory_addresses = Vec::new();
+    let mut memory_reads = Vec::new();
+    let mut pre_trigger_history = VecDeque::new();
 
     loop {
         let mut maybe_new_status = None;
 
         match status {
             Status::Initializing => {
-                // make target continue
-                log::trace!("sending GDB continue command");
-                gdb.send_packet("c")?;
-                log::trace!("target resumed");
+                // the target is halted as soon as GDB connects, arm the watchpoint before
+                // resuming it
+                if let Some(capture) = &settings.watchpoint_capture {
+                    gdb.insert_watchpoint(capture.kind, capture.address, capture.length)
+                        .context("failed to arm the watchpoint")?;
+                    info(&format!(
+                        "Waiting for {:?} watchpoint hit at 0x{:08X}",
+                        capture.kind, capture.address
+                    ));
+                }
+
+                // make target continue; some servers (e.g. QEMU) can't access memory while the
+                // target runs, in that case the user can opt to sample a halted target
+                if settings.resume_target {
+                    log::trace!("sending GDB continue command");
+                    gdb.continue_target()?;
+                    log::trace!("target resumed");
+                }
 
                 maybe_new_status = Some(Status::Sampling);
                 last_sampled_at = Instant::now();
@@ -238,7 +289,11 @@ fn sampler_thread(
                     Ok(ThreadCommand::SetActiveAddresses(memory_addresses)) => {
                         // TODO: validate before setting, if we can even do that?
                         // TODO: limit the number of addresses that can be sampled?
-                        active_memory_addresses = memory_addresses;
+                        memory_reads = plan_memory_reads(
+                            &memory_addresses,
+                            max_read_size,
+                            settings.max_read_gap,
+                        );
                     }
                     Ok(other) => {
                         log::warn!("unexpected command in sampling state: {:?}", other);
@@ -268,7 +323,7 @@ fn sampler_thread(
                     log::warn!(
                         "lagging behind by {}us ({}%)",
                         lag.as_micros(),
-                        (lag.as_secs_f64() * rate * 100.0).round() as i32
+                        (lag.as_secs_f64() * settings.rate * 100.0).round() as i32
                     );
                 }
 
@@ -290,76 +345,66 @@ fn sampler_thread(
                 //   for the debugger, or other events that might happend after issuing a GDB "continue"
                 //   command to the OpenOCD
 
-                let mut requested_at = None;
-                let mut received_at = None;
-
-                let mut samples = Vec::new();
-
-                for &memory_address in &active_memory_addresses {
-                    // TODO: support different value sizes and types?
-                    log::trace!("sending GDB memory read command");
-
-                    let tx_timestamp = gdb.send_packet(&format!("m {:08x},4", memory_address))?;
-
-                    // TODO: we actually have different timestamps for each sample
-                    requested_at.get_or_insert(tx_timestamp.get_systemtime());
-
-                    // TODO: handle timeouts
-                    loop {
-                        log::trace!("waiting GDB response");
-                        let (response, rx_timestamp) = gdb.read_response()?;
-
-                        log::trace!("{:?} : {:?}", response, response.to_string());
-
-                        // TODO: we actually have different timestamps for each sample
-                        received_at.get_or_insert(rx_timestamp.get_systemtime());
-
-                        match response {
-                            // OpenOCD sends empty 'O' packets during target execution to keep the
-                            // connection alive, we ignore those: everything fine if we get one
-                            // https://github.com/openocd-org/openocd/blob/2e60e2eca9d06dcb99a4adb81ebe435a72ab0c7f/src/server/gdb_server.c#L3748
-                            gdbremote::Response::Packet(data) if data == b"O" => continue,
-                            gdbremote::Response::Packet(data)
-                                if parse_hex_value(&data).is_some() =>
+                if let Some(sample) = read_samples(&mut gdb, &memory_reads, start)? {
+                    match &settings.watchpoint_capture {
+                        Some(capture) => {
+                            let history_start = sample
+                                .0
+                                .saturating_sub(capture.pre_trigger.as_micros() as u64);
+
+                            pre_trigger_history.push_back(sample);
+                            while pre_trigger_history
+                                .front()
+                                .is_some_and(|&(t, _): &Sample| t < history_start)
                             {
-                                let value =
-                                    parse_hex_value(&data).context("failed to parse hex data")?;
-
-                                samples.push((
-                                    memory_address,
-                                    f32::from_le_bytes(value.to_be_bytes()) as f64,
-                                ));
-                                break;
-                            }
-                            _ => {
-                                // TODO: empty the GDB responses queue, to start fresh, and
-                                // try sampling again at next outer iteration
-                                log::warn!(
-                                    "unexpected/unparsable response to read request: {:?}",
-                                    response
-                                );
+                                pre_trigger_history.pop_front();
                             }
                         }
+                        None => sampled_tx.send(sample)?,
                     }
                 }
 
-                if samples.len() > 0 {
-                    // TODO: conversion from u128 to u64 could fail
-                    let request_timestamp = requested_at
-                        .expect("should have a request timestamp")
-                        .duration_since(start)
-                        .map(|d| d.as_micros())
-                        .unwrap_or(0) as u64;
+                // forward the events the server reported on its own, like console output and
+                // stops; without memory reads, nobody else would look for them
+                if memory_reads.is_empty() {
+                    gdb.poll_async_events()?;
+                }
 
-                    let response_timestamp = received_at
-                        .expect("should have a response timestamp")
+                let mut watchpoint_hit = false;
+                while let Some((event, timestamp)) = gdb.pop_async_event() {
+                    let time = timestamp
+                        .get_systemtime()
                         .duration_since(start)
                         .map(|d| d.as_micros())
                         .unwrap_or(0) as u64;
 
-                    let averaged_timestamp = (request_timestamp + response_timestamp) / 2;
+                    if let AsyncEvent::Stop(stop_reply) = &event {
+                        watchpoint_hit |= stop_reply.watchpoint.is_some();
+                    }
+
+                    notifications_tx.send(Notification::from_gdb_event(event, Some(time)))?;
+                }
+
+                if let Some(capture) = settings.watchpoint_capture.filter(|_| watchpoint_hit) {
+                    // freeze the capture: send the history, followed by a snapshot of the
+                    // variables taken while the target is halted by the watchpoint
+                    let captured_samples = pre_trigger_history.len();
+                    for sample in pre_trigger_history.drain(..) {
+                        sampled_tx.send(sample)?;
+                    }
+                    if let Some(snapshot) = read_samples(&mut gdb, &memory_reads, start)? {
+                        sampled_tx.send(snapshot)?;
+                    }
+
+                    if capture.resume_after_hit {
+                        gdb.continue_target()?;
+                    }
 
-                    sampled_tx.send((averaged_timestamp, samples))?;
+                    info(&format!(
+                        "Watchpoint hit, captured {} samples and the final snapshot",
+                        captured_samples
+                    ));
+                    maybe_new_status = Some(Status::Paused);
                 }
             }
             Status::Paused => match command_rx.recv() {
@@ -368,13 +413,20 @@ fn sampler_thread(
                     maybe_new_status = Some(Status::Terminated);
                 }
                 Ok(ThreadCommand::Resume) => {
+                    // after a watchpoint hit, the target might have been left halted; the
+                    // watchpoint is still armed for the next capture
+                    if settings.watchpoint_capture.is_some() && !gdb.is_target_running() {
+                        gdb.continue_target()?;
+                    }
+
                     maybe_new_status = Some(Status::Sampling);
                     last_sampled_at = Instant::now();
                 }
                 Ok(ThreadCommand::SetActiveAddresses(memory_addresses)) => {
                     // TODO: validate before setting, if we can even do that?
                     // TODO: limit the number of addresses that can be sampled?
-                    active_memory_addresses = memory_addresses;
+                    memory_reads =
+                        plan_memory_reads(&memory_addresses, max_read_size, settings.max_read_gap);
                 }
                 Ok(other) => {
                     log::warn!("Unexpected command in paused state: {:?}", other);
@@ -384,6 +436,14 @@ fn sampler_thread(
                 }
             },
             Status::Terminated => {
+                // not all the GDB servers remove the watchpoints when the connection is closed,
+                // leaving them armed in the target
+                if let Some(capture) = &settings.watchpoint_capture {
+                    if let Err(err) = disarm_watchpoint(&mut gdb, capture) {
+                        log::warn!("failed to remove watchpoint: {:?}", err);
+                    }
+                }
+
                 // break the main loop, finishing this thread
                 break;
             }
@@ -401,10 +461,135 @@ fn sampler_thread(
     Ok(())
 }
 
-fn parse_hex_value(data: &[u8]) -> Option<u32> {
-    let data_string = String::from_utf8(data.to_vec()).ok()?;
-    let value = u32::from_str_radix(&data_string, 16).ok()?;
-    Some(value)
+/// Remove the watchpoint of `capture`; GDB servers can't always change watchpoints while the
+/// target runs, so a running target is halted first, and resumed afterwards.
+fn disarm_watchpoint(
+    gdb: &mut GDBRemote,
+    capture: &WatchpointCapture,
+) -> Result<(), GDBRemoteError> {
+    let interrupted = gdb.interrupt_target()?;
+    gdb.remove_watchpoint(capture.kind, capture.address, capture.length)?;
+
+    if interrupted {
+        gdb.continue_target()?;
+    }
+
+    Ok(())
+}
+
+/// Read the variables covered by `memory_reads`, returning them as a single sample, with the
+/// time in microseconds since `start`, or `None` if there is nothing to read.
+fn read_samples(
+    gdb: &mut GDBRemote,
+    memory_reads: &[MemoryRead],
+    start: SystemTime,
+) -> anyhow::Result<Option<Sample>> {
+    let mut requested_at = None;
+    let mut received_at = None;
+
+    let mut samples = Vec::new();
+
+    for memory_read in memory_reads {
+        // TODO: support different value sizes and types?
+        log::trace!("sending GDB memory read command");
+
+        let tx_timestamp = gdb.request_memory(memory_read.address, memory_read.length)?;
+
+        // TODO: we actually have different timestamps for each sample
+        requested_at.get_or_insert(tx_timestamp.get_systemtime());
+
+        log::trace!("waiting GDB response");
+        let bytes = match gdb.read_memory_reply(memory_read.length) {
+            Ok((bytes, rx_timestamp)) => {
+                // TODO: we actually have different timestamps for each sample
+                received_at.get_or_insert(rx_timestamp.get_systemtime());
+                bytes
+            }
+            Err(
+                err @ (GDBRemoteError::ErrorResponse(_)
+                | GDBRemoteError::UnexpectedResponse(_)
+                | GDBRemoteError::ParseError(_)),
+            ) => {
+                // TODO: empty the GDB responses queue, to start fresh, and
+                // try sampling again at next outer iteration
+                log::warn!("failed to read memory: {:?}", err);
+                continue;
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        for &memory_address in &memory_read.variables {
+            let offset = (memory_address - memory_read.address) as usize;
+            let value = f32::from_le_bytes(
+                bytes[offset..offset + 4]
+                    .try_into()
+                    .expect("slice is 4 bytes long"),
+            );
+
+            samples.push((memory_address, value as f64));
+        }
+    }
+
+    if samples.is_empty() {
+        return Ok(None);
+    }
+
+    // TODO: conversion from u128 to u64 could fail
+    let request_timestamp = requested_at
+        .expect("should have a request timestamp")
+        .duration_since(start)
+        .map(|d| d.as_micros())
+        .unwrap_or(0) as u64;
+
+    let response_timestamp = received_at
+        .expect("should have a response timestamp")
+        .duration_since(start)
+        .map(|d| d.as_micros())
+        .unwrap_or(0) as u64;
+
+    let averaged_timestamp = (request_timestamp + response_timestamp) / 2;
+
+    Ok(Some((averaged_timestamp, samples)))
+}
+
+/// Single memory access covering one or more sampled variables.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct MemoryRead {
+    address: u32,
+    length: usize,
+    variables: Vec<u32>,
+}
+
+/// Group the 4-byte variables at `addresses` into as few memory reads as possible, each at
+/// most `max_read_size` bytes long, merging variables that overlap, are adjacent or are at most
+/// `max_gap` bytes apart.
+fn plan_memory_reads(addresses: &[u32], max_read_size: usize, max_gap: u32) -> Vec<MemoryRead> {
+    let mut sorted_addresses = addresses.to_vec();
+    sorted_addresses.sort_unstable();
+    sorted_addresses.dedup();
+
+    let mut reads: Vec<MemoryRead> = Vec::new();
+
+    for address in sorted_addresses {
+        if let Some(read) = reads.last_mut() {
+            let read_end = read.address as u64 + read.length as u64;
+            let merged_length = (address as u64 + 4 - read.address as u64) as usize;
+
+            if address as u64 <= read_end + max_gap as u64 && merged_length <= max_read_size {
+                read.length = read.length.max(merged_length);
+                read.variables.push(address);
+                continue;
+            }
+        }
+
+        reads.push(MemoryRead {
+            address,
+            length: 4,
+            variables: vec![address],
+        });
+    }
+
+    reads
 }
 
 #[derive(Debug)]
@@ -449,3 +634,204 @@ fn maximize_adapter_speed(telnet_address: SocketAddr) -> anyhow::Result<usize> {
 
     Ok(actual_speed)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plan_memory_reads() {
+        let addresses = [
+            0x2000_0010,
+            0x2000_0000,
+            0x2000_0100,
+            0x2000_0004,
+            0x2000_0006,
+        ];
+
+        // by default only the adjacent and overlapping variables are read together
+        let reads = plan_memory_reads(&addresses, 64, 0);
+        assert_eq!(
+            reads,
+            [
+                MemoryRead {
+                    address: 0x2000_0000,
+                    length: 0xa,
+                    variables: vec![0x2000_0000, 0x2000_0004, 0x2000_0006],
+                },
+                MemoryRead {
+                    address: 0x2000_0010,
+                    length: 4,
+                    variables: vec![0x2000_0010],
+                },
+                MemoryRead {
+                    address: 0x2000_0100,
+                    length: 4,
+                    variables: vec![0x2000_0100],
+                },
+            ]
+        );
+
+        let reads = plan_memory_reads(&addresses, 64, 32);
+        assert_eq!(
+            reads,
+            [
+                MemoryRead {
+                    address: 0x2000_0000,
+                    length: 0x14,
+                    variables: vec![0x2000_0000, 0x2000_0004, 0x2000_0006, 0x2000_0010],
+                },
+                MemoryRead {
+                    address: 0x2000_0100,
+                    length: 4,
+                    variables: vec![0x2000_0100],
+                },
+            ]
+        );
+
+        // close variables are still split when they don't fit in a single read
+        let reads = plan_memory_reads(&[0x2000_0000, 0x2000_0008, 0x2000_0008], 8, 32);
+        assert_eq!(reads.len(), 2);
+        assert!(reads.iter().all(|read| read.length <= 8));
+    }
+
+    #[test]
+    fn test_sampling_generic_gdb_server() {
+        use crate::gdbremote::mock_stub::{MockStub, MockStubConfig};
+
+        let mut memory = vec![0; 64];
+        memory[0..4].copy_from_slice(&1.5f32.to_le_bytes());
+        memory[8..12].copy_from_slice(&(-2.0f32).to_le_bytes());
+
+        // the gap between the variables is only read when asked to
+        for (max_read_gap, reads) in [
+            (0, &["m20000000,4", "m20000008,4"][..]),
+            (4, &["m20000000,c"][..]),
+        ] {
+            let stub = MockStub::spawn(MockStubConfig::default(), memory.clone());
+
+            let settings = MemSamplerSettings {
+                gdb_server: GDBServerKind::Generic,
+                resume_target: true,
+                rate: 100.0,
+                max_read_gap,
+                watchpoint_capture: None,
+            };
+            let sampler =
+                MemSampler::start(stub.address, None::<SocketAddr>, None, settings).unwrap();
+            sampler.set_active_signals(&[0x2000_0000, 0x2000_0008]);
+
+            let (_, mut values) = loop {
+                let sample = sampler
+                    .sampled_channel()
+                    .recv_timeout(Duration::from_secs(2))
+                    .unwrap();
+
+                if sample.1.len() == 2 {
+                    break sample;
+                }
+            };
+            values.sort_by_key(|&(id, _)| id);
+            assert_eq!(values, [(0x2000_0000, 1.5), (0x2000_0008, -2.0)]);
+
+            Box::new(sampler).stop();
+
+            let received = stub.received.lock().unwrap().clone();
+            let memory_reads = received
+                .iter()
+                .filter(|command| command.starts_with('m'))
+                .collect::<std::collections::BTreeSet<_>>();
+            assert_eq!(memory_reads.into_iter().collect::<Vec<_>>(), reads);
+        }
+    }
+
+    #[test]
+    fn test_watchpoint_capture() {
+        use crate::gdbremote::mock_stub::{MockStub, MockStubConfig};
+
+        let stub = MockStub::spawn(MockStubConfig::default(), vec![0; 16]);
+
+        let capture = WatchpointCapture {
+            kind: WatchpointKind::Write,
+            address: 0x2000_0008,
+            length: 4,
+            pre_trigger: Duration::from_millis(100),
+            resume_after_hit: true,
+        };
+        let settings = MemSamplerSettings {
+            gdb_server: GDBServerKind::Generic,
+            resume_target: true,
+            rate: 200.0,
+            max_read_gap: 0,
+            watchpoint_capture: Some(capture),
+        };
+        let sampler = MemSampler::start(stub.address, None::<SocketAddr>, None, settings).unwrap();
+        sampler.set_active_signals(&[0x2000_0000]);
+
+        // nothing is sent while waiting for the hit
+        thread::sleep(Duration::from_millis(300));
+        assert!(sampler.sampled_channel().try_recv().is_err());
+
+        stub.memory.lock().unwrap()[0..4].copy_from_slice(&42.0f32.to_le_bytes());
+        stub.halt("T05watch:20000008;");
+
+        let status = loop {
+            match sampler
+                .notification_channel()
+                .recv_timeout(Duration::from_secs(2))
+                .unwrap()
+            {
+                Notification::NewStatus(status) if status != Status::Sampling => break status,
+                _ => {}
+            }
+        };
+        assert_eq!(status, Status::Paused);
+
+        let samples: Vec<Sample> = sampler.sampled_channel().try_iter().collect();
+        let duration = samples.last().unwrap().0 - samples.first().unwrap().0;
+        assert!(samples.len() > 2);
+        assert!(duration <= 150_000, "history spans {} us", duration);
+        assert_eq!(samples.last().unwrap().1, [(0x2000_0000, 42.0)]);
+
+        // the watchpoint was armed before resuming, and the target resumed after the hit
+        thread::sleep(Duration::from_millis(100));
+        let received = stub.received.lock().unwrap().clone();
+        let armed_at = received.iter().position(|c| c == "Z2,20000008,4").unwrap();
+        assert_eq!(received[armed_at + 1], "c");
+        assert_eq!(received.iter().filter(|&c| c == "c").count(), 2);
+
+        Box::new(sampler).stop();
+    }
+
+    #[test]
+    fn test_watchpoint_removed_on_stop() {
+        use crate::gdbremote::mock_stub::{MockStub, MockStubConfig};
+
+        let stub = MockStub::spawn(MockStubConfig::default(), vec![0; 16]);
+
+        let capture = WatchpointCapture {
+            kind: WatchpointKind::Access,
+            address: 0x2000_0005,
+            length: 1,
+            pre_trigger: Duration::from_millis(100),
+            resume_after_hit: true,
+        };
+        let settings = MemSamplerSettings {
+            gdb_server: GDBServerKind::Generic,
+            resume_target: true,
+            rate: 200.0,
+            max_read_gap: 0,
+            watchpoint_capture: Some(capture),
+        };
+        let sampler = MemSampler::start(stub.address, None::<SocketAddr>, None, settings).unwrap();
+        sampler.set_active_signals(&[0x2000_0000]);
+        thread::sleep(Duration::from_millis(100));
+
+        // stopped before any hit, while the target runs
+        Box::new(sampler).stop();
+
+        let received = stub.received.lock().unwrap().clone();
+        let interrupted_at = received.iter().position(|c| c == "\x03").unwrap();
+        assert_eq!(received[interrupted_at + 1..], ["z4,20000005,1", "c"]);
+    }
+}
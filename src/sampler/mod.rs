@@ -1,12 +1,20 @@
 use std::sync::mpsc;
 
+use crate::gdbremote::AsyncEvent;
+
 mod fakesampler;
-mod rttsampler;
+mod gdbrttsampler;
 mod memsampler;
+mod replaysampler;
+mod rttsampler;
+mod rttstream;
 
 pub use fakesampler::FakeSampler;
+pub use gdbrttsampler::{GDBRTTSampler, GDBRTTSettings};
+pub use memsampler::{MemSampler, MemSamplerSettings, WatchpointCapture};
+pub use replaysampler::ReplaySampler;
 pub use rttsampler::RTTSampler;
-pub use memsampler::MemSampler;
+pub use rttstream::{RTTSettings, RTTTimeSource, RTTTimestampUnit};
 
 // TODOs:
 // - error handling
@@ -29,9 +37,30 @@ pub enum Notification {
     NewStatus(Status),
     Info(String),
     Error(String),
+    /// Text printed by the target on the debugger console.
+    ConsoleOutput(String),
+    /// The target stopped, with a description of the reason; the time is in the time base of
+    /// the samples, when known.
+    TargetStopped(Option<u64>, String),
 }
 
-pub trait Sampler {
+impl Notification {
+    /// Notification of an event the GDB server reported on its own, which happened at `time`.
+    fn from_gdb_event(event: AsyncEvent, time: Option<u64>) -> Notification {
+        match event {
+            AsyncEvent::ConsoleOutput(text) => Notification::ConsoleOutput(text),
+            AsyncEvent::Stop(stop_reply) => {
+                Notification::TargetStopped(time, format!("Target {}", stop_reply))
+            }
+            AsyncEvent::Notification(data) => Notification::Info(format!(
+                "GDB notification: {}",
+                String::from_utf8_lossy(&data)
+            )),
+        }
+    }
+}
+
+pub trait Sampler: Send {
     fn available_signals(&self) -> Vec<(u32, String)>;
     fn set_active_signals(&self, ids: &[u32]);
 
@@ -43,5 +72,14 @@ pub trait Sampler {
     fn pause(&self);
     fn resume(&self);
 
+    /// Move the playback to `time`, in microseconds; only samplers replaying a recording can.
+    /// The samples from before the seek still in the channel get discarded, so that the next
+    /// ones received are from `time` on.
+    fn seek(&self, _time: u64) {}
+
+    /// Change the playback speed, relative to the real time; only samplers replaying a recording
+    /// can.
+    fn set_speed(&self, _speed: f64) {}
+
     fn stop(self: Box<Self>);
 }
@@ -0,0 +1,832 @@
+use std::path::Path;
+use std::time::{Duration, Instant};
+use std::{sync::mpsc, thread};
+
+use anyhow::Context;
+
+use crate::sampler::{Notification, Sample, Sampler, Status};
+use crate::session::{Record, SessionReader};
+
+const SAMPLE_BUFFER_SIZE: usize = 1024;
+
+/// Longest sleep between two checks of the commands channel.
+const MAX_SLEEP: Duration = Duration::from_millis(10);
+
+#[derive(Debug)]
+enum ThreadCommand {
+    SetActiveSignals(Vec<u32>),
+    Pause,
+    Resume,
+    /// Move the playback, telling the sampler once done, then waiting for it to discard the
+    /// samples sent before.
+    Seek(u64, mpsc::Sender<()>, mpsc::Receiver<()>),
+    SetSpeed(f64),
+    Stop,
+}
+
+#[derive(Debug, Clone)]
+enum ReplayEvent {
+    Sample(Sample),
+    /// Notification recorded after the samples up to the given time.
+    Notification(u64, Notification),
+}
+
+impl ReplayEvent {
+    fn time(&self) -> u64 {
+        match self {
+            ReplayEvent::Sample((t, _)) => *t,
+            ReplayEvent::Notification(t, _) => *t,
+        }
+    }
+}
+
+/// Recording loaded in memory, with the events ordered by time.
+#[derive(Debug, Default)]
+struct Recording {
+    signals: Vec<(u32, String)>,
+    events: Vec<ReplayEvent>,
+}
+
+impl Recording {
+    fn load(filename: &Path, rate: f64) -> anyhow::Result<Recording> {
+        let extension = filename
+            .extension()
+            .map(|s| s.to_string_lossy().to_ascii_lowercase());
+
+        match extension.as_deref() {
+            Some("session") => Recording::load_session(filename),
+            Some("csv") => Recording::load_csv(filename, rate),
+            Some("npy") => Recording::load_npy(filename),
+            _ => anyhow::bail!("cannot replay {:?}, unknown file format", filename),
+        }
+    }
+
+    fn load_session(filename: &Path) -> anyhow::Result<Recording> {
+        let mut recording = Recording::default();
+
+        // the segments recorded after a reconnection are placed one after the other
+        let mut time_offset = 0;
+        let mut last_time = 0;
+
+        for record in SessionReader::open(filename)? {
+            match record? {
+                Record::Settings(_) => time_offset = last_time,
+                Record::Signal(signal) => {
+                    recording.signals.retain(|(id, _)| *id != signal.id);
+                    recording.signals.push((signal.id, signal.name));
+                }
+                Record::Sample((t, values)) => {
+                    last_time = last_time.max(t + time_offset);
+                    recording
+                        .events
+                        .push(ReplayEvent::Sample((t + time_offset, values)));
+                }
+                Record::Notification(Notification::TargetStopped(time, description)) => {
+                    let time = time.map(|t| t + time_offset);
+                    recording.events.push(ReplayEvent::Notification(
+                        time.unwrap_or(last_time),
+                        Notification::TargetStopped(time, description),
+                    ));
+                }
+                Record::Notification(notification) => {
+                    recording
+                        .events
+                        .push(ReplayEvent::Notification(last_time, notification));
+                }
+            }
+        }
+
+        // the order of the recording is kept for the events at the same time
+        recording.events.sort_by_key(ReplayEvent::time);
+
+        Ok(recording)
+    }
+
+    /// Load a CSV file as written by the export. In the tidy layout, each row has the time, the
+    /// signal name and the value; otherwise there's a header of signal names and a row per time,
+    /// where a `time` column, in seconds, is used if present, and otherwise samples are assumed
+    /// to be taken at `rate`. NaN values are the times a signal wasn't sampled.
+    fn load_csv(filename: &Path, rate: f64) -> anyhow::Result<Recording> {
+        let contents = std::fs::read_to_string(filename)
+            .with_context(|| format!("cannot read {:?}", filename))?;
+        let mut lines = contents.lines().filter(|line| !line.trim().is_empty());
+
+        let header = lines.next().context("empty CSV file")?;
+        let names: Vec<&str> = header.split(',').map(str::trim).collect();
+
+        if names == ["time", "signal", "value"] {
+            return Recording::load_tidy_csv(lines);
+        }
+
+        let time_column = names
+            .iter()
+            .position(|name| name.eq_ignore_ascii_case("time"));
+
+        let mut recording = Recording::default();
+        let mut samples = Vec::new();
+        let mut ids = Vec::with_capacity(names.len());
+        for (column, name) in names.iter().enumerate() {
+            if Some(column) != time_column {
+                let id = recording.signals.len() as u32;
+                recording.signals.push((id, name.to_string()));
+                ids.push(Some(id));
+            } else {
+                ids.push(None);
+            }
+        }
+
+        for (i, line) in lines.enumerate() {
+            let values = line
+                .split(',')
+                .map(|value| value.trim().parse::<f64>())
+                .collect::<Result<Vec<_>, _>>()
+                .with_context(|| format!("invalid CSV row {}", i + 2))?;
+
+            if values.len() != names.len() {
+                anyhow::bail!(
+                    "CSV row {} has {} values instead of {}",
+                    i + 2,
+                    values.len(),
+                    names.len()
+                );
+            }
+
+            let t = match time_column {
+                Some(column) => values[column],
+                None => i as f64 / rate,
+            };
+            let sample = ids
+                .iter()
+                .zip(values)
+                .filter_map(|(id, y)| id.filter(|_| !y.is_nan()).map(|id| (id, y)))
+                .collect();
+
+            samples.push((t, sample));
+        }
+
+        recording.events = rebased_samples(samples);
+
+        Ok(recording)
+    }
+
+    fn load_tidy_csv<'a>(lines: impl Iterator<Item = &'a str>) -> anyhow::Result<Recording> {
+        let mut recording = Recording::default();
+        let mut samples: Vec<(f64, Vec<(u32, f64)>)> = Vec::new();
+
+        for (i, line) in lines.enumerate() {
+            let parse_row = || {
+                let mut fields = line.split(',').map(str::trim);
+                let t = fields.next()?.parse::<f64>().ok()?;
+                let name = fields.next()?;
+                let y = fields.next()?.parse::<f64>().ok()?;
+
+                fields.next().is_none().then_some((t, name, y))
+            };
+            let (t, name, y) = parse_row().with_context(|| format!("invalid CSV row {}", i + 2))?;
+
+            let id = match recording.signals.iter().find(|(_, n)| n == name) {
+                Some((id, _)) => *id,
+                None => {
+                    let id = recording.signals.len() as u32;
+                    recording.signals.push((id, name.to_string()));
+                    id
+                }
+            };
+
+            // the samples of different signals at the same time go together
+            match samples.last_mut() {
+                Some((last_t, values)) if *last_t == t => values.push((id, y)),
+                _ => samples.push((t, vec![(id, y)])),
+            }
+        }
+
+        recording.events = rebased_samples(samples);
+
+        Ok(recording)
+    }
+
+    /// Load a NumPy file as written by the export in the aligned layout, a 2D array with a row
+    /// per time and the time, in seconds, followed by a column per signal.
+    fn load_npy(filename: &Path) -> anyhow::Result<Recording> {
+        let file =
+            std::fs::File::open(filename).with_context(|| format!("cannot open {:?}", filename))?;
+        let npy = npyz::NpyFile::new(std::io::BufReader::new(file))?;
+
+        let (n_rows, n_columns) = match *npy.shape() {
+            [n_rows, n_columns] if n_columns > 0 => (n_rows as usize, n_columns as usize),
+            ref shape => anyhow::bail!("cannot replay an array of shape {:?}", shape),
+        };
+        if npy.order() != npyz::Order::C {
+            anyhow::bail!("cannot replay an array in Fortran order");
+        }
+
+        let data: Vec<f64> = npy
+            .into_vec()
+            .context("cannot read the array as f64 values")?;
+        debug_assert_eq!(data.len(), n_rows * n_columns);
+
+        let signals = (0..n_columns as u32 - 1)
+            .map(|id| (id, format!("Column {}", id + 1)))
+            .collect();
+
+        let samples = data
+            .chunks_exact(n_columns)
+            .map(|row| {
+                let values = row[1..]
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, y)| !y.is_nan())
+                    .map(|(id, &y)| (id as u32, y))
+                    .collect();
+
+                (row[0], values)
+            })
+            .collect();
+
+        Ok(Recording {
+            signals,
+            events: rebased_samples(samples),
+        })
+    }
+
+    fn duration(&self) -> u64 {
+        self.events.last().map_or(0, ReplayEvent::time)
+    }
+}
+
+/// Events of the samples at the given times, in seconds, ordered by time and rebased to the first
+/// one: exports keep the times of the capture, which may be far from zero, or even negative when
+/// relative to a trigger.
+fn rebased_samples(samples: Vec<(f64, Vec<(u32, f64)>)>) -> Vec<ReplayEvent> {
+    let start = samples
+        .iter()
+        .map(|(t, _)| *t)
+        .filter(|t| t.is_finite())
+        .fold(f64::INFINITY, f64::min);
+
+    let mut events: Vec<_> = samples
+        .into_iter()
+        .map(|(t, values)| ReplayEvent::Sample((((t - start) * 1e6).round() as u64, values)))
+        .collect();
+    events.sort_by_key(ReplayEvent::time);
+
+    events
+}
+
+/// Sampler playing back a recorded session, or a CSV or NumPy export, in real time or at a
+/// different speed.
+pub struct ReplaySampler {
+    join_handle: thread::JoinHandle<()>,
+    command_tx: mpsc::Sender<ThreadCommand>,
+    notifications_rx: mpsc::Receiver<Notification>,
+    sampled_rx: mpsc::Receiver<Sample>,
+    signals: Vec<(u32, String)>,
+    duration: u64,
+}
+
+impl ReplaySampler {
+    /// Replay `filename` at `speed` times the real time; `rate` is the sample rate of the CSV
+    /// files that don't record the time.
+    pub fn start(filename: &Path, rate: f64, speed: f64) -> anyhow::Result<ReplaySampler> {
+        let recording = Recording::load(filename, rate)?;
+        let signals = recording.signals.clone();
+        let duration = recording.duration();
+
+        log::info!(
+            "replaying {:?}: {} signals, {} events, {} us",
+            filename,
+            signals.len(),
+            recording.events.len(),
+            duration
+        );
+
+        let (sampled_tx, sampled_rx) = mpsc::sync_channel(SAMPLE_BUFFER_SIZE);
+        let (command_tx, command_rx) = mpsc::channel();
+        let (notifications_tx, notifications_rx) = mpsc::channel();
+
+        let join_handle = thread::spawn(move || {
+            let result = sampler_thread(
+                recording,
+                speed,
+                sampled_tx,
+                command_rx,
+                notifications_tx.clone(),
+            );
+
+            if let Err(err) = result {
+                log::error!("sampler thread returned with error: {:?}", err);
+                log::debug!("sending error notification and switch to terminated state");
+
+                // ignore the send errors instead of unwrapping, at this point if even sending to
+                // `notifications_tx` fails, the situation is sort of unrecoverable
+                if let Err(e) = notifications_tx.send(Notification::Error(format!("{:?}", err))) {
+                    log::error!("error notification send failed: {:?}", e);
+                }
+                if let Err(e) = notifications_tx.send(Notification::NewStatus(Status::Terminated)) {
+                    log::error!("new status notification send failed: {:?}", e);
+                }
+            }
+        });
+
+        Ok(ReplaySampler {
+            join_handle,
+            command_tx,
+            notifications_rx,
+            sampled_rx,
+            signals,
+            duration,
+        })
+    }
+
+    /// Time of the last recorded event, in microseconds.
+    pub fn duration(&self) -> u64 {
+        self.duration
+    }
+}
+
+impl Sampler for ReplaySampler {
+    fn available_signals(&self) -> Vec<(u32, String)> {
+        self.signals.clone()
+    }
+
+    fn set_active_signals(&self, ids: &[u32]) {
+        if let Err(err) = self
+            .command_tx
+            .send(ThreadCommand::SetActiveSignals(ids.to_vec()))
+        {
+            log::error!("failed to send SetActiveSignals command: {:?}", err);
+        }
+    }
+
+    fn sampled_channel(&self) -> &mpsc::Receiver<Sample> {
+        &self.sampled_rx
+    }
+
+    fn notification_channel(&self) -> &mpsc::Receiver<Notification> {
+        &self.notifications_rx
+    }
+
+    fn pause(&self) {
+        if let Err(err) = self.command_tx.send(ThreadCommand::Pause) {
+            log::error!("failed to send pause command: {:?}", err);
+        }
+    }
+
+    fn resume(&self) {
+        if let Err(err) = self.command_tx.send(ThreadCommand::Resume) {
+            log::error!("failed to send resume command: {:?}", err);
+        }
+    }
+
+    fn seek(&self, time: u64) {
+        let (seeked_tx, seeked_rx) = mpsc::channel();
+        let (discarded_tx, discarded_rx) = mpsc::channel();
+
+        if let Err(err) = self
+            .command_tx
+            .send(ThreadCommand::Seek(time, seeked_tx, discarded_rx))
+        {
+            log::error!("failed to send seek command: {:?}", err);
+            return;
+        }
+
+        // the thread may be blocked sending a sample, before it even gets to the command
+        loop {
+            self.sampled_rx.try_iter().for_each(drop);
+
+            match seeked_rx.recv_timeout(MAX_SLEEP) {
+                Ok(()) => break,
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                Err(mpsc::RecvTimeoutError::Disconnected) => return,
+            }
+        }
+
+        // the thread waits for these to be gone before playing from the new position
+        self.sampled_rx.try_iter().for_each(drop);
+        let _ = discarded_tx.send(());
+    }
+
+    fn set_speed(&self, speed: f64) {
+        if let Err(err) = self.command_tx.send(ThreadCommand::SetSpeed(speed)) {
+            log::error!("failed to send set speed command: {:?}", err);
+        }
+    }
+
+    fn stop(self: Box<Self>) {
+        if let Err(err) = self.command_tx.send(ThreadCommand::Stop) {
+            log::debug!("asked to stop sampler but thread seems to already be dead (command send failed: {:?})", err);
+
+            debug_assert!(self.join_handle.is_finished());
+        }
+
+        // TODO: if there are implementation errors in the sampler thread, and the Stop command is not processed,
+        // this can block indefinitely
+        if let Err(err) = self.join_handle.join() {
+            log::warn!("failed to join sampler thread: {:?}", err);
+        }
+    }
+}
+
+/// Playback position: the recording time `origin` corresponds to the instant `started_at`.
+struct Clock {
+    started_at: Instant,
+    origin: u64,
+    speed: f64,
+}
+
+impl Clock {
+    fn new(origin: u64, speed: f64) -> Clock {
+        Clock {
+            started_at: Instant::now(),
+            origin,
+            speed,
+        }
+    }
+
+    /// Current recording time.
+    fn now(&self) -> u64 {
+        self.origin + (self.started_at.elapsed().as_secs_f64() * self.speed * 1e6) as u64
+    }
+
+    /// Real time left until the recording time `t`.
+    fn until(&self, t: u64) -> Duration {
+        let now = self.now();
+        match t > now {
+            true => Duration::from_secs_f64((t - now) as f64 * 1e-6 / self.speed),
+            false => Duration::ZERO,
+        }
+    }
+}
+
+/// Tell [`ReplaySampler::seek`] the playback moved, and wait for it to discard the samples sent
+/// from the previous position, so that none of them gets received after the seek.
+fn wait_for_discarded_samples(seeked_tx: mpsc::Sender<()>, discarded_rx: mpsc::Receiver<()>) {
+    if seeked_tx.send(()).is_ok() {
+        let _ = discarded_rx.recv();
+    }
+}
+
+fn sampler_thread(
+    recording: Recording,
+    mut speed: f64,
+    sampled_tx: mpsc::SyncSender<Sample>,
+    command_rx: mpsc::Receiver<ThreadCommand>,
+    notifications_tx: mpsc::Sender<Notification>,
+) -> anyhow::Result<()> {
+    let events = recording.events;
+
+    let mut status = Status::Initializing;
+    // all the signals are played until told otherwise, not to drop the first samples
+    let mut active_ids: Vec<u32> = recording.signals.iter().map(|(id, _)| *id).collect();
+    let mut position = 0;
+    // the recording may not start at zero
+    let start = events.first().map_or(0, ReplayEvent::time);
+    let mut clock = Clock::new(start, speed);
+
+    // position to seek to, when starting again from `Paused`
+    let seek = |time: u64| events.partition_point(|event| event.time() < time);
+
+    loop {
+        let mut maybe_new_status = None;
+
+        match status {
+            Status::Initializing => {
+                maybe_new_status = Some(Status::Sampling);
+                clock = Clock::new(start, speed);
+            }
+            Status::Sampling => match command_rx.try_recv() {
+                Ok(ThreadCommand::Stop) => {
+                    maybe_new_status = Some(Status::Terminated);
+                }
+                Ok(ThreadCommand::Pause) => {
+                    maybe_new_status = Some(Status::Paused);
+                }
+                Ok(ThreadCommand::SetActiveSignals(ids)) => {
+                    active_ids = ids;
+                }
+                Ok(ThreadCommand::Seek(time, seeked_tx, discarded_rx)) => {
+                    position = seek(time);
+                    wait_for_discarded_samples(seeked_tx, discarded_rx);
+                    clock = Clock::new(time, speed);
+                }
+                Ok(ThreadCommand::SetSpeed(new_speed)) => {
+                    speed = new_speed;
+                    clock = Clock::new(clock.now(), speed);
+                }
+                Ok(other) => {
+                    log::warn!("Unexpected command in sampling state: {:?}", other);
+                }
+                Err(mpsc::TryRecvError::Empty) => {
+                    let now = clock.now();
+
+                    while let Some(event) = events.get(position).filter(|e| e.time() <= now) {
+                        match event {
+                            ReplayEvent::Sample((t, values)) => {
+                                let values = values
+                                    .iter()
+                                    .filter(|(id, _)| active_ids.contains(id))
+                                    .copied()
+                                    .collect::<Vec<_>>();
+
+                                if !values.is_empty() {
+                                    sampled_tx.send((*t, values))?;
+                                }
+                            }
+                            ReplayEvent::Notification(_, notification) => {
+                                notifications_tx.send(notification.clone())?;
+                            }
+                        }
+
+                        position += 1;
+                    }
+
+                    match events.get(position) {
+                        Some(next_event) => {
+                            thread::sleep(clock.until(next_event.time()).min(MAX_SLEEP))
+                        }
+                        None => {
+                            notifications_tx.send(Notification::Info("Replay finished".into()))?;
+                            maybe_new_status = Some(Status::Paused);
+                        }
+                    }
+                }
+                Err(err) => {
+                    anyhow::bail!("Closed TX end of command channel ({})", err);
+                }
+            },
+            Status::Paused => match command_rx.recv() {
+                Ok(ThreadCommand::Stop) => {
+                    maybe_new_status = Some(Status::Terminated);
+                }
+                Ok(ThreadCommand::Resume) => {
+                    maybe_new_status = Some(Status::Sampling);
+
+                    // resuming at the end of the recording starts it over
+                    if position == events.len() {
+                        position = 0;
+                    }
+                    let origin = events.get(position).map_or(0, ReplayEvent::time);
+                    clock = Clock::new(origin, speed);
+                }
+                Ok(ThreadCommand::SetActiveSignals(ids)) => {
+                    active_ids = ids;
+                }
+                Ok(ThreadCommand::Seek(time, seeked_tx, discarded_rx)) => {
+                    position = seek(time);
+                    wait_for_discarded_samples(seeked_tx, discarded_rx);
+                }
+                Ok(ThreadCommand::SetSpeed(new_speed)) => {
+                    speed = new_speed;
+                }
+                Ok(other) => {
+                    log::warn!("Unexpected command in paused state: {:?}", other);
+                }
+                Err(err) => {
+                    anyhow::bail!("Closed TX end of command channel ({})", err);
+                }
+            },
+            Status::Terminated => {
+                // break the main loop, finishing this thread
+                break;
+            }
+        }
+
+        match maybe_new_status {
+            Some(new_status) if new_status != status => {
+                notifications_tx.send(Notification::NewStatus(new_status))?;
+                status = new_status;
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::buffer::SampleBuffer;
+    use crate::export::{Alignment, ExportLayout, ExportOptions};
+    use crate::session::SessionWriter;
+    use crate::utils::temporary_path;
+    use crate::SignalConfig;
+
+    fn exported_signals() -> (Vec<SignalConfig>, HashMap<u32, SampleBuffer>) {
+        let signals = vec![
+            SignalConfig::new(3, "a".into(), None),
+            SignalConfig::new(5, "b".into(), None),
+        ];
+
+        let mut samples = HashMap::new();
+        for i in 0..10 {
+            let t = i as f64 * 0.1;
+            samples
+                .entry(3)
+                .or_insert_with(SampleBuffer::new)
+                .push(t, i as f64);
+            samples
+                .entry(5)
+                .or_insert_with(SampleBuffer::new)
+                .push(t, -(i as f64));
+        }
+
+        (signals, samples)
+    }
+
+    #[test]
+    fn test_load_exports() {
+        let (signals, samples) = exported_signals();
+
+        let csv_filename = temporary_path("replay-export", "csv");
+        let options = ExportOptions {
+            layout: ExportLayout::Tidy,
+            range: None,
+            decimation: 1,
+        };
+        crate::export::write_csv(&csv_filename, &signals, &samples, &options).unwrap();
+        let from_csv = Recording::load(&csv_filename, 10.0).unwrap();
+        std::fs::remove_file(&csv_filename).unwrap();
+
+        let npy_filename = temporary_path("replay-export", "npy");
+        crate::export::write_npy(
+            &npy_filename,
+            &signals,
+            &samples,
+            &ExportOptions {
+                layout: ExportLayout::Aligned(Alignment::Hold),
+                ..options
+            },
+        )
+        .unwrap();
+        let from_npy = Recording::load(&npy_filename, 10.0).unwrap();
+        std::fs::remove_file(&npy_filename).unwrap();
+
+        assert_eq!(from_csv.signals, [(0, "a".into()), (1, "b".into())]);
+        assert_eq!(from_npy.signals.len(), 2);
+
+        for recording in [from_csv, from_npy] {
+            assert_eq!(recording.events.len(), 10);
+            assert_eq!(recording.duration(), 900_000);
+
+            match &recording.events[4] {
+                ReplayEvent::Sample((t, values)) => {
+                    assert_eq!(*t, 400_000);
+                    assert_eq!(values, &[(0, 4.0), (1, -4.0)]);
+                }
+                event => panic!("unexpected event {:?}", event),
+            }
+        }
+    }
+
+    #[test]
+    fn test_csv_time_column() {
+        let filename = temporary_path("replay-time-column", "csv");
+        std::fs::write(&filename, "x,time\n1,0.5\n2,0.25\n").unwrap();
+        let recording = Recording::load(&filename, 1.0).unwrap();
+        std::fs::remove_file(&filename).unwrap();
+
+        assert_eq!(recording.signals, [(0, "x".into())]);
+        let times: Vec<_> = recording.events.iter().map(ReplayEvent::time).collect();
+        assert_eq!(times, [0, 250_000]);
+    }
+
+    #[test]
+    fn test_csv_rebased_times() {
+        // exported around a trigger, a long time into the capture
+        let filename = temporary_path("replay-rebased", "csv");
+        std::fs::write(&filename, "time,x\n3599.9,1\n3600.0,2\n3600.1,3\n").unwrap();
+        let recording = Recording::load(&filename, 1.0).unwrap();
+
+        let times: Vec<_> = recording.events.iter().map(ReplayEvent::time).collect();
+        assert_eq!(times, [0, 100_000, 200_000]);
+        assert_eq!(recording.duration(), 200_000);
+
+        // the playback starts right away, instead of an hour later
+        let sampler = Box::new(ReplaySampler::start(&filename, 1.0, 1.0).unwrap());
+        std::fs::remove_file(&filename).unwrap();
+
+        let (t, _) = sampler
+            .sampled_channel()
+            .recv_timeout(Duration::from_secs(1))
+            .unwrap();
+        assert_eq!(t, 0);
+        sampler.stop();
+
+        let filename = temporary_path("replay-negative", "csv");
+        std::fs::write(&filename, "time,signal,value\n-0.2,x,1\n0.0,x,2\n").unwrap();
+        let recording = Recording::load(&filename, 1.0).unwrap();
+        std::fs::remove_file(&filename).unwrap();
+
+        let times: Vec<_> = recording.events.iter().map(ReplayEvent::time).collect();
+        assert_eq!(times, [0, 200_000]);
+    }
+
+    #[test]
+    fn test_replay_session() {
+        let filename = temporary_path("replay-session", "session");
+        {
+            let mut writer = SessionWriter::create(&filename).unwrap();
+            writer.write_settings(&[]).unwrap();
+            writer
+                .write_signal(&SignalConfig::new(1, "x".into(), None))
+                .unwrap();
+            for i in 0..100 {
+                writer
+                    .write_sample(&(i * 1000, vec![(1, i as f64)]))
+                    .unwrap();
+            }
+            writer
+                .write_notification(&Notification::ConsoleOutput("done\n".into()))
+                .unwrap();
+            writer.flush().unwrap();
+        }
+
+        // 100 ms of recording replayed in 50 ms
+        let sampler = Box::new(ReplaySampler::start(&filename, 1.0, 2.0).unwrap());
+        std::fs::remove_file(&filename).unwrap();
+
+        assert_eq!(sampler.available_signals(), [(1, "x".into())]);
+        assert_eq!(sampler.duration(), 99_000);
+
+        let received: Vec<Sample> = (0..100)
+            .map(|_| {
+                sampler
+                    .sampled_channel()
+                    .recv_timeout(Duration::from_secs(5))
+                    .unwrap()
+            })
+            .collect();
+        assert!(received
+            .iter()
+            .enumerate()
+            .all(|(i, (t, values))| *t == i as u64 * 1000 && values == &[(1, i as f64)]));
+
+        let notifications: Vec<_> = (0..4)
+            .map(|_| {
+                sampler
+                    .notification_channel()
+                    .recv_timeout(Duration::from_secs(5))
+                    .unwrap()
+            })
+            .collect();
+        assert!(matches!(&notifications[1], Notification::ConsoleOutput(text) if text == "done\n"));
+        assert!(matches!(&notifications[2], Notification::Info(_)));
+        assert!(matches!(
+            notifications[3],
+            Notification::NewStatus(Status::Paused)
+        ));
+
+        // seeking while paused plays back from there when resumed
+        sampler.seek(90_000);
+        sampler.resume();
+        let (t, _) = sampler
+            .sampled_channel()
+            .recv_timeout(Duration::from_secs(5))
+            .unwrap();
+        assert_eq!(t, 90_000);
+
+        sampler.stop();
+    }
+
+    #[test]
+    fn test_seek_while_sampling() {
+        let filename = temporary_path("replay-seek", "session");
+        {
+            let mut writer = SessionWriter::create(&filename).unwrap();
+            writer.write_settings(&[]).unwrap();
+            writer
+                .write_signal(&SignalConfig::new(1, "x".into(), None))
+                .unwrap();
+            for i in 0..10_000 {
+                writer
+                    .write_sample(&(i * 100, vec![(1, i as f64)]))
+                    .unwrap();
+            }
+            writer.flush().unwrap();
+        }
+
+        let sampler = Box::new(ReplaySampler::start(&filename, 1.0, 1.0).unwrap());
+        std::fs::remove_file(&filename).unwrap();
+
+        // the samples pile up in the channel, then the playback goes back
+        thread::sleep(Duration::from_millis(300));
+        sampler.seek(100_000);
+
+        let received: Vec<u64> = (0..100)
+            .map(|_| {
+                sampler
+                    .sampled_channel()
+                    .recv_timeout(Duration::from_secs(5))
+                    .unwrap()
+                    .0
+            })
+            .collect();
+        assert_eq!(received[0], 100_000);
+        assert!(received.windows(2).all(|t| t[0] < t[1]));
+
+        sampler.stop();
+    }
+}
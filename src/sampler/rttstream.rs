@@ -0,0 +1,931 @@
+use std::{
+    sync::mpsc,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+
+use super::{Notification, Sample};
+
+// parameters of the online stream synchronization heuristics, see `RTTStreamDecoder`
+const RESYNC_WINDOW: usize = 8;
+const RESYNC_MIN_SCORE: usize = RESYNC_WINDOW - 2;
+const RESYNC_TRIGGER_STREAK: usize = 4;
+const RESYNC_MAX_DELTA_FACTOR: f64 = 8.0;
+const RESYNC_DELTA_AVERAGING: f64 = 0.05;
+
+// gains of the loop that smooths host receive timestamps, see `SampleTimestamper`
+const HOST_TIME_PERIOD_AVERAGING: f64 = 0.05;
+const HOST_TIME_PHASE_CORRECTION: f64 = 0.1;
+
+/// Consecutive timestamps going backwards after which the counter is assumed to have restarted,
+/// e.g. after a target reset, instead of a few packets being out of order.
+const TIMESTAMP_REANCHOR_STREAK: usize = 3;
+
+/// How to timestamp the samples of RTT channels whose packets don't carry a timestamp field.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RTTTimeSource {
+    /// Samples are evenly spaced according to the given nominal sample rate [Hz].
+    NominalRate(f64),
+    /// Samples are timestamped with the host receive time, smoothed to remove the jitter
+    /// due to RTT polling and TCP transfers.
+    HostTime,
+    /// Samples are numbered, and the index is used as timestamp (one second per sample).
+    SampleIndex,
+}
+
+/// Unit of the ticks of the packets timestamp field.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RTTTimestampUnit {
+    Microseconds,
+    Nanoseconds,
+    /// CPU cycles (e.g. from `DWT->CYCCNT`) at the given core clock [MHz].
+    CpuCycles(f64),
+}
+
+impl RTTTimestampUnit {
+    fn ticks_to_micros(&self, ticks: u64) -> u64 {
+        match *self {
+            RTTTimestampUnit::Microseconds => ticks,
+            RTTTimestampUnit::Nanoseconds => ticks / 1000,
+            RTTTimestampUnit::CpuCycles(mhz) => (ticks as f64 / mhz).round() as u64,
+        }
+    }
+}
+
+/// Where to look for the RTT control block, and how to poll and timestamp the scope channel
+/// found there.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RTTSettings {
+    pub search_address: u32,
+    pub search_size: u32,
+    /// Interval between two reads of the ring buffer, in milliseconds.
+    pub polling_interval: u32,
+    pub timestamp_unit: RTTTimestampUnit,
+    pub untimed_time_source: RTTTimeSource,
+    pub relative_time: bool,
+}
+
+impl RTTSettings {
+    pub fn timestamper(&self) -> SampleTimestamper {
+        SampleTimestamper::new(
+            self.timestamp_unit,
+            self.untimed_time_source,
+            self.relative_time,
+        )
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RTTScopePacketFieldType {
+    Boolean,
+    Float,
+    Signed,
+    Unsigned,
+}
+
+#[derive(Debug)]
+pub struct RTTScopePacketField {
+    pub type_: RTTScopePacketFieldType,
+    pub size: u8,
+}
+
+impl RTTScopePacketField {
+    fn parse(description: &str) -> Option<RTTScopePacketField> {
+        debug_assert!(description.len() == 2);
+
+        let type_char = (description.chars().nth(0)? as char).to_ascii_lowercase();
+        let size_char = (description.chars().nth(1)? as char).to_ascii_lowercase();
+
+        let size = [1, 2, 4][['1', '2', '4'].iter().position(|&c| c == size_char)?];
+
+        match (type_char, size) {
+            ('b', 1) => Some(RTTScopePacketField {
+                type_: RTTScopePacketFieldType::Boolean,
+                size,
+            }),
+            ('f', 4) => Some(RTTScopePacketField {
+                type_: RTTScopePacketFieldType::Float,
+                size,
+            }),
+            ('i', _) => Some(RTTScopePacketField {
+                type_: RTTScopePacketFieldType::Signed,
+                size,
+            }),
+            ('u', _) => Some(RTTScopePacketField {
+                type_: RTTScopePacketFieldType::Unsigned,
+                size,
+            }),
+            _ => None,
+        }
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Option<f32> {
+        use RTTScopePacketFieldType::*;
+
+        debug_assert!(bytes.len() == self.size as usize);
+
+        match self.type_ {
+            Boolean if *bytes.get(0)? != 0 => Some(1.0),
+            Boolean if *bytes.get(0)? == 0 => Some(0.0),
+            Float => Some(f32::from_le_bytes(bytes.try_into().ok()?)),
+            Signed if self.size == 1 => Some(i8::from_le_bytes(bytes.try_into().ok()?) as f32),
+            Signed if self.size == 2 => Some(i16::from_le_bytes(bytes.try_into().ok()?) as f32),
+            Signed if self.size == 4 => Some(i32::from_le_bytes(bytes.try_into().ok()?) as f32),
+            Unsigned if self.size == 1 => Some(u8::from_le_bytes(bytes.try_into().ok()?) as f32),
+            Unsigned if self.size == 2 => Some(u16::from_le_bytes(bytes.try_into().ok()?) as f32),
+            Unsigned if self.size == 4 => Some(u32::from_le_bytes(bytes.try_into().ok()?) as f32),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct RTTScopePacketStructure {
+    pub has_u32_us_time: bool,
+    pub fields: Vec<RTTScopePacketField>,
+}
+
+impl RTTScopePacketStructure {
+    pub fn packet_size(&self) -> usize {
+        let time_field_size = if self.has_u32_us_time { 4 } else { 0 };
+
+        self.fields
+            .iter()
+            .map(|field| field.size as usize)
+            .sum::<usize>()
+            + time_field_size
+    }
+
+    pub fn available_signals(&self) -> Vec<(u32, String)> {
+        self.fields
+            .iter()
+            .enumerate()
+            .map(|(i, field)| {
+                (
+                    i as u32,
+                    format!("y{} ({:?}, {} bytes)", i, field.type_, field.size),
+                )
+            })
+            .collect()
+    }
+
+    pub fn decode_bytes(&self, mut bytes: &[u8]) -> Option<(Option<u32>, Vec<f32>)> {
+        let time = if self.has_u32_us_time {
+            let time_bytes = bytes[0..4].try_into().ok()?;
+            bytes = &bytes[4..];
+            Some(u32::from_le_bytes(time_bytes))
+        } else {
+            None
+        };
+
+        let values = self
+            .fields
+            .iter()
+            .map(|field| {
+                let to_decode = &bytes[0..field.size as usize];
+                bytes = &bytes[field.size as usize..];
+                field.decode(to_decode)
+            })
+            .collect::<Option<Vec<f32>>>()?;
+
+        Some((time, values))
+    }
+}
+
+/// Tells whether an RTT up-channel, given its name, likely carries scope packets.
+pub fn is_scope_channel_name(name: &str) -> bool {
+    // TODO: better detection logic
+    name.to_lowercase().contains("scope")
+}
+
+pub fn parse_scope_packet_structure(channel_name: &str) -> Option<RTTScopePacketStructure> {
+    // parses something like "JScope_T4F4F4F4F4", see
+    // https://wiki.segger.com/UM08028_J-Scope#RTT_channel_naming_convention
+
+    let format_string = channel_name.split('_').last()?.to_ascii_lowercase();
+
+    let mut to_parse: &str = &format_string;
+    let mut packet_structure = RTTScopePacketStructure {
+        has_u32_us_time: false,
+        fields: Vec::new(),
+    };
+
+    match to_parse.strip_prefix("t4") {
+        Some(stripped) => {
+            to_parse = stripped;
+            packet_structure.has_u32_us_time = true;
+        }
+        None => {
+            packet_structure.has_u32_us_time = false;
+        }
+    }
+
+    while to_parse.len() >= 2 {
+        packet_structure
+            .fields
+            .push(RTTScopePacketField::parse(&to_parse[0..2])?);
+
+        to_parse = &to_parse[2..];
+    }
+
+    if to_parse.len() > 0 {
+        debug_assert!(to_parse.len() == 1);
+        log::warn!("leftover characters while parsing scope channel name");
+    }
+
+    Some(packet_structure)
+}
+
+/// Splits the raw RTT byte stream into scope packets, keeping track of their alignment.
+///
+/// When packets carry a timestamp, its monotonic and nearly constant increments tell whether
+/// we are aligned to the packet boundaries: the decoder locks to the byte offset whose
+/// timestamps look the most plausible, and relocks automatically when decoding starts producing
+/// nonsense. Without a timestamp, packets are sliced blindly and alignment must be ensured
+/// externally (i.e. by halting the target, or by starting to read from where the target is
+/// currently writing).
+pub struct RTTStreamDecoder {
+    packet_structure: RTTScopePacketStructure,
+    buffer: Vec<u8>,
+    position: usize,
+    locked: bool,
+    previous_timestamp: Option<u32>,
+    average_delta: f64,
+    implausible_streak: usize,
+    resync_count: u64,
+    dropped_packets: u64,
+}
+
+impl RTTStreamDecoder {
+    pub fn new(packet_structure: RTTScopePacketStructure) -> RTTStreamDecoder {
+        RTTStreamDecoder {
+            packet_structure,
+            buffer: Vec::new(),
+            position: 0,
+            locked: false,
+            previous_timestamp: None,
+            average_delta: 0.0,
+            implausible_streak: 0,
+            resync_count: 0,
+            dropped_packets: 0,
+        }
+    }
+
+    pub fn feed(&mut self, bytes: &[u8]) {
+        // compact the already consumed bytes once per feed, instead of once per packet
+        self.buffer.drain(..self.position);
+        self.position = 0;
+
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Number of times the stream lost and then recovered the alignment.
+    pub fn resync_count(&self) -> u64 {
+        self.resync_count
+    }
+
+    /// Number of packets thrown away because they didn't look plausible, or while searching
+    /// for the packet boundaries.
+    pub fn dropped_packets(&self) -> u64 {
+        self.dropped_packets
+    }
+
+    pub fn next_packet(&mut self) -> Option<(Option<u32>, Vec<f32>)> {
+        let packet_size = self.packet_structure.packet_size();
+
+        if !self.packet_structure.has_u32_us_time {
+            let packet = self.peek_packet(0)?;
+            self.position += packet_size;
+            return Some(packet);
+        }
+
+        loop {
+            if !self.locked && !self.try_lock() {
+                return None;
+            }
+
+            // we need the following packet too, so that a single large (but legitimate) gap in
+            // the timestamps is not mistaken for a misalignment
+            let next_timestamp = self.peek_timestamp(packet_size)?;
+            let (maybe_timestamp, values) = self.peek_packet(0)?;
+            let timestamp = maybe_timestamp.expect("packet structure has a timestamp");
+
+            self.position += packet_size;
+
+            let delta_from_previous = self
+                .previous_timestamp
+                .map(|previous| timestamp.wrapping_sub(previous));
+            let delta_to_next = next_timestamp.wrapping_sub(timestamp);
+
+            self.previous_timestamp = Some(timestamp);
+
+            let plausible = delta_from_previous.is_none_or(|d| self.is_plausible_delta(d))
+                || self.is_plausible_delta(delta_to_next);
+
+            if plausible {
+                if let Some(delta) = delta_from_previous.filter(|&d| self.is_plausible_delta(d)) {
+                    self.average_delta +=
+                        (delta as f64 - self.average_delta) * RESYNC_DELTA_AVERAGING;
+                }
+
+                self.implausible_streak = 0;
+
+                return Some((Some(timestamp), values));
+            }
+
+            self.dropped_packets += 1;
+            self.implausible_streak += 1;
+
+            if self.implausible_streak >= RESYNC_TRIGGER_STREAK {
+                log::warn!("RTT stream lost synchronization, trying to relock");
+
+                self.locked = false;
+                self.resync_count += 1;
+            }
+        }
+    }
+
+    fn is_plausible_delta(&self, delta: u32) -> bool {
+        delta > 0 && (delta as f64) <= self.average_delta * RESYNC_MAX_DELTA_FACTOR
+    }
+
+    fn available(&self) -> usize {
+        self.buffer.len() - self.position
+    }
+
+    fn peek_timestamp(&self, offset: usize) -> Option<u32> {
+        let start = self.position + offset;
+        let bytes = self.buffer.get(start..start + 4)?;
+        Some(u32::from_le_bytes(bytes.try_into().ok()?))
+    }
+
+    fn peek_packet(&self, offset: usize) -> Option<(Option<u32>, Vec<f32>)> {
+        let start = self.position + offset;
+        let bytes = self
+            .buffer
+            .get(start..start + self.packet_structure.packet_size())?;
+
+        // decoding only fails if bytes are missing, so we treat that like incomplete data
+        self.packet_structure.decode_bytes(bytes)
+    }
+
+    /// Search the byte offset at which the timestamps of the following packets look the most
+    /// plausible, and lock onto it. Returns `false` if more data is needed.
+    fn try_lock(&mut self) -> bool {
+        let packet_size = self.packet_structure.packet_size();
+
+        while self.available() >= (RESYNC_WINDOW + 2) * packet_size {
+            let best = (0..packet_size)
+                .map(|offset| {
+                    let timestamps = (0..=RESYNC_WINDOW)
+                        .map(|i| self.peek_timestamp(offset + i * packet_size).unwrap())
+                        .collect::<Vec<_>>();
+
+                    let (score, dispersion, median_delta) = score_timestamps(&timestamps);
+                    (offset, score, dispersion, median_delta)
+                })
+                .max_by(|a, b| {
+                    // higher score first, then lower dispersion; reading the timestamp one byte
+                    // too early multiplies the increments by 256 without affecting their
+                    // regularity, so then prefer the smaller increments
+                    a.1.cmp(&b.1)
+                        .then(b.2.total_cmp(&a.2))
+                        .then(b.3.cmp(&a.3))
+                        .then(b.0.cmp(&a.0))
+                });
+
+            match best {
+                Some((offset, score, _, median_delta)) if score >= RESYNC_MIN_SCORE => {
+                    log::debug!(
+                        "RTT stream locked at offset {} (score {}, median delta {})",
+                        offset,
+                        score,
+                        median_delta
+                    );
+
+                    self.position += offset;
+                    self.locked = true;
+                    self.previous_timestamp = None;
+                    self.average_delta = median_delta as f64;
+                    self.implausible_streak = 0;
+
+                    return true;
+                }
+                _ => {
+                    // nothing looks like a packet boundary here, skip ahead and retry
+                    self.position += packet_size;
+                    self.dropped_packets += 1;
+                }
+            }
+        }
+
+        false
+    }
+}
+
+/// Score how much a sequence of raw timestamps looks like the one of a correctly aligned stream,
+/// that is with positive and nearly constant increments. Returns the number of increments that
+/// are close to the median one, the mean relative deviation from the median, and the median.
+fn score_timestamps(timestamps: &[u32]) -> (usize, f64, u32) {
+    let mut deltas = timestamps
+        .windows(2)
+        .map(|pair| pair[1].wrapping_sub(pair[0]))
+        .filter(|&delta| delta > 0 && delta < (1 << 31))
+        .collect::<Vec<_>>();
+
+    if deltas.is_empty() {
+        return (0, f64::INFINITY, 0);
+    }
+
+    deltas.sort_unstable();
+    let median = deltas[deltas.len() / 2];
+
+    let score = deltas
+        .iter()
+        .filter(|&&delta| delta >= median / 4 && delta <= median.saturating_mul(4))
+        .count();
+
+    let dispersion = deltas
+        .iter()
+        .map(|&delta| (delta as f64 - median as f64).abs() / median as f64)
+        .sum::<f64>()
+        / deltas.len() as f64;
+
+    (score, dispersion, median)
+}
+
+/// Assigns the final timestamps [µs] to the decoded packets, either from their own timestamp
+/// field or, if they don't have one, from the configured [`RTTTimeSource`].
+///
+/// The timestamp field is a free-running 32-bit counter, which wraps around (after ~71 minutes
+/// when counting microseconds, or after a few seconds when counting CPU cycles): we accumulate
+/// its wrapping increments into a monotonic 64-bit timeline. Going backwards is clamped, and
+/// forward jumps larger than half the counter range can't be told apart from that; when it
+/// keeps going backwards, the counter restarted, and the timeline continues from there.
+pub struct SampleTimestamper {
+    timestamp_unit: RTTTimestampUnit,
+    last_raw_timestamp: Option<u32>,
+    unwrapped_ticks: u64,
+    backward_streak: usize,
+    untimed_time_source: RTTTimeSource,
+    relative_time: bool,
+    origin: Option<u64>,
+    sample_index: u64,
+    host_start: Instant,
+    host_period: Option<f64>,
+    host_last_received_at: Option<f64>,
+    host_last_timestamp: Option<f64>,
+}
+
+impl SampleTimestamper {
+    pub fn new(
+        timestamp_unit: RTTTimestampUnit,
+        untimed_time_source: RTTTimeSource,
+        relative_time: bool,
+    ) -> SampleTimestamper {
+        SampleTimestamper {
+            timestamp_unit,
+            last_raw_timestamp: None,
+            unwrapped_ticks: 0,
+            backward_streak: 0,
+            untimed_time_source,
+            relative_time,
+            origin: None,
+            sample_index: 0,
+            host_start: Instant::now(),
+            host_period: None,
+            host_last_received_at: None,
+            host_last_timestamp: None,
+        }
+    }
+
+    /// Timestamp a batch of packets received all together at `received_at`.
+    pub fn timestamp_batch(
+        &mut self,
+        packet_timestamps: impl ExactSizeIterator<Item = Option<u32>>,
+        received_at: Instant,
+    ) -> Vec<u64> {
+        let batch_len = packet_timestamps.len();
+
+        let mut host_timestamps = match self.untimed_time_source {
+            RTTTimeSource::HostTime => self.smooth_host_time(batch_len, received_at),
+            _ => Vec::new(),
+        }
+        .into_iter();
+
+        packet_timestamps
+            .map(|maybe_timestamp| {
+                let absolute = match (maybe_timestamp, self.untimed_time_source) {
+                    (Some(timestamp), _) => {
+                        let ticks = self.unwrap_timestamp(timestamp);
+                        self.timestamp_unit.ticks_to_micros(ticks)
+                    }
+                    (None, RTTTimeSource::NominalRate(rate)) => {
+                        (self.sample_index as f64 * 1e6 / rate).round() as u64
+                    }
+                    (None, RTTTimeSource::HostTime) => host_timestamps.next().unwrap_or(0),
+                    (None, RTTTimeSource::SampleIndex) => self.sample_index * 1_000_000,
+                };
+
+                self.sample_index += 1;
+
+                if self.relative_time {
+                    absolute.saturating_sub(*self.origin.get_or_insert(absolute))
+                } else {
+                    absolute
+                }
+            })
+            .collect()
+    }
+
+    fn unwrap_timestamp(&mut self, raw_timestamp: u32) -> u64 {
+        match self.last_raw_timestamp {
+            Some(last) => {
+                let delta = raw_timestamp.wrapping_sub(last) as i32;
+
+                if delta >= 0 {
+                    self.unwrapped_ticks += delta as u64;
+                    self.last_raw_timestamp = Some(raw_timestamp);
+                    self.backward_streak = 0;
+                } else if self.backward_streak + 1 >= TIMESTAMP_REANCHOR_STREAK {
+                    log::warn!("timestamps keep going backwards, assuming the counter restarted");
+                    self.last_raw_timestamp = Some(raw_timestamp);
+                    self.backward_streak = 0;
+                } else {
+                    log::debug!("clamping timestamp going backwards by {} ticks", -delta);
+                    self.backward_streak += 1;
+                }
+            }
+            None => {
+                self.unwrapped_ticks = raw_timestamp as u64;
+                self.last_raw_timestamp = Some(raw_timestamp);
+            }
+        }
+
+        self.unwrapped_ticks
+    }
+
+    /// Spread the samples of a batch over the time elapsed since the previous batch, while
+    /// slowly tracking the host clock: a simple loop estimates the sample period and corrects
+    /// the accumulated phase error, so that the result is both smooth and not drifting.
+    fn smooth_host_time(&mut self, batch_len: usize, received_at: Instant) -> Vec<u64> {
+        if batch_len == 0 {
+            return Vec::new();
+        }
+
+        let received_at = (received_at - self.host_start).as_secs_f64() * 1e6;
+
+        let (last_received_at, last_timestamp) =
+            match (self.host_last_received_at, self.host_last_timestamp) {
+                (Some(last_received_at), Some(last_timestamp)) => {
+                    (last_received_at, last_timestamp)
+                }
+                _ => {
+                    // first batch: nothing to infer the period from, stack the samples at the
+                    // receive time
+                    self.host_last_received_at = Some(received_at);
+                    self.host_last_timestamp = Some(received_at);
+                    return vec![received_at.round() as u64; batch_len];
+                }
+            };
+
+        let measured_period = (received_at - last_received_at) / batch_len as f64;
+        let period = match self.host_period {
+            Some(period) => period + (measured_period - period) * HOST_TIME_PERIOD_AVERAGING,
+            None => measured_period,
+        };
+
+        let phase_error = received_at - (last_timestamp + period * batch_len as f64);
+        let start = last_timestamp + phase_error * HOST_TIME_PHASE_CORRECTION;
+
+        let timestamps = (1..=batch_len)
+            .map(|i| f64::max(start + period * i as f64, last_timestamp))
+            .collect::<Vec<_>>();
+
+        self.host_period = Some(period);
+        self.host_last_received_at = Some(received_at);
+        self.host_last_timestamp = timestamps.last().copied();
+
+        timestamps.into_iter().map(|t| t.round() as u64).collect()
+    }
+}
+
+/// The part of the RTT samplers after the ring buffer is read, whatever the transport: decodes
+/// the received bytes, timestamps and sends the samples, and reports resyncs and the rate.
+pub struct RTTSampleStream {
+    decoder: RTTStreamDecoder,
+    timestamper: SampleTimestamper,
+    previous_rate_measurement_instant: Instant,
+    rate_measurement_samples_received: usize,
+}
+
+impl RTTSampleStream {
+    pub fn new(
+        packet_structure: RTTScopePacketStructure,
+        settings: &RTTSettings,
+    ) -> RTTSampleStream {
+        RTTSampleStream {
+            decoder: RTTStreamDecoder::new(packet_structure),
+            timestamper: settings.timestamper(),
+            previous_rate_measurement_instant: Instant::now(),
+            rate_measurement_samples_received: 0,
+        }
+    }
+
+    /// Start measuring the sample rate over from now, e.g. when the target starts running.
+    pub fn restart_rate_measurement(&mut self) {
+        self.previous_rate_measurement_instant = Instant::now();
+        self.rate_measurement_samples_received = 0;
+    }
+
+    /// Process the `data` read from the ring buffer at `received_at`; the samples are decoded
+    /// and timestamped in any case, but only sent while `sampling`.
+    pub fn process(
+        &mut self,
+        data: &[u8],
+        received_at: Instant,
+        sampling: bool,
+        sampled_tx: &mpsc::SyncSender<Sample>,
+        notifications_tx: &mpsc::Sender<Notification>,
+    ) -> anyhow::Result<()> {
+        let decoder = &mut self.decoder;
+
+        decoder.feed(data);
+
+        let resyncs_before = decoder.resync_count();
+
+        let mut packets = Vec::new();
+        while let Some(packet) = decoder.next_packet() {
+            packets.push(packet);
+        }
+
+        let timestamps = self.timestamper.timestamp_batch(
+            packets.iter().map(|(maybe_timestamp, _)| *maybe_timestamp),
+            received_at,
+        );
+
+        for ((_, values), timestamp) in packets.into_iter().zip(timestamps) {
+            if sampling {
+                let samples = values
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, y)| (i as u32, y as f64))
+                    .collect::<Vec<(u32, f64)>>();
+
+                sampled_tx
+                    .send((timestamp, samples))
+                    .context("failed to send sampled value")?;
+            }
+
+            self.rate_measurement_samples_received += 1;
+        }
+
+        if decoder.resync_count() != resyncs_before {
+            let message = format!(
+                "RTT stream resynchronized ({} resyncs, {} packets dropped)",
+                decoder.resync_count(),
+                decoder.dropped_packets()
+            );
+
+            log::info!("{}", message);
+            if let Err(err) = notifications_tx.send(Notification::Info(message)) {
+                log::error!("Failed to send info notification: {:?}", err);
+            }
+        }
+
+        let now = Instant::now();
+        if now - self.previous_rate_measurement_instant >= Duration::from_secs(1) {
+            let measured_rate = self.rate_measurement_samples_received as f64
+                / (now - self.previous_rate_measurement_instant).as_secs_f64();
+
+            log::debug!("measured rate {} samples/s", measured_rate);
+
+            let mut stats = format!("{} samples/s", measured_rate.round() as i64);
+            if decoder.resync_count() > 0 {
+                stats += &format!(", {} resyncs", decoder.resync_count());
+            }
+
+            if let Err(err) = notifications_tx.send(Notification::Info(stats)) {
+                log::error!("Failed to send info notification: {:?}", err);
+            }
+
+            self.restart_rate_measurement();
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn encode_packets(timestamps: impl Iterator<Item = u32>) -> Vec<u8> {
+        timestamps
+            .flat_map(|t| {
+                let value = (t as f32 * 0.001).sin();
+                [t.to_le_bytes(), value.to_le_bytes()].concat()
+            })
+            .collect()
+    }
+
+    fn decode_all(decoder: &mut RTTStreamDecoder) -> Vec<u32> {
+        let mut timestamps = Vec::new();
+        while let Some((t, _)) = decoder.next_packet() {
+            timestamps.push(t.unwrap());
+        }
+        timestamps
+    }
+
+    #[test]
+    fn test_decoder_locks_on_misaligned_start() {
+        let structure = parse_scope_packet_structure("JScope_T4F4").unwrap();
+        let mut decoder = RTTStreamDecoder::new(structure);
+
+        let bytes = encode_packets((0..100).map(|i| 1000 + i * 50));
+        decoder.feed(&bytes[3..]);
+
+        let timestamps = decode_all(&mut decoder);
+
+        assert!(timestamps.len() >= 90);
+        assert!(timestamps.windows(2).all(|pair| pair[1] - pair[0] == 50));
+        assert_eq!(decoder.resync_count(), 0);
+    }
+
+    #[test]
+    fn test_decoder_resyncs_after_lost_bytes() {
+        let structure = parse_scope_packet_structure("JScope_T4F4").unwrap();
+        let mut decoder = RTTStreamDecoder::new(structure);
+
+        let mut bytes = encode_packets((0..200).map(|i| 1000 + i * 50));
+        bytes.drain(800..803);
+        decoder.feed(&bytes);
+
+        let timestamps = decode_all(&mut decoder);
+
+        assert_eq!(decoder.resync_count(), 1);
+        assert!(timestamps.len() >= 180);
+        assert!(timestamps.windows(2).all(|pair| pair[1] > pair[0]));
+        // the very last packet is held back, waiting for the following one
+        assert_eq!(*timestamps.last().unwrap(), 1000 + 198 * 50);
+    }
+
+    #[test]
+    fn test_decoder_without_timestamp() {
+        let structure = parse_scope_packet_structure("JScope_I2U1").unwrap();
+        let mut decoder = RTTStreamDecoder::new(structure);
+
+        decoder.feed(&[0xFF, 0xFF, 7, 0x10, 0x00]);
+
+        assert_eq!(decoder.next_packet(), Some((None, vec![-1.0, 7.0])));
+        assert_eq!(decoder.next_packet(), None);
+    }
+
+    #[test]
+    fn test_timestamper_relative_time() {
+        let mut timestamper = SampleTimestamper::new(
+            RTTTimestampUnit::Microseconds,
+            RTTTimeSource::SampleIndex,
+            true,
+        );
+
+        let timestamps = timestamper.timestamp_batch(
+            [Some(5000), Some(5100), Some(5200)].into_iter(),
+            Instant::now(),
+        );
+
+        assert_eq!(timestamps, vec![0, 100, 200]);
+    }
+
+    #[test]
+    fn test_timestamper_nominal_rate_and_index() {
+        let mut timestamper = SampleTimestamper::new(
+            RTTTimestampUnit::Microseconds,
+            RTTTimeSource::NominalRate(1000.0),
+            false,
+        );
+        let timestamps =
+            timestamper.timestamp_batch([None, None, None].into_iter(), Instant::now());
+        assert_eq!(timestamps, vec![0, 1000, 2000]);
+
+        let mut timestamper = SampleTimestamper::new(
+            RTTTimestampUnit::Microseconds,
+            RTTTimeSource::SampleIndex,
+            false,
+        );
+        let timestamps = timestamper.timestamp_batch([None, None].into_iter(), Instant::now());
+        assert_eq!(timestamps, vec![0, 1_000_000]);
+    }
+
+    #[test]
+    fn test_timestamper_unwraps_timestamps() {
+        let mut timestamper = SampleTimestamper::new(
+            RTTTimestampUnit::Microseconds,
+            RTTTimeSource::SampleIndex,
+            false,
+        );
+
+        let raw = [u32::MAX - 100, u32::MAX, 99, 1099];
+        let timestamps = timestamper.timestamp_batch(raw.map(Some).into_iter(), Instant::now());
+
+        let base = (u32::MAX - 100) as u64;
+        assert_eq!(timestamps, vec![base, base + 100, base + 200, base + 1200]);
+
+        // small steps backwards are clamped
+        let timestamps = timestamper.timestamp_batch([Some(1000)].into_iter(), Instant::now());
+        assert_eq!(timestamps, vec![base + 1200]);
+    }
+
+    #[test]
+    fn test_timestamper_reanchors_after_reset() {
+        let mut timestamper = SampleTimestamper::new(
+            RTTTimestampUnit::Microseconds,
+            RTTTimeSource::SampleIndex,
+            false,
+        );
+
+        // a single packet out of order is clamped, without losing track of the counter
+        let raw = [1000, 2000, 1500, 3000];
+        let timestamps = timestamper.timestamp_batch(raw.map(Some).into_iter(), Instant::now());
+        assert_eq!(timestamps, vec![1000, 2000, 2000, 3000]);
+
+        // the target is reset, and its counter restarts from zero
+        let raw = [50, 150, 250, 350, 450];
+        let timestamps = timestamper.timestamp_batch(raw.map(Some).into_iter(), Instant::now());
+        assert_eq!(timestamps, vec![3000, 3000, 3000, 3100, 3200]);
+    }
+
+    #[test]
+    fn test_timestamper_units() {
+        let mut timestamper = SampleTimestamper::new(
+            RTTTimestampUnit::CpuCycles(168.0),
+            RTTTimeSource::SampleIndex,
+            true,
+        );
+
+        let raw = [0, 168_000, 336_000, 168_000_000];
+        let timestamps = timestamper.timestamp_batch(raw.map(Some).into_iter(), Instant::now());
+        assert_eq!(timestamps, vec![0, 1000, 2000, 1_000_000]);
+
+        let mut timestamper = SampleTimestamper::new(
+            RTTTimestampUnit::Nanoseconds,
+            RTTTimeSource::SampleIndex,
+            true,
+        );
+
+        let raw = [4_000_000_000, 500_000_000];
+        let timestamps = timestamper.timestamp_batch(raw.map(Some).into_iter(), Instant::now());
+        assert_eq!(timestamps, vec![0, 794_967]);
+    }
+
+    #[test]
+    fn test_timestamper_host_time_is_smooth() {
+        let mut timestamper = SampleTimestamper::new(
+            RTTTimestampUnit::Microseconds,
+            RTTTimeSource::HostTime,
+            false,
+        );
+        let start = timestamper.host_start;
+
+        let mut timestamps = Vec::new();
+        for batch in 0..200u64 {
+            // 10 samples every 10ms, with a few ms of jitter on the receive time
+            let jitter = [0, 3, 1, 4, 2][batch as usize % 5];
+            let received_at = start + Duration::from_millis(batch * 10 + jitter);
+
+            timestamps.extend(timestamper.timestamp_batch([None; 10].into_iter(), received_at));
+        }
+
+        assert!(timestamps.windows(2).all(|pair| pair[1] >= pair[0]));
+
+        // after settling, the spacing should be close to the true period of 1ms
+        let settled = &timestamps[1000..];
+        let deltas = settled
+            .windows(2)
+            .map(|pair| pair[1] as f64 - pair[0] as f64);
+        assert!(deltas.clone().all(|delta| (delta - 1000.0).abs() < 300.0));
+
+        // and the timestamps should track the host clock
+        let last_received = Duration::from_millis(199 * 10 + 4).as_micros() as f64;
+        assert!((*timestamps.last().unwrap() as f64 - last_received).abs() < 5000.0);
+    }
+
+    #[test]
+    fn test_decoder_tolerates_timestamp_gap() {
+        let structure = parse_scope_packet_structure("JScope_T4F4").unwrap();
+        let mut decoder = RTTStreamDecoder::new(structure);
+
+        let before_gap = (0..50).map(|i| 1000 + i * 50);
+        let after_gap = (0..50).map(|i| 1_000_000 + i * 50);
+        decoder.feed(&encode_packets(before_gap.chain(after_gap)));
+
+        let timestamps = decode_all(&mut decoder);
+
+        assert_eq!(decoder.resync_count(), 0);
+        assert_eq!(decoder.dropped_packets(), 0);
+        assert_eq!(timestamps.len(), 99);
+    }
+}
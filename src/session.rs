@@ -0,0 +1,440 @@
+// Session files, recording everything that happens while sampling so that it can be reopened
+// later for offline analysis.
+//
+// A session file is append-only: after a header, it's a sequence of tagged records, each made
+// of a tag byte, a little endian u32 payload length and the payload. Records with unknown tags
+// are skipped, and a record truncated at the end of the file (like the last one written before
+// a crash) is ignored.
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::Path;
+
+use anyhow::Context;
+use eframe::egui::Color32;
+
+use crate::sampler::{Notification, Sample};
+use crate::SignalConfig;
+
+const MAGIC: &[u8; 16] = b"OCDSCOPE-SESSION";
+const VERSION: u16 = 1;
+
+const TAG_SETTINGS: u8 = 1;
+const TAG_SIGNAL: u8 = 2;
+const TAG_SAMPLE: u8 = 3;
+const TAG_NOTIFICATION: u8 = 4;
+
+const NOTIFICATION_INFO: u8 = 0;
+const NOTIFICATION_ERROR: u8 = 1;
+const NOTIFICATION_CONSOLE_OUTPUT: u8 = 2;
+const NOTIFICATION_TARGET_STOPPED: u8 = 3;
+
+/// Records larger than this are considered a sign of a corrupted file.
+const MAX_RECORD_SIZE: usize = 64 * 1024 * 1024;
+
+/// Metadata of a recorded signal.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionSignal {
+    pub id: u32,
+    pub name: String,
+    pub color: Color32,
+    pub scale: f64,
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone)]
+pub enum Record {
+    /// Connection settings, as key-value pairs.
+    Settings(Vec<(String, String)>),
+    Signal(SessionSignal),
+    Sample(Sample),
+    Notification(Notification),
+}
+
+pub struct SessionWriter {
+    writer: BufWriter<File>,
+    recorded_signals: HashSet<u32>,
+}
+
+impl SessionWriter {
+    pub fn create(filename: &Path) -> anyhow::Result<SessionWriter> {
+        let file = File::create(filename)
+            .with_context(|| format!("cannot create session file {:?}", filename))?;
+
+        let mut writer = BufWriter::new(file);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+
+        Ok(SessionWriter {
+            writer,
+            recorded_signals: HashSet::new(),
+        })
+    }
+
+    /// Whether the metadata of signal `id` was already recorded.
+    pub fn has_signal(&self, id: u32) -> bool {
+        self.recorded_signals.contains(&id)
+    }
+
+    pub fn write_settings(&mut self, settings: &[(String, String)]) -> anyhow::Result<()> {
+        let mut payload = Vec::new();
+        for (key, value) in settings {
+            put_string(&mut payload, key);
+            put_string(&mut payload, value);
+        }
+
+        self.write_record(TAG_SETTINGS, &payload)
+    }
+
+    pub fn write_signal(&mut self, signal: &SignalConfig) -> anyhow::Result<()> {
+        let mut payload = Vec::new();
+        payload.extend(signal.id.to_le_bytes());
+        payload.extend(signal.color.to_array());
+        payload.extend(signal.scale.to_le_bytes());
+        payload.push(signal.enabled as u8);
+        payload.extend(signal.name.as_bytes());
+
+        self.recorded_signals.insert(signal.id);
+        self.write_record(TAG_SIGNAL, &payload)
+    }
+
+    pub fn write_sample(&mut self, sample: &Sample) -> anyhow::Result<()> {
+        let (t, values) = sample;
+
+        let mut payload = Vec::with_capacity(8 + 12 * values.len());
+        payload.extend(t.to_le_bytes());
+        for (id, y) in values {
+            payload.extend(id.to_le_bytes());
+            payload.extend(y.to_le_bytes());
+        }
+
+        self.write_record(TAG_SAMPLE, &payload)
+    }
+
+    /// Record a notification; status changes are not recorded, since they make no sense
+    /// outside of a live sampler.
+    pub fn write_notification(&mut self, notification: &Notification) -> anyhow::Result<()> {
+        let mut payload = Vec::new();
+
+        match notification {
+            Notification::NewStatus(_) => return Ok(()),
+            Notification::Info(text) => {
+                payload.push(NOTIFICATION_INFO);
+                payload.extend(text.as_bytes());
+            }
+            Notification::Error(text) => {
+                payload.push(NOTIFICATION_ERROR);
+                payload.extend(text.as_bytes());
+            }
+            Notification::ConsoleOutput(text) => {
+                payload.push(NOTIFICATION_CONSOLE_OUTPUT);
+                payload.extend(text.as_bytes());
+            }
+            Notification::TargetStopped(time, description) => {
+                payload.push(NOTIFICATION_TARGET_STOPPED);
+                payload.push(time.is_some() as u8);
+                payload.extend(time.unwrap_or_default().to_le_bytes());
+                payload.extend(description.as_bytes());
+            }
+        }
+
+        self.write_record(TAG_NOTIFICATION, &payload)
+    }
+
+    /// Make sure everything recorded so far is on disk.
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn write_record(&mut self, tag: u8, payload: &[u8]) -> anyhow::Result<()> {
+        self.writer.write_all(&[tag])?;
+        self.writer
+            .write_all(&(payload.len() as u32).to_le_bytes())?;
+        self.writer.write_all(payload)?;
+
+        Ok(())
+    }
+}
+
+pub struct SessionReader {
+    reader: BufReader<File>,
+}
+
+impl SessionReader {
+    pub fn open(filename: &Path) -> anyhow::Result<SessionReader> {
+        let file = File::open(filename)
+            .with_context(|| format!("cannot open session file {:?}", filename))?;
+        let mut reader = BufReader::new(file);
+
+        let mut header = [0; MAGIC.len() + 2];
+        reader
+            .read_exact(&mut header)
+            .context("not a session file")?;
+
+        if &header[..MAGIC.len()] != MAGIC {
+            anyhow::bail!("not a session file");
+        }
+
+        let version = u16::from_le_bytes([header[MAGIC.len()], header[MAGIC.len() + 1]]);
+        if version > VERSION {
+            anyhow::bail!("unsupported session file version {}", version);
+        }
+
+        Ok(SessionReader { reader })
+    }
+
+    /// Read the next record, or `None` at the end of the file.
+    pub fn next_record(&mut self) -> anyhow::Result<Option<Record>> {
+        loop {
+            let mut record_header = [0; 5];
+            if !read_exact_or_eof(&mut self.reader, &mut record_header)? {
+                return Ok(None);
+            }
+
+            let tag = record_header[0];
+            let length = u32::from_le_bytes(record_header[1..].try_into().unwrap()) as usize;
+            if length > MAX_RECORD_SIZE {
+                anyhow::bail!("corrupted session file, record of {} bytes", length);
+            }
+
+            let mut payload = vec![0; length];
+            if !read_exact_or_eof(&mut self.reader, &mut payload)? {
+                log::warn!("ignoring the truncated record at the end of the session file");
+                return Ok(None);
+            }
+
+            let record = match tag {
+                TAG_SETTINGS => parse_settings(&payload),
+                TAG_SIGNAL => parse_signal(&payload),
+                TAG_SAMPLE => parse_sample(&payload),
+                TAG_NOTIFICATION => parse_notification(&payload),
+                _ => {
+                    log::warn!("skipping session record with unknown tag {}", tag);
+                    continue;
+                }
+            };
+
+            return record
+                .map(Some)
+                .with_context(|| format!("corrupted session record with tag {}", tag));
+        }
+    }
+}
+
+impl Iterator for SessionReader {
+    type Item = anyhow::Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_record().transpose()
+    }
+}
+
+/// Fill `buffer`, returning `false` if the file ends before that.
+fn read_exact_or_eof(reader: &mut impl Read, buffer: &mut [u8]) -> anyhow::Result<bool> {
+    match reader.read_exact(buffer) {
+        Ok(()) => Ok(true),
+        Err(err) if err.kind() == ErrorKind::UnexpectedEof => Ok(false),
+        Err(err) => Err(err.into()),
+    }
+}
+
+fn put_string(payload: &mut Vec<u8>, s: &str) {
+    payload.extend((s.len() as u32).to_le_bytes());
+    payload.extend(s.as_bytes());
+}
+
+/// Split `length` bytes off the front of `payload`.
+fn take<'a>(payload: &mut &'a [u8], length: usize) -> Option<&'a [u8]> {
+    if payload.len() < length {
+        return None;
+    }
+
+    let (taken, rest) = payload.split_at(length);
+    *payload = rest;
+    Some(taken)
+}
+
+fn take_u32(payload: &mut &[u8]) -> Option<u32> {
+    Some(u32::from_le_bytes(take(payload, 4)?.try_into().ok()?))
+}
+
+fn take_u64(payload: &mut &[u8]) -> Option<u64> {
+    Some(u64::from_le_bytes(take(payload, 8)?.try_into().ok()?))
+}
+
+fn take_string(payload: &mut &[u8]) -> Option<String> {
+    let length = take_u32(payload)? as usize;
+    String::from_utf8(take(payload, length)?.to_vec()).ok()
+}
+
+fn parse_settings(mut payload: &[u8]) -> anyhow::Result<Record> {
+    let mut settings = Vec::new();
+
+    while !payload.is_empty() {
+        let key = take_string(&mut payload).context("invalid key")?;
+        let value = take_string(&mut payload).context("invalid value")?;
+        settings.push((key, value));
+    }
+
+    Ok(Record::Settings(settings))
+}
+
+fn parse_signal(mut payload: &[u8]) -> anyhow::Result<Record> {
+    let id = take_u32(&mut payload).context("missing id")?;
+    let [r, g, b, a]: [u8; 4] = take(&mut payload, 4).context("missing color")?.try_into()?;
+    let scale = f64::from_bits(take_u64(&mut payload).context("missing scale")?);
+    let enabled = take(&mut payload, 1).context("missing enabled flag")?[0] != 0;
+    let name = String::from_utf8(payload.to_vec())?;
+
+    Ok(Record::Signal(SessionSignal {
+        id,
+        name,
+        color: Color32::from_rgba_premultiplied(r, g, b, a),
+        scale,
+        enabled,
+    }))
+}
+
+fn parse_sample(mut payload: &[u8]) -> anyhow::Result<Record> {
+    let t = take_u64(&mut payload).context("missing time")?;
+
+    if !payload.len().is_multiple_of(12) {
+        anyhow::bail!("invalid length of the values");
+    }
+
+    let values = payload
+        .chunks_exact(12)
+        .map(|value| {
+            let id = u32::from_le_bytes(value[..4].try_into().unwrap());
+            let y = f64::from_le_bytes(value[4..].try_into().unwrap());
+            (id, y)
+        })
+        .collect();
+
+    Ok(Record::Sample((t, values)))
+}
+
+fn parse_notification(mut payload: &[u8]) -> anyhow::Result<Record> {
+    let kind = take(&mut payload, 1).context("missing kind")?[0];
+
+    let notification = match kind {
+        NOTIFICATION_INFO => Notification::Info(String::from_utf8(payload.to_vec())?),
+        NOTIFICATION_ERROR => Notification::Error(String::from_utf8(payload.to_vec())?),
+        NOTIFICATION_CONSOLE_OUTPUT => {
+            Notification::ConsoleOutput(String::from_utf8(payload.to_vec())?)
+        }
+        NOTIFICATION_TARGET_STOPPED => {
+            let has_time = take(&mut payload, 1).context("missing time flag")?[0] != 0;
+            let time = take_u64(&mut payload).context("missing time")?;
+            let description = String::from_utf8(payload.to_vec())?;
+
+            Notification::TargetStopped(has_time.then_some(time), description)
+        }
+        _ => anyhow::bail!("unknown notification kind {}", kind),
+    };
+
+    Ok(Record::Notification(notification))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::temporary_path;
+
+    fn write_test_session(filename: &Path) {
+        let mut writer = SessionWriter::create(filename).unwrap();
+
+        writer
+            .write_settings(&[("gdb_address".into(), "127.0.0.1:3333".into())])
+            .unwrap();
+
+        let mut signal = SignalConfig::new(7, "temperature".into(), None);
+        signal.scale = 0.5;
+        signal.enabled = true;
+        assert!(!writer.has_signal(7));
+        writer.write_signal(&signal).unwrap();
+        assert!(writer.has_signal(7));
+
+        writer.write_sample(&(1000, vec![(7, 1.5)])).unwrap();
+        writer
+            .write_notification(&Notification::NewStatus(crate::sampler::Status::Sampling))
+            .unwrap();
+        writer
+            .write_notification(&Notification::ConsoleOutput("hello\n".into()))
+            .unwrap();
+        writer
+            .write_notification(&Notification::TargetStopped(Some(2000), "halted".into()))
+            .unwrap();
+        writer
+            .write_sample(&(3000, vec![(7, -2.0), (8, 4.0)]))
+            .unwrap();
+        writer.flush().unwrap();
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let filename = temporary_path("session-round-trip", "session");
+        write_test_session(&filename);
+
+        let records = SessionReader::open(&filename)
+            .unwrap()
+            .collect::<anyhow::Result<Vec<_>>>()
+            .unwrap();
+        std::fs::remove_file(&filename).unwrap();
+
+        assert_eq!(records.len(), 6);
+        assert!(matches!(&records[0], Record::Settings(s) if s[0].1 == "127.0.0.1:3333"));
+        match &records[1] {
+            Record::Signal(signal) => {
+                assert_eq!(signal.id, 7);
+                assert_eq!(signal.name, "temperature");
+                assert_eq!(signal.color, crate::utils::color_for_id(7));
+                assert_eq!(signal.scale, 0.5);
+                assert!(signal.enabled);
+            }
+            record => panic!("unexpected record {:?}", record),
+        }
+        assert!(matches!(&records[2], Record::Sample((1000, v)) if v == &[(7, 1.5)]));
+        assert!(
+            matches!(&records[3], Record::Notification(Notification::ConsoleOutput(t)) if t == "hello\n")
+        );
+        assert!(matches!(
+            &records[4],
+            Record::Notification(Notification::TargetStopped(Some(2000), d)) if d == "halted"
+        ));
+        assert!(matches!(&records[5], Record::Sample((3000, v)) if v == &[(7, -2.0), (8, 4.0)]));
+    }
+
+    #[test]
+    fn test_truncated_and_unknown_records() {
+        let filename = temporary_path("session-truncated", "session");
+        write_test_session(&filename);
+
+        // an unknown record in the middle is skipped, a truncated one at the end is ignored
+        let mut contents = std::fs::read(&filename).unwrap();
+        let full_length = contents.len();
+        contents.extend([0xff, 2, 0, 0, 0, 0xaa, 0xbb]);
+        contents.extend([TAG_SAMPLE, 20, 0, 0, 0, 1, 2, 3]);
+        std::fs::write(&filename, &contents).unwrap();
+
+        let records = SessionReader::open(&filename)
+            .unwrap()
+            .collect::<anyhow::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(records.len(), 6);
+
+        std::fs::write(&filename, &contents[..full_length - 3]).unwrap();
+        let records = SessionReader::open(&filename)
+            .unwrap()
+            .collect::<anyhow::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(records.len(), 5);
+
+        std::fs::write(&filename, b"not a session").unwrap();
+        assert!(SessionReader::open(&filename).is_err());
+
+        std::fs::remove_file(&filename).unwrap();
+    }
+}
@@ -0,0 +1,271 @@
+// Spectrogram of a signal: the amplitude spectra of consecutive segments of the samples, one column
+// each, rendered to an image with the frequencies on the vertical axis.
+
+use eframe::egui::{Color32, ColorImage};
+
+use crate::{
+    buffer::SampleBuffer,
+    spectrum::{self, Resampled, WindowFunction},
+};
+
+/// Columns computed at most, the hop between them being raised to fit wider time windows.
+const MAX_COLUMNS: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Colormap {
+    Viridis,
+    Inferno,
+    Grayscale,
+}
+
+impl Colormap {
+    pub const ALL: [Colormap; 3] = [Colormap::Viridis, Colormap::Inferno, Colormap::Grayscale];
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Colormap::Viridis => "Viridis",
+            Colormap::Inferno => "Inferno",
+            Colormap::Grayscale => "Grayscale",
+        }
+    }
+
+    /// Color of `value`, from 0 to 1, interpolating between a few stops of the colormap.
+    pub fn color(&self, value: f32) -> Color32 {
+        let stops: &[[u8; 3]] = match self {
+            Colormap::Viridis => &[
+                [68, 1, 84],
+                [59, 82, 139],
+                [33, 145, 140],
+                [94, 201, 98],
+                [253, 231, 37],
+            ],
+            Colormap::Inferno => &[
+                [0, 0, 4],
+                [87, 16, 110],
+                [188, 55, 84],
+                [249, 142, 9],
+                [252, 255, 164],
+            ],
+            Colormap::Grayscale => &[[0, 0, 0], [255, 255, 255]],
+        };
+
+        let position = value.clamp(0.0, 1.0) * (stops.len() - 1) as f32;
+        let i = (position.floor() as usize).min(stops.len() - 2);
+        let alpha = position - i as f32;
+
+        let channel = |c: usize| {
+            let (a, b) = (stops[i][c] as f32, stops[i + 1][c] as f32);
+            (a + (b - a) * alpha).round() as u8
+        };
+
+        Color32::from_rgb(channel(0), channel(1), channel(2))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpectrogramSettings {
+    pub signal: Option<u32>,
+    /// Number of samples of each FFT, a power of two.
+    pub fft_size: usize,
+    /// Samples between the starts of two consecutive FFTs, at least.
+    pub hop: usize,
+    pub window: WindowFunction,
+    pub colormap: Colormap,
+    /// Range of the amplitudes shown, in dB below the highest one.
+    pub dynamic_range: f32,
+}
+
+impl Default for SpectrogramSettings {
+    fn default() -> SpectrogramSettings {
+        SpectrogramSettings {
+            signal: None,
+            fft_size: 256,
+            hop: 64,
+            window: WindowFunction::Hann,
+            colormap: Colormap::Viridis,
+            dynamic_range: 80.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Spectrogram {
+    /// Time of the center of the first column, in seconds.
+    pub start: f64,
+    /// Time between two consecutive columns, in seconds.
+    pub column_time: f64,
+    /// Rate the samples were resampled at, in Hz.
+    pub sample_rate: f64,
+    pub fft_size: usize,
+    /// Amplitude, in dB, of the frequencies from DC to Nyquist of each column.
+    pub columns: Vec<Vec<f32>>,
+}
+
+impl Spectrogram {
+    /// Computes the spectrogram of the samples of `buffer` between `from_t` and `to_t`; `None` if
+    /// there are fewer of them than an FFT needs.
+    pub fn compute(
+        buffer: &SampleBuffer,
+        from_t: f64,
+        to_t: f64,
+        settings: &SpectrogramSettings,
+    ) -> Option<Spectrogram> {
+        let resampled = Resampled::new(buffer, from_t, to_t)?;
+        let sample_rate = resampled.rate;
+
+        let fft_size = settings.fft_size.next_power_of_two();
+        if resampled.len() < fft_size {
+            return None;
+        }
+
+        let hop = settings
+            .hop
+            .max(1)
+            .max((resampled.len() - fft_size).div_ceil(MAX_COLUMNS));
+
+        let window = settings.window.coefficients(fft_size);
+        let window_sum: f64 = window.iter().sum();
+
+        // only the segments under the columns get resampled, which the hop may leave gaps between
+        let columns = (0..=resampled.len() - fft_size)
+            .step_by(hop)
+            .map(|start| {
+                let values = resampled.values(start, fft_size);
+                let power = spectrum::power_spectrum(&values, &window, fft_size);
+
+                power
+                    .iter()
+                    .enumerate()
+                    .map(|(k, &p)| {
+                        let amplitude = spectrum::amplitude(k, p, fft_size, window_sum);
+                        20.0 * amplitude.max(1e-12).log10() as f32
+                    })
+                    .collect()
+            })
+            .collect();
+
+        Some(Spectrogram {
+            start: resampled.start + (fft_size / 2) as f64 / sample_rate,
+            column_time: hop as f64 / sample_rate,
+            sample_rate,
+            fft_size,
+            columns,
+        })
+    }
+
+    /// Frequency step between two rows, in Hz.
+    pub fn resolution(&self) -> f64 {
+        self.sample_rate / self.fft_size as f64
+    }
+
+    /// Time range covered by the columns, from the left edge of the first one to the right edge of
+    /// the last one.
+    pub fn time_range(&self) -> (f64, f64) {
+        let first = self.start - self.column_time / 2.0;
+        (first, first + self.columns.len() as f64 * self.column_time)
+    }
+
+    /// Image of the spectrogram, one pixel per column and frequency, the highest at the top, with
+    /// the `dynamic_range` dB below the highest amplitude spread over the colormap.
+    pub fn image(&self, colormap: Colormap, dynamic_range: f32) -> ColorImage {
+        let height = self.fft_size / 2 + 1;
+        let mut image = ColorImage::new([self.columns.len(), height], Color32::BLACK);
+
+        let ceiling = self
+            .columns
+            .iter()
+            .flatten()
+            .copied()
+            .fold(f32::NEG_INFINITY, f32::max);
+        let floor = ceiling - dynamic_range.max(1.0);
+
+        for (x, column) in self.columns.iter().enumerate() {
+            for (k, amplitude) in column.iter().enumerate() {
+                let value = (amplitude - floor) / (ceiling - floor);
+                image[(x, height - 1 - k)] = colormap.color(value);
+            }
+        }
+
+        image
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_colormap() {
+        assert_eq!(Colormap::Grayscale.color(0.0), Color32::BLACK);
+        assert_eq!(Colormap::Grayscale.color(2.0), Color32::WHITE);
+        assert_eq!(Colormap::Grayscale.color(0.5), Color32::from_gray(128));
+        assert_eq!(
+            Colormap::Viridis.color(1.0),
+            Color32::from_rgb(253, 231, 37)
+        );
+    }
+
+    #[test]
+    fn test_spectrogram() {
+        // 100 Hz for the first second, then 300 Hz, sampled at 1 kHz
+        let mut buffer = SampleBuffer::new();
+        for i in 0..2000 {
+            let t = i as f64 * 1e-3;
+            let frequency = if t < 1.0 { 100.0 } else { 300.0 };
+            buffer.push(t, (2.0 * std::f64::consts::PI * frequency * t).sin());
+        }
+
+        let settings = SpectrogramSettings::default();
+        let spectrogram = Spectrogram::compute(&buffer, 0.0, 10.0, &settings).unwrap();
+
+        assert_eq!(spectrogram.columns.len(), (2000 - 256) / 64 + 1);
+        assert!((spectrogram.column_time - 0.064).abs() < 1e-9);
+        let (from_t, to_t) = spectrogram.time_range();
+        assert!(from_t > 0.0 && to_t < 2.0);
+
+        let peak_frequency = |column: &Vec<f32>| {
+            let (k, _) = column
+                .iter()
+                .enumerate()
+                .max_by(|(_, a), (_, b)| a.total_cmp(b))
+                .unwrap();
+            k as f64 * spectrogram.resolution()
+        };
+        let first = spectrogram.columns.first().unwrap();
+        let last = spectrogram.columns.last().unwrap();
+        assert!((peak_frequency(first) - 100.0).abs() < 2.0 * spectrogram.resolution());
+        assert!((peak_frequency(last) - 300.0).abs() < 2.0 * spectrogram.resolution());
+
+        let image = spectrogram.image(Colormap::Grayscale, 60.0);
+        assert_eq!(image.size, [spectrogram.columns.len(), 129]);
+
+        // too few samples for an FFT
+        assert!(Spectrogram::compute(&buffer, 0.0, 0.1, &settings).is_none());
+    }
+
+    #[test]
+    fn test_spectrogram_column_limit() {
+        // a 100 Hz sine over far more samples than the columns read, with a wider hop
+        let mut buffer = SampleBuffer::new();
+        for i in 0..400_000 {
+            let t = i as f64 * 1e-3;
+            buffer.push(t, (2.0 * std::f64::consts::PI * 100.0 * t).sin());
+        }
+
+        let settings = SpectrogramSettings::default();
+        let spectrogram = Spectrogram::compute(&buffer, 0.0, 1000.0, &settings).unwrap();
+
+        assert!(spectrogram.columns.len() <= MAX_COLUMNS && spectrogram.columns.len() > 1000);
+        assert!((spectrogram.column_time - 0.391).abs() < 1e-6);
+        assert!((spectrogram.sample_rate - 1000.0).abs() < 1e-6);
+
+        for column in &spectrogram.columns {
+            let (k, _) = column
+                .iter()
+                .enumerate()
+                .max_by(|(_, a), (_, b)| a.total_cmp(b))
+                .unwrap();
+            assert!((k as f64 * spectrogram.resolution() - 100.0).abs() < 8.0);
+        }
+    }
+}
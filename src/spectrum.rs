@@ -0,0 +1,448 @@
+// Amplitude spectrum of a signal over a time window, averaging the FFTs of overlapping segments
+// (Welch's method). The samples get resampled at a uniform rate first, since the ones read from
+// memory come at whatever times the debugger manages to.
+
+use std::ops::Range;
+
+use egui_plot::PlotPoint;
+
+use crate::buffer::SampleBuffer;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WindowFunction {
+    Rectangular,
+    Hann,
+    Hamming,
+    Blackman,
+    FlatTop,
+}
+
+impl WindowFunction {
+    pub const ALL: [WindowFunction; 5] = [
+        WindowFunction::Rectangular,
+        WindowFunction::Hann,
+        WindowFunction::Hamming,
+        WindowFunction::Blackman,
+        WindowFunction::FlatTop,
+    ];
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            WindowFunction::Rectangular => "Rectangular",
+            WindowFunction::Hann => "Hann",
+            WindowFunction::Hamming => "Hamming",
+            WindowFunction::Blackman => "Blackman",
+            WindowFunction::FlatTop => "Flat top",
+        }
+    }
+
+    /// Coefficients of a window of `n` samples.
+    pub fn coefficients(&self, n: usize) -> Vec<f64> {
+        // cosine sum windows, a0 - a1 cos(x) + a2 cos(2x) - ...
+        let terms: &[f64] = match self {
+            WindowFunction::Rectangular => &[1.0],
+            WindowFunction::Hann => &[0.5, 0.5],
+            WindowFunction::Hamming => &[0.54, 0.46],
+            WindowFunction::Blackman => &[0.42, 0.5, 0.08],
+            WindowFunction::FlatTop => &[
+                0.21557895,
+                0.41663158,
+                0.277263158,
+                0.083578947,
+                0.006947368,
+            ],
+        };
+
+        let period = n.saturating_sub(1).max(1) as f64;
+
+        (0..n)
+            .map(|i| {
+                let x = 2.0 * std::f64::consts::PI * i as f64 / period;
+                terms
+                    .iter()
+                    .enumerate()
+                    .map(|(k, a)| {
+                        let sign = if k % 2 == 0 { 1.0 } else { -1.0 };
+                        sign * a * (k as f64 * x).cos()
+                    })
+                    .sum()
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpectrumSettings {
+    pub signal: Option<u32>,
+    /// Length of the time window analyzed, in seconds, up to the last sample.
+    pub duration: f64,
+    /// Whether the time window is the one shown in the time plot instead.
+    pub follow_view: bool,
+    pub window: WindowFunction,
+    /// Number of samples of each FFT, a power of two.
+    pub fft_size: usize,
+    /// Number of FFTs averaged, on segments overlapping by half.
+    pub averages: usize,
+    pub decibels: bool,
+    pub log_frequency: bool,
+    /// Number of the highest peaks marked.
+    pub peaks: usize,
+    /// Whether the spectrum gets computed again at every frame.
+    pub live: bool,
+}
+
+impl Default for SpectrumSettings {
+    fn default() -> SpectrumSettings {
+        SpectrumSettings {
+            signal: None,
+            duration: 1.0,
+            follow_view: false,
+            window: WindowFunction::Hann,
+            fft_size: 1024,
+            averages: 4,
+            decibels: true,
+            log_frequency: false,
+            peaks: 3,
+            live: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Spectrum {
+    /// Rate the samples were resampled at, in Hz.
+    pub sample_rate: f64,
+    /// Amplitude at each frequency, in Hz, from DC to the Nyquist frequency.
+    pub bins: Vec<PlotPoint>,
+}
+
+impl Spectrum {
+    /// Computes the spectrum of the samples of `buffer` between `from_t` and `to_t`; `None` if
+    /// there are too few of them.
+    pub fn compute(
+        buffer: &SampleBuffer,
+        from_t: f64,
+        to_t: f64,
+        settings: &SpectrumSettings,
+    ) -> Option<Spectrum> {
+        let resampled = Resampled::new(buffer, from_t, to_t)?;
+
+        // only the last segments get averaged
+        let segment_length = settings.fft_size.next_power_of_two().min(resampled.len());
+        let hop = (segment_length / 2).max(1);
+        let count =
+            (segment_length + hop * settings.averages.saturating_sub(1)).min(resampled.len());
+        let values = resampled.values(resampled.len() - count, count);
+
+        Some(Spectrum {
+            sample_rate: resampled.rate,
+            bins: amplitude_spectrum(&values, resampled.rate, settings),
+        })
+    }
+
+    /// Frequency resolution, in Hz.
+    pub fn resolution(&self) -> f64 {
+        self.bins.get(1).map_or(0.0, |bin| bin.x)
+    }
+
+    /// The local maxima with the highest amplitude, at most `count` of them, highest first.
+    pub fn peaks(&self, count: usize) -> Vec<PlotPoint> {
+        let mut peaks = self
+            .bins
+            .windows(3)
+            .filter(|w| w[1].y > w[0].y && w[1].y >= w[2].y)
+            .map(|w| w[1])
+            .collect::<Vec<_>>();
+
+        peaks.sort_by(|a, b| b.y.total_cmp(&a.y));
+        peaks.truncate(count);
+
+        peaks
+    }
+}
+
+/// The samples of a buffer between two times, resampled at a uniform rate, their average one, by
+/// linear interpolation. Only the stretches of values asked for get interpolated, so that a long
+/// time window costs no more than the segments the FFTs read from it.
+pub struct Resampled<'a> {
+    buffer: &'a SampleBuffer,
+    indices: Range<usize>,
+    /// Time of the first value, in seconds.
+    pub start: f64,
+    /// Values per second.
+    pub rate: f64,
+}
+
+impl<'a> Resampled<'a> {
+    /// Resamples the samples of `buffer` between `from_t` and `to_t`, as many values as samples;
+    /// `None` if there are fewer than two of them.
+    pub fn new(buffer: &'a SampleBuffer, from_t: f64, to_t: f64) -> Option<Resampled<'a>> {
+        let indices = buffer.index_range(from_t, to_t);
+        if indices.len() < 2 {
+            return None;
+        }
+
+        let (first, last) = (buffer.get(indices.start), buffer.get(indices.end - 1));
+        let duration = last.x - first.x;
+        if duration <= 0.0 {
+            return None;
+        }
+
+        Some(Resampled {
+            rate: (indices.len() - 1) as f64 / duration,
+            start: first.x,
+            buffer,
+            indices,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.indices.len()
+    }
+
+    /// The `count` values from the `from`-th one.
+    pub fn values(&self, from: usize, count: usize) -> Vec<f64> {
+        let from_t = self.start + from as f64 / self.rate;
+        let to_t = self.start + (from + count) as f64 / self.rate;
+
+        // with the samples around the stretch, to interpolate its ends
+        let around = self.buffer.index_range(from_t, to_t);
+        let first = around.start.saturating_sub(1).max(self.indices.start);
+        let last = (around.end + 1).min(self.indices.end);
+        let samples = (first..last)
+            .map(|i| self.buffer.get(i))
+            .collect::<Vec<_>>();
+
+        let mut j = 0;
+        (0..count)
+            .map(|i| {
+                let t = from_t + i as f64 / self.rate;
+
+                while j + 2 < samples.len() && samples[j + 1].x <= t {
+                    j += 1;
+                }
+
+                let (a, b) = (samples[j], samples[(j + 1).min(samples.len() - 1)]);
+                let alpha = ((t - a.x) / (b.x - a.x)).clamp(0.0, 1.0);
+
+                match alpha.is_nan() {
+                    true => a.y,
+                    false => a.y * (1.0 - alpha) + b.y * alpha,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Single sided amplitude spectrum of `values`, averaging the power of the last `averages`
+/// segments of `fft_size` samples, overlapping by half; fewer samples get zero padded.
+fn amplitude_spectrum(
+    values: &[f64],
+    sample_rate: f64,
+    settings: &SpectrumSettings,
+) -> Vec<PlotPoint> {
+    let fft_size = settings.fft_size.next_power_of_two();
+    let segment_length = fft_size.min(values.len());
+    let hop = (segment_length / 2).max(1);
+
+    let window = settings.window.coefficients(segment_length);
+    let window_sum: f64 = window.iter().sum();
+
+    let mut power = vec![0.0; fft_size / 2 + 1];
+    let mut count = 0;
+
+    let mut end = values.len();
+    while count < settings.averages.max(1) && end >= segment_length {
+        let segment = power_spectrum(&values[end - segment_length..end], &window, fft_size);
+        for (p, segment_p) in power.iter_mut().zip(segment) {
+            *p += segment_p;
+        }
+
+        count += 1;
+        end -= hop;
+    }
+
+    power
+        .iter()
+        .enumerate()
+        .map(|(k, p)| {
+            let amplitude = amplitude(k, p / count as f64, fft_size, window_sum);
+
+            let amplitude = match settings.decibels {
+                true => 20.0 * amplitude.max(1e-12).log10(),
+                false => amplitude,
+            };
+
+            PlotPoint::new(k as f64 * sample_rate / fft_size as f64, amplitude)
+        })
+        .collect()
+}
+
+/// Power of the frequencies from DC to Nyquist of `segment`, multiplied by `window`, zero padded
+/// to `fft_size` samples.
+pub fn power_spectrum(segment: &[f64], window: &[f64], fft_size: usize) -> Vec<f64> {
+    let mut re = vec![0.0; fft_size];
+    let mut im = vec![0.0; fft_size];
+    for (i, (value, w)) in segment.iter().zip(window).enumerate() {
+        re[i] = value * w;
+    }
+
+    fft(&mut re, &mut im);
+
+    (0..=fft_size / 2)
+        .map(|k| re[k] * re[k] + im[k] * im[k])
+        .collect()
+}
+
+/// Amplitude of the sine at the frequency bin `k`, from its `power` and the sum of the window.
+pub fn amplitude(k: usize, power: f64, fft_size: usize, window_sum: f64) -> f64 {
+    // both the positive and the negative frequencies, but DC and Nyquist
+    let sides = if k == 0 || k == fft_size / 2 {
+        1.0
+    } else {
+        2.0
+    };
+
+    sides * power.sqrt() / window_sum
+}
+
+/// In place radix-2 FFT of the complex values `re + j im`, whose length is a power of two.
+fn fft(re: &mut [f64], im: &mut [f64]) {
+    let n = re.len();
+    debug_assert!(n.is_power_of_two() && im.len() == n);
+
+    // bit reversal permutation
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+
+    let mut length = 2;
+    while length <= n {
+        let angle = -2.0 * std::f64::consts::PI / length as f64;
+        let (w_im, w_re) = angle.sin_cos();
+
+        for start in (0..n).step_by(length) {
+            let (mut u_re, mut u_im) = (1.0, 0.0);
+
+            for k in 0..length / 2 {
+                let (a, b) = (start + k, start + k + length / 2);
+
+                let t_re = re[b] * u_re - im[b] * u_im;
+                let t_im = re[b] * u_im + im[b] * u_re;
+
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+
+                (u_re, u_im) = (u_re * w_re - u_im * w_im, u_re * w_im + u_im * w_re);
+            }
+        }
+
+        length <<= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fft() {
+        let n = 16;
+        let mut re = (0..n)
+            .map(|i| (2.0 * std::f64::consts::PI * 3.0 * i as f64 / n as f64).cos())
+            .collect::<Vec<_>>();
+        let mut im = vec![0.0; n];
+
+        fft(&mut re, &mut im);
+
+        for k in 0..n {
+            let magnitude = (re[k] * re[k] + im[k] * im[k]).sqrt();
+            let expected = if k == 3 || k == n - 3 {
+                n as f64 / 2.0
+            } else {
+                0.0
+            };
+            assert!(
+                (magnitude - expected).abs() < 1e-9,
+                "bin {}: {}",
+                k,
+                magnitude
+            );
+        }
+    }
+
+    #[test]
+    fn test_window_coefficients() {
+        let hann = WindowFunction::Hann.coefficients(9);
+        assert!(hann[0].abs() < 1e-12 && hann[8].abs() < 1e-12);
+        assert!((hann[4] - 1.0).abs() < 1e-12);
+
+        assert!(WindowFunction::Rectangular
+            .coefficients(4)
+            .iter()
+            .all(|&w| w == 1.0));
+    }
+
+    #[test]
+    fn test_resample() {
+        let mut buffer = SampleBuffer::new();
+        buffer.push(0.0, 0.0);
+        buffer.push(0.5, 5.0);
+        buffer.push(2.0, 20.0);
+
+        let resampled = Resampled::new(&buffer, 0.0, 10.0).unwrap();
+        assert_eq!(resampled.rate, 1.0);
+        assert_eq!(resampled.len(), 3);
+
+        let values = resampled.values(0, 3);
+        assert_eq!(values.len(), 3);
+        assert!((values[1] - 10.0).abs() < 1e-12);
+        assert!((values[2] - 20.0).abs() < 1e-12);
+        assert_eq!(resampled.values(1, 2), values[1..]);
+
+        assert!(Resampled::new(&buffer, 0.0, 0.1).is_none());
+    }
+
+    #[test]
+    fn test_spectrum() {
+        // a 50 Hz sine of amplitude 2, sampled at 1 kHz with some jitter, over an offset of 1
+        let mut buffer = SampleBuffer::new();
+        for i in 0..4096 {
+            let t = i as f64 * 1e-3 + if i % 2 == 0 { 1e-5 } else { 0.0 };
+            buffer.push(t, 1.0 + 2.0 * (2.0 * std::f64::consts::PI * 50.0 * t).sin());
+        }
+
+        let settings = SpectrumSettings {
+            window: WindowFunction::FlatTop,
+            decibels: false,
+            ..SpectrumSettings::default()
+        };
+        let spectrum = Spectrum::compute(&buffer, 0.0, 10.0, &settings).unwrap();
+
+        assert!((spectrum.sample_rate - 1000.0).abs() < 1.0);
+        assert_eq!(spectrum.bins.len(), settings.fft_size / 2 + 1);
+        assert!((spectrum.resolution() - spectrum.sample_rate / 1024.0).abs() < 1e-9);
+
+        let peaks = spectrum.peaks(1);
+        assert!((peaks[0].x - 50.0).abs() < 2.0 * spectrum.resolution());
+        assert!(
+            (peaks[0].y - 2.0).abs() < 0.05,
+            "peak amplitude {}",
+            peaks[0].y
+        );
+        assert!((spectrum.bins[0].y - 1.0).abs() < 0.05);
+    }
+}
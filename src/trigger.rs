@@ -0,0 +1,617 @@
+// Oscilloscope-style triggering on the sampled signals: a condition is looked for on one
+// signal, and when it's met a frame of all the signals around the trigger time is copied out
+// of the buffers, so that it stays frozen for inspection while sampling goes on.
+//
+// All the times are in seconds, in the same time base of the sample buffers.
+
+use std::collections::HashMap;
+
+use egui_plot::PlotPoint;
+
+use crate::buffer::SampleBuffer;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Slope {
+    Rising,
+    Falling,
+    Either,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Polarity {
+    Positive,
+    Negative,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WindowEvent {
+    Enter,
+    Exit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TriggerCondition {
+    /// The signal crosses `level` with the given slope, after having been at least
+    /// `hysteresis` away from it on the other side; this rejects the noise around the level.
+    Edge {
+        slope: Slope,
+        level: f64,
+        hysteresis: f64,
+    },
+    /// A pulse beyond `level` (above it for positive pulses, below it for negative ones) ends,
+    /// having lasted between `min_width` and `max_width` seconds.
+    PulseWidth {
+        polarity: Polarity,
+        level: f64,
+        hysteresis: f64,
+        min_width: f64,
+        max_width: f64,
+    },
+    /// The signal enters or exits the `[low, high]` window.
+    Window {
+        event: WindowEvent,
+        low: f64,
+        high: f64,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriggerMode {
+    /// Capture a single frame, then wait to be armed again.
+    Single,
+    /// Capture a frame each time the condition is met.
+    Normal,
+    /// Like [`TriggerMode::Normal`], but capture a free-running frame when the condition isn't
+    /// met for a whole frame length.
+    Auto,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TriggerSettings {
+    pub signal_id: u32,
+    pub condition: TriggerCondition,
+    pub mode: TriggerMode,
+    /// Length of the frame before the trigger time.
+    pub pre_trigger: f64,
+    /// Length of the frame after the trigger time.
+    pub post_trigger: f64,
+    /// In single mode, arm again automatically this many seconds after each capture.
+    pub rearm_delay: Option<f64>,
+}
+
+impl Default for TriggerSettings {
+    fn default() -> Self {
+        TriggerSettings {
+            signal_id: 0,
+            condition: TriggerCondition::Edge {
+                slope: Slope::Rising,
+                level: 0.0,
+                hysteresis: 0.1,
+            },
+            mode: TriggerMode::Normal,
+            pre_trigger: 0.1,
+            post_trigger: 0.4,
+            rearm_delay: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TriggerState {
+    Disarmed,
+    /// Looking for the condition since the given time.
+    Armed {
+        since: f64,
+    },
+    /// The condition was met at the given time, waiting for the post-trigger samples.
+    Triggered {
+        at: f64,
+    },
+    /// A frame was captured in single mode; it will be armed again at the given time, if any.
+    Captured {
+        rearm_at: Option<f64>,
+    },
+}
+
+/// Frame of all the signals around a trigger, copied out of the sample buffers.
+#[derive(Debug, Clone)]
+pub struct CapturedFrame {
+    /// Time of the trigger; `None` for the free-running frames of the auto mode.
+    pub trigger_time: Option<f64>,
+    /// Time shown as zero when plotting the frame: the trigger time, if any.
+    pub reference_time: f64,
+    pub signals: HashMap<u32, Vec<PlotPoint>>,
+}
+
+/// State kept while looking for the trigger condition on the samples of a signal.
+#[derive(Debug, Clone, Default)]
+struct ConditionDetector {
+    previous: Option<PlotPoint>,
+    armed_rising: bool,
+    armed_falling: bool,
+    pulse_start: Option<f64>,
+    /// Time the pulse last fell below the level, if it hasn't risen above it since.
+    pulse_end: Option<f64>,
+}
+
+impl ConditionDetector {
+    /// Feed the next sample, returning the time at which the condition was met, if it was.
+    fn feed(&mut self, condition: &TriggerCondition, t: f64, y: f64) -> Option<f64> {
+        let previous = self.previous.replace(PlotPoint::new(t, y));
+
+        match *condition {
+            TriggerCondition::Edge {
+                slope,
+                level,
+                hysteresis,
+            } => {
+                if y < level - hysteresis {
+                    self.armed_rising = true;
+                }
+                if y > level + hysteresis {
+                    self.armed_falling = true;
+                }
+
+                let rising = matches!(slope, Slope::Rising | Slope::Either);
+                let falling = matches!(slope, Slope::Falling | Slope::Either);
+
+                if rising && self.armed_rising && y >= level {
+                    self.armed_rising = false;
+                    return Some(crossing_time(previous, t, y, level));
+                }
+                if falling && self.armed_falling && y <= level {
+                    self.armed_falling = false;
+                    return Some(crossing_time(previous, t, y, level));
+                }
+
+                None
+            }
+            TriggerCondition::PulseWidth {
+                polarity,
+                level,
+                hysteresis,
+                min_width,
+                max_width,
+            } => {
+                // negative pulses are positive pulses of the inverted signal
+                let (y, level, previous) = match polarity {
+                    Polarity::Positive => (y, level, previous),
+                    Polarity::Negative => (-y, -level, previous.map(|p| PlotPoint::new(p.x, -p.y))),
+                };
+
+                match self.pulse_start {
+                    None => {
+                        if y < level - hysteresis {
+                            self.armed_rising = true;
+                        } else if self.armed_rising && y >= level {
+                            self.armed_rising = false;
+                            self.pulse_start = Some(crossing_time(previous, t, y, level));
+                        }
+
+                        None
+                    }
+                    // both edges are timed at the level, the hysteresis only confirms the end
+                    Some(start) => {
+                        if y >= level {
+                            self.pulse_end = None;
+                        } else if self.pulse_end.is_none() {
+                            self.pulse_end = Some(crossing_time(previous, t, y, level));
+                        }
+
+                        if y >= level - hysteresis {
+                            return None;
+                        }
+
+                        self.pulse_start = None;
+                        self.armed_rising = true;
+
+                        let end = self.pulse_end.take().unwrap_or(t);
+                        let width = end - start;
+
+                        (min_width <= width && width <= max_width).then_some(end)
+                    }
+                }
+            }
+            TriggerCondition::Window { event, low, high } => {
+                let inside = |y: f64| low <= y && y <= high;
+
+                let previous = previous?;
+                match event {
+                    WindowEvent::Enter if !inside(previous.y) && inside(y) => Some(t),
+                    WindowEvent::Exit if inside(previous.y) && !inside(y) => Some(t),
+                    _ => None,
+                }
+            }
+        }
+    }
+}
+
+/// Time at which the signal crossed `level`, linearly interpolated between the previous sample
+/// and the current one at `t`.
+fn crossing_time(previous: Option<PlotPoint>, t: f64, y: f64, level: f64) -> f64 {
+    match previous {
+        Some(p) if p.y != y && p.x < t => {
+            let alpha = ((level - p.y) / (y - p.y)).clamp(0.0, 1.0);
+            p.x + alpha * (t - p.x)
+        }
+        _ => t,
+    }
+}
+
+pub struct Trigger {
+    pub settings: TriggerSettings,
+    state: TriggerState,
+    detector: ConditionDetector,
+    frame: Option<CapturedFrame>,
+}
+
+impl Trigger {
+    pub fn new(settings: TriggerSettings) -> Trigger {
+        Trigger {
+            settings,
+            state: TriggerState::Disarmed,
+            detector: ConditionDetector::default(),
+            frame: None,
+        }
+    }
+
+    pub fn state(&self) -> TriggerState {
+        self.state
+    }
+
+    /// Last captured frame, if any.
+    pub fn frame(&self) -> Option<&CapturedFrame> {
+        self.frame.as_ref()
+    }
+
+    /// Start looking for the condition, with data received up to `now`.
+    pub fn arm(&mut self, now: f64) {
+        self.state = TriggerState::Armed { since: now };
+        self.detector = ConditionDetector::default();
+    }
+
+    pub fn disarm(&mut self) {
+        self.state = TriggerState::Disarmed;
+    }
+
+    /// Disarm and drop the captured frame.
+    pub fn reset(&mut self) {
+        self.disarm();
+        self.frame = None;
+    }
+
+    /// Process a new sample of signal `id`; only the samples of the trigger signal matter.
+    pub fn process_sample(&mut self, id: u32, t: f64, y: f64) {
+        if id != self.settings.signal_id {
+            return;
+        }
+
+        if let TriggerState::Armed { .. } = self.state {
+            if let Some(at) = self.detector.feed(&self.settings.condition, t, y) {
+                log::debug!("trigger condition met at {}", at);
+                self.state = TriggerState::Triggered { at };
+            }
+        }
+    }
+
+    /// Advance the state now that all the samples up to `now` have been received, capturing
+    /// a frame from `buffers` when it's complete; returns whether a frame was captured.
+    pub fn update(&mut self, now: f64, buffers: &HashMap<u32, SampleBuffer>) -> bool {
+        let frame_length = self.settings.pre_trigger + self.settings.post_trigger;
+
+        match self.state {
+            TriggerState::Triggered { at } if now >= at + self.settings.post_trigger => {
+                self.capture(Some(at), at, buffers);
+
+                match self.settings.mode {
+                    TriggerMode::Single => {
+                        self.state = TriggerState::Captured {
+                            rearm_at: self.settings.rearm_delay.map(|delay| now + delay),
+                        };
+                    }
+                    TriggerMode::Normal | TriggerMode::Auto => self.arm(now),
+                }
+
+                true
+            }
+            TriggerState::Armed { since }
+                if self.settings.mode == TriggerMode::Auto && now - since >= frame_length =>
+            {
+                self.capture(None, now - self.settings.post_trigger, buffers);
+                self.arm(now);
+
+                true
+            }
+            TriggerState::Captured {
+                rearm_at: Some(rearm_at),
+            } if now >= rearm_at => {
+                self.arm(now);
+
+                false
+            }
+            _ => false,
+        }
+    }
+
+    fn capture(
+        &mut self,
+        trigger_time: Option<f64>,
+        reference_time: f64,
+        buffers: &HashMap<u32, SampleBuffer>,
+    ) {
+        let from_t = reference_time - self.settings.pre_trigger;
+        let to_t = reference_time + self.settings.post_trigger;
+
+        let signals = buffers
+            .iter()
+            .map(|(&id, buffer)| {
+                let points = buffer.range(from_t, to_t).collect();
+
+                (id, points)
+            })
+            .collect();
+
+        self.frame = Some(CapturedFrame {
+            trigger_time,
+            reference_time,
+            signals,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feed_all(condition: TriggerCondition, samples: &[(f64, f64)]) -> Vec<f64> {
+        let mut detector = ConditionDetector::default();
+
+        samples
+            .iter()
+            .filter_map(|&(t, y)| detector.feed(&condition, t, y))
+            .collect()
+    }
+
+    #[test]
+    fn test_edge_with_hysteresis() {
+        let rising = TriggerCondition::Edge {
+            slope: Slope::Rising,
+            level: 1.0,
+            hysteresis: 0.5,
+        };
+
+        // the noise around the level triggers once, until the signal goes back below 0.5
+        let samples = [
+            (0.0, 0.0),
+            (1.0, 2.0),
+            (2.0, 0.9),
+            (3.0, 1.1),
+            (4.0, 0.0),
+            (5.0, 1.0),
+        ];
+        assert_eq!(feed_all(rising, &samples), [0.5, 5.0]);
+
+        let falling = TriggerCondition::Edge {
+            slope: Slope::Falling,
+            level: 1.0,
+            hysteresis: 0.5,
+        };
+        // the signal never goes above 1.5 again after the first falling edge
+        assert_eq!(feed_all(falling, &samples), [1.0 + 1.0 / 1.1]);
+
+        let either = TriggerCondition::Edge {
+            slope: Slope::Either,
+            level: 1.0,
+            hysteresis: 0.0,
+        };
+        assert_eq!(
+            feed_all(either, &[(0.0, 0.0), (1.0, 2.0), (2.0, 0.0)]),
+            [0.5, 1.5]
+        );
+    }
+
+    #[test]
+    fn test_pulse_width() {
+        let condition = TriggerCondition::PulseWidth {
+            polarity: Polarity::Positive,
+            level: 1.0,
+            hysteresis: 0.0,
+            min_width: 2.0,
+            max_width: 3.0,
+        };
+
+        let mut samples = Vec::new();
+        // pulses 1, 2.5 and 5 seconds wide, sampled every 0.5 seconds
+        for (start, width) in [(1.0, 1.0), (5.0, 2.5), (10.0, 5.0)] {
+            samples.push((start - 0.5, 0.0));
+            samples.push((start, 2.0));
+            samples.push((start + width - 0.5, 2.0));
+            samples.push((start + width, 0.0));
+        }
+
+        let triggers = feed_all(condition, &samples);
+        assert_eq!(triggers.len(), 1);
+        assert!((triggers[0] - 7.25).abs() < 1e-9);
+
+        let negative = TriggerCondition::PulseWidth {
+            polarity: Polarity::Negative,
+            level: 1.0,
+            hysteresis: 0.0,
+            min_width: 0.0,
+            max_width: 1.0,
+        };
+        let inverted: Vec<_> = samples.iter().map(|&(t, y)| (t, 2.0 - y)).collect();
+        assert_eq!(feed_all(negative, &inverted).len(), 1);
+    }
+
+    #[test]
+    fn test_pulse_width_with_hysteresis() {
+        let condition = TriggerCondition::PulseWidth {
+            polarity: Polarity::Positive,
+            level: 1.0,
+            hysteresis: 0.5,
+            min_width: 1.9,
+            max_width: 2.1,
+        };
+
+        // a trapezoid pulse with slow edges, crossing the level at 1 and 3 seconds, and the
+        // level minus the hysteresis at 0.75 and 3.25 seconds
+        let samples = [
+            (0.0, -1.0),
+            (0.5, 0.0),
+            (1.5, 2.0),
+            (2.5, 2.0),
+            (3.0, 1.0),
+            (3.2, 0.6),
+            (3.5, 0.0),
+        ];
+        assert_eq!(feed_all(condition, &samples), [3.0]);
+
+        // noise around the level on the falling edge doesn't end the pulse early
+        let samples = [(0.0, 0.0), (1.0, 2.0), (2.0, 0.8), (2.5, 1.2), (3.0, 0.0)];
+        let triggers = feed_all(condition, &samples);
+        assert_eq!(triggers.len(), 1);
+        assert!(
+            (triggers[0] - (2.5 + 0.5 / 6.0)).abs() < 1e-9,
+            "{triggers:?}"
+        );
+    }
+
+    #[test]
+    fn test_window() {
+        let samples = [(0.0, -5.0), (1.0, 0.0), (2.0, 0.5), (3.0, 5.0), (4.0, 0.0)];
+
+        let enter = TriggerCondition::Window {
+            event: WindowEvent::Enter,
+            low: -1.0,
+            high: 1.0,
+        };
+        assert_eq!(feed_all(enter, &samples), [1.0, 4.0]);
+
+        let exit = TriggerCondition::Window {
+            event: WindowEvent::Exit,
+            low: -1.0,
+            high: 1.0,
+        };
+        assert_eq!(feed_all(exit, &samples), [3.0]);
+    }
+
+    /// Square wave with a period of 1 s, sampled at 100 Hz, on signal 0, and its inverse on
+    /// signal 1.
+    fn run(
+        trigger: &mut Trigger,
+        buffers: &mut HashMap<u32, SampleBuffer>,
+        from: usize,
+        to: usize,
+    ) -> usize {
+        let mut captures = 0;
+
+        for i in from..to {
+            let t = i as f64 * 0.01;
+            let y = if (i / 50) % 2 == 0 { 0.0 } else { 1.0 };
+
+            for (id, y) in [(0, y), (1, 1.0 - y)] {
+                buffers
+                    .entry(id)
+                    .or_insert_with(SampleBuffer::new)
+                    .push(t, y);
+                trigger.process_sample(id, t, y);
+            }
+
+            if trigger.update(t, buffers) {
+                captures += 1;
+            }
+        }
+
+        captures
+    }
+
+    fn settings(mode: TriggerMode) -> TriggerSettings {
+        TriggerSettings {
+            signal_id: 0,
+            condition: TriggerCondition::Edge {
+                slope: Slope::Rising,
+                level: 0.5,
+                hysteresis: 0.1,
+            },
+            mode,
+            pre_trigger: 0.2,
+            post_trigger: 0.3,
+            rearm_delay: None,
+        }
+    }
+
+    #[test]
+    fn test_single_mode() {
+        let mut buffers = HashMap::new();
+        let mut trigger = Trigger::new(settings(TriggerMode::Single));
+
+        // nothing happens until armed
+        assert_eq!(run(&mut trigger, &mut buffers, 0, 100), 0);
+        assert!(trigger.frame().is_none());
+
+        trigger.arm(1.0);
+        assert_eq!(run(&mut trigger, &mut buffers, 100, 500), 1);
+        assert_eq!(trigger.state(), TriggerState::Captured { rearm_at: None });
+
+        // the rising edge at 1.5 s, between the samples at 1.49 s and 1.5 s
+        let frame = trigger.frame().unwrap();
+        let trigger_time = frame.trigger_time.unwrap();
+        assert!((trigger_time - 1.495).abs() < 1e-9);
+
+        for (id, points) in &frame.signals {
+            assert!(points.first().unwrap().x >= trigger_time - 0.2);
+            assert!(points.last().unwrap().x <= trigger_time + 0.3);
+            assert_eq!(points.len(), 50, "signal {}", id);
+        }
+        assert!(frame.signals[&0]
+            .iter()
+            .all(|p| (p.x < trigger_time) == (p.y == 0.0)));
+        assert!(frame.signals[&1]
+            .iter()
+            .all(|p| (p.x < trigger_time) == (p.y == 1.0)));
+    }
+
+    #[test]
+    fn test_single_mode_rearm_loop() {
+        let mut buffers = HashMap::new();
+        let mut settings = settings(TriggerMode::Single);
+        settings.rearm_delay = Some(1.0);
+        let mut trigger = Trigger::new(settings);
+
+        // rising edges every second, but after each capture at 0.3 s past the edge the trigger
+        // stays frozen for one second, missing the next edge
+        trigger.arm(0.0);
+        assert_eq!(run(&mut trigger, &mut buffers, 0, 1000), 5);
+    }
+
+    #[test]
+    fn test_normal_and_auto_modes() {
+        let mut buffers = HashMap::new();
+        let mut trigger = Trigger::new(settings(TriggerMode::Normal));
+        trigger.arm(0.0);
+        assert_eq!(run(&mut trigger, &mut buffers, 0, 1000), 10);
+
+        // a condition never met captures nothing in normal mode, a frame each half second in
+        // auto mode
+        let mut never_met = settings(TriggerMode::Normal);
+        never_met.condition = TriggerCondition::Edge {
+            slope: Slope::Rising,
+            level: 10.0,
+            hysteresis: 0.1,
+        };
+
+        let mut trigger = Trigger::new(never_met);
+        trigger.arm(0.0);
+        assert_eq!(run(&mut trigger, &mut buffers, 0, 1000), 0);
+
+        never_met.mode = TriggerMode::Auto;
+        let mut trigger = Trigger::new(never_met);
+        trigger.arm(0.0);
+        assert_eq!(run(&mut trigger, &mut buffers, 0, 1000), 19);
+
+        let frame = trigger.frame().unwrap();
+        assert_eq!(frame.trigger_time, None);
+        assert!((frame.reference_time - (9.5 - 0.3)).abs() < 1e-9);
+    }
+}
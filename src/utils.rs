@@ -33,12 +33,42 @@ pub fn color_for_id(id: u32) -> ecolor::Color32 {
     ecolor::Hsva::new(h, 0.85, 0.5, 1.0).into()
 }
 
+/// Splits an enum variant formatted with `{:?}`, like `CpuCycles(72.0)`, into its name and its
+/// number, if any.
+pub fn parse_variant(value: &str) -> (&str, Option<f64>) {
+    match value
+        .strip_suffix(')')
+        .and_then(|value| value.split_once('('))
+    {
+        Some((name, number)) => (name, number.parse().ok()),
+        None => (value, None),
+    }
+}
+
+/// Path of a file in the temporary directory for the tests, unique to the process and `name`.
+#[cfg(test)]
+pub fn temporary_path(name: &str, extension: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!(
+        "ocdscope-{}-{}.{}",
+        name,
+        std::process::id(),
+        extension
+    ))
+}
+
 #[cfg(test)]
 mod tests {
     use std::net::{Ipv4Addr, SocketAddrV4, TcpListener};
 
     use super::*;
 
+    #[test]
+    fn test_parse_variant() {
+        assert_eq!(parse_variant("HostTime"), ("HostTime", None));
+        assert_eq!(parse_variant("CpuCycles(72.5)"), ("CpuCycles", Some(72.5)));
+        assert_eq!(parse_variant("CpuCycles(fast)"), ("CpuCycles", None));
+    }
+
     #[test]
     fn can_find_a_free_tcp_port() {
         if let Err(err) = find_free_tcp_port() {
@@ -0,0 +1,168 @@
+// X-Y plot of a signal against another one, e.g. the two currents of a Clarke transform or the
+// phase plane of a position and its velocity: the samples of the two signals are paired by time,
+// interpolating each one at the sample times of the other.
+
+use crate::buffer::SampleBuffer;
+
+#[derive(Debug, Clone)]
+pub struct XYPlotSettings {
+    pub x_signal: Option<u32>,
+    pub y_signal: Option<u32>,
+    /// Length of the time window plotted, in seconds, up to the last sample.
+    pub window: f64,
+    /// Whether the time window is the one shown in the time plot instead.
+    pub follow_view: bool,
+    /// Whether the older points fade out, the most recent ones being opaque.
+    pub fade: bool,
+    pub equal_axes: bool,
+}
+
+impl Default for XYPlotSettings {
+    fn default() -> XYPlotSettings {
+        XYPlotSettings {
+            x_signal: None,
+            y_signal: None,
+            window: 1.0,
+            follow_view: false,
+            fade: true,
+            equal_axes: false,
+        }
+    }
+}
+
+/// Pairs the values of `x` and `y` between `from_t` and `to_t` by time, at the sample times of
+/// both where they overlap, keeping at most about `max_points` of them evenly.
+pub fn pair_by_time(
+    x: &SampleBuffer,
+    y: &SampleBuffer,
+    from_t: f64,
+    to_t: f64,
+    max_points: usize,
+) -> Vec<[f64; 2]> {
+    let (Some(x_bounds), Some(y_bounds)) = (x.time_bounds(), y.time_bounds()) else {
+        return Vec::new();
+    };
+
+    // out of the overlap, one of the two values would only be extrapolated
+    let from_t = from_t.max(x_bounds.0).max(y_bounds.0);
+    let to_t = to_t.min(x_bounds.1).min(y_bounds.1);
+    if from_t > to_t {
+        return Vec::new();
+    }
+
+    // the same stride on both signals, only the samples kept are visited
+    let (x_range, y_range) = (x.index_range(from_t, to_t), y.index_range(from_t, to_t));
+    let step = (x_range.len() + y_range.len())
+        .div_ceil(max_points.max(1))
+        .max(1);
+
+    let mut x_times = x_range.step_by(step).map(|i| x.get(i).x).peekable();
+    let mut y_times = y_range.step_by(step).map(|i| y.get(i).x).peekable();
+    let mut points = Vec::with_capacity(max_points + 1);
+
+    // merge the two sorted sequences of times, once for the ones of both
+    loop {
+        let t = match (x_times.peek(), y_times.peek()) {
+            (Some(&tx), Some(&ty)) if tx < ty => x_times.next(),
+            (Some(&tx), Some(&ty)) if ty < tx => y_times.next(),
+            (Some(_), Some(_)) => {
+                y_times.next();
+                x_times.next()
+            }
+            (Some(_), None) => x_times.next(),
+            (None, _) => y_times.next(),
+        };
+        let Some(t) = t else {
+            break;
+        };
+
+        points.push([x.value_at(t), y.value_at(t)]);
+    }
+
+    points
+}
+
+/// Splits `points` in at most `steps` consecutive segments, sharing their ends, with the opacity
+/// they are drawn with, from the oldest and most transparent to the last and opaque one.
+pub fn fade_segments(points: &[[f64; 2]], steps: usize) -> Vec<(f32, Vec<[f64; 2]>)> {
+    if points.len() < 2 {
+        return vec![(1.0, points.to_vec())];
+    }
+
+    let segment_length = (points.len() - 1).div_ceil(steps.max(1));
+    let count = (points.len() - 1).div_ceil(segment_length);
+
+    (0..points.len() - 1)
+        .step_by(segment_length)
+        .enumerate()
+        .map(|(k, start)| {
+            let end = (start + segment_length + 1).min(points.len());
+            ((k + 1) as f32 / count as f32, points[start..end].to_vec())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pair_by_time() {
+        let mut x = SampleBuffer::new();
+        for i in 0..4 {
+            x.push(i as f64, i as f64);
+        }
+
+        let mut y = SampleBuffer::new();
+        for i in 0..3 {
+            y.push(i as f64 + 0.5, 10.0 * (i + 1) as f64);
+        }
+
+        // only where the two overlap, at the times of both
+        let pairs = pair_by_time(&x, &y, 0.0, 10.0, 100);
+        assert_eq!(
+            pairs,
+            vec![
+                [0.5, 10.0],
+                [1.0, 15.0],
+                [1.5, 20.0],
+                [2.0, 25.0],
+                [2.5, 30.0]
+            ]
+        );
+
+        assert_eq!(
+            pair_by_time(&x, &y, 1.0, 2.0, 2),
+            vec![[1.0, 15.0], [1.5, 20.0]]
+        );
+        assert!(pair_by_time(&x, &y, 5.0, 10.0, 100).is_empty());
+        assert!(pair_by_time(&x, &SampleBuffer::new(), 0.0, 10.0, 100).is_empty());
+
+        // long signals are strided down to about the number of points asked for
+        let (mut x, mut y) = (SampleBuffer::new(), SampleBuffer::new());
+        for i in 0..10_000 {
+            x.push(i as f64, i as f64);
+            y.push(i as f64 + 0.5, i as f64);
+        }
+        let pairs = pair_by_time(&x, &y, 0.0, 10_000.0, 100);
+        assert_eq!(pairs.len(), 100);
+        assert!(pairs.windows(2).all(|w| w[0][0] < w[1][0]));
+    }
+
+    #[test]
+    fn test_fade_segments() {
+        let points = (0..10).map(|i| [i as f64, 0.0]).collect::<Vec<_>>();
+
+        let segments = fade_segments(&points, 3);
+        assert_eq!(segments.len(), 3);
+        assert_eq!(segments[0].1.first(), Some(&[0.0, 0.0]));
+        assert_eq!(segments[2].1.last(), Some(&[9.0, 0.0]));
+        assert_eq!(segments[2].0, 1.0);
+        assert!(segments.windows(2).all(|w| w[0].0 < w[1].0));
+
+        // consecutive segments are joined
+        assert!(segments.windows(2).all(|w| w[0].1.last() == w[1].1.first()));
+
+        assert_eq!(fade_segments(&points[..1], 3).len(), 1);
+    }
+}